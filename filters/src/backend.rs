@@ -0,0 +1,413 @@
+use crate::{kernel, validate_image, FilterError, Image, Luma, Weight};
+use ndarray::prelude::*;
+use rayon::prelude::*;
+
+/// Primitive operations that the filters are built from
+///
+/// A backend implements convolution and pointwise operations, the filters themselves are
+/// provided methods, so each backend runs the same algorithm. This allows backends to be
+/// swapped and their results to be compared.
+pub trait Backend {
+    /// Convolve `buf_read` with a kernel of odd dimensions into `buf_write`
+    fn convolve<T>(&self, img: &mut Image<T>, kernel: &Array2<f32>) -> Result<(), FilterError>
+    where
+        T: Sync + Send + Copy + Into<f32>,
+        Weight: Into<T>;
+
+    /// Apply a function to each pixel of a buffer
+    fn map_pixels<T, F>(&self, buf: &mut [T], channels: usize, f: F)
+    where
+        T: Sync + Send,
+        F: Fn(&mut [T]) + Sync + Send;
+
+    /// Combine each pixel of a buffer with the pixel at the same position in another buffer
+    fn zip_pixels<T, F>(&self, buf: &mut [T], other: &[T], channels: usize, f: F)
+    where
+        T: Sync + Send,
+        F: Fn(&mut [T], &[T]) + Sync + Send;
+
+    /// Convolve along the x-axis and then along the y-axis
+    fn convolve_separable<T>(
+        &self,
+        img: &mut Image<T>,
+        kernel_x: &Array2<f32>,
+        kernel_y: &Array2<f32>,
+    ) -> Result<(), FilterError>
+    where
+        T: Sync + Send + Copy + Into<f32>,
+        Weight: Into<T>,
+    {
+        self.convolve(img, kernel_x)?;
+
+        // Use the previous buffer as source for the second pass
+        img.buf_read.copy_from_slice(img.buf_write);
+
+        self.convolve(img, kernel_y)
+    }
+
+    fn box_blur_1d<T>(&self, img: &mut Image<T>, radius: usize) -> Result<(), FilterError>
+    where
+        T: Sync + Send + Copy + Into<f32>,
+        Weight: Into<T>,
+    {
+        let (kernel_x, kernel_y) = kernel::box_blur_kernel_1d(radius);
+
+        self.convolve_separable(img, &kernel_x, &kernel_y)
+    }
+
+    fn box_blur_2d<T>(&self, img: &mut Image<T>, radius: usize) -> Result<(), FilterError>
+    where
+        T: Sync + Send + Copy + Into<f32>,
+        Weight: Into<T>,
+    {
+        self.convolve(img, &kernel::box_blur_kernel_2d(radius))
+    }
+
+    fn gaussian_blur_1d<T>(&self, img: &mut Image<T>, sigma: f32) -> Result<(), FilterError>
+    where
+        T: Sync + Send + Copy + Into<f32>,
+        Weight: Into<T>,
+    {
+        let (kernel_x, kernel_y) = kernel::try_gaussian_blur_kernel_1d(sigma)?;
+
+        self.convolve_separable(img, &kernel_x, &kernel_y)
+    }
+
+    fn gaussian_blur_2d<T>(&self, img: &mut Image<T>, sigma: f32) -> Result<(), FilterError>
+    where
+        T: Sync + Send + Copy + Into<f32>,
+        Weight: Into<T>,
+    {
+        self.convolve(img, &kernel::try_gaussian_blur_kernel_2d(sigma)?)
+    }
+
+    fn sobel2d<T>(
+        &self,
+        img: &mut Image<T>,
+        sigma: Option<f32>,
+        luma: Option<Luma>,
+    ) -> Result<(), FilterError>
+    where
+        T: Sync + Send + Copy + Into<f32>,
+        Weight: Into<T>,
+    {
+        validate_image(img)?;
+
+        // Luma conversion requires at least the RGB channels
+        if luma.is_some() && img.channels < 3 {
+            return Err(FilterError::UnsupportedChannels(img.channels));
+        }
+
+        // Apply Gaussian blur if -s / --sigma is passed
+        if let Some(sigma) = sigma {
+            self.gaussian_blur_1d(img, sigma)?;
+
+            // Write the result to the read buffer for the second pass
+            img.buf_read.copy_from_slice(img.buf_write);
+        }
+
+        let (kernel_x, kernel_y) = kernel::sobel_2d();
+
+        // Change color to Luma, without it the gradient of each channel is found on its own
+        // See: https://www.wikiwand.com/en/Grayscale#/Luma_coding_in_video_systems
+        if let Some(luma) = luma {
+            self.map_pixels(img.buf_read, img.channels, |p| {
+                let y = Weight(luma.luma(p[0].into(), p[1].into(), p[2].into()));
+
+                p[0] = y.into();
+                p[1] = y.into();
+                p[2] = y.into();
+            });
+        }
+
+        // Find the gradient along the x-axis
+        self.convolve(img, &kernel_x)?;
+
+        // Create an extra buffer, as one is required for each gradient
+        let mut tmp = img.buf_read.to_vec();
+
+        // Find the gradient along the y-axis
+        self.convolve(
+            &mut Image {
+                buf_read: img.buf_read,
+                buf_write: &mut tmp,
+                ..*img
+            },
+            &kernel_y,
+        )?;
+
+        // Apply Pythagorean theorem to both buffers for the gradient magnitude
+        self.zip_pixels(img.buf_write, &tmp, img.channels, |gx, gy| {
+            for (gx, gy) in gx.iter_mut().zip(gy) {
+                *gx = Weight(((*gx).into().powi(2) + (*gy).into().powi(2)).sqrt()).into();
+            }
+        });
+
+        Ok(())
+    }
+}
+
+/// Multi-threaded backend that processes rows and pixels in parallel with rayon
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Cpu;
+
+/// Single-threaded backend, for debugging and as a reference for the other backends
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Scalar;
+
+impl Backend for Cpu {
+    fn convolve<T>(&self, img: &mut Image<T>, kernel: &Array2<f32>) -> Result<(), FilterError>
+    where
+        T: Sync + Send + Copy + Into<f32>,
+        Weight: Into<T>,
+    {
+        validate_convolve(img, kernel)?;
+
+        let Image {
+            ref buf_read,
+            width,
+            height,
+            channels,
+            ..
+        } = *img;
+
+        img.buf_write
+            // Process one row of pixels for each thread
+            .par_chunks_exact_mut(width as usize * channels)
+            .enumerate()
+            .for_each(|(y, pixels)| {
+                pixels
+                    .par_chunks_exact_mut(channels)
+                    .enumerate()
+                    .for_each(|(x, pixel)| {
+                        convolve_pixel(buf_read, (width, height, channels), kernel, (x, y), pixel)
+                    });
+            });
+
+        Ok(())
+    }
+
+    fn map_pixels<T, F>(&self, buf: &mut [T], channels: usize, f: F)
+    where
+        T: Sync + Send,
+        F: Fn(&mut [T]) + Sync + Send,
+    {
+        buf.par_chunks_mut(channels).for_each(f);
+    }
+
+    fn zip_pixels<T, F>(&self, buf: &mut [T], other: &[T], channels: usize, f: F)
+    where
+        T: Sync + Send,
+        F: Fn(&mut [T], &[T]) + Sync + Send,
+    {
+        buf.par_chunks_mut(channels)
+            .zip(other.par_chunks(channels))
+            .for_each(|(a, b)| f(a, b));
+    }
+}
+
+impl Backend for Scalar {
+    fn convolve<T>(&self, img: &mut Image<T>, kernel: &Array2<f32>) -> Result<(), FilterError>
+    where
+        T: Sync + Send + Copy + Into<f32>,
+        Weight: Into<T>,
+    {
+        validate_convolve(img, kernel)?;
+
+        let Image {
+            ref buf_read,
+            width,
+            height,
+            channels,
+            ..
+        } = *img;
+
+        for (y, pixels) in img
+            .buf_write
+            .chunks_exact_mut(width as usize * channels)
+            .enumerate()
+        {
+            for (x, pixel) in pixels.chunks_exact_mut(channels).enumerate() {
+                convolve_pixel(buf_read, (width, height, channels), kernel, (x, y), pixel);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn map_pixels<T, F>(&self, buf: &mut [T], channels: usize, f: F)
+    where
+        T: Sync + Send,
+        F: Fn(&mut [T]) + Sync + Send,
+    {
+        buf.chunks_mut(channels).for_each(f);
+    }
+
+    fn zip_pixels<T, F>(&self, buf: &mut [T], other: &[T], channels: usize, f: F)
+    where
+        T: Sync + Send,
+        F: Fn(&mut [T], &[T]) + Sync + Send,
+    {
+        for (a, b) in buf.chunks_mut(channels).zip(other.chunks(channels)) {
+            f(a, b);
+        }
+    }
+}
+
+fn validate_convolve<T>(img: &Image<T>, kernel: &Array2<f32>) -> Result<(), FilterError>
+where
+    T: Sync + Send + Copy + Into<f32>,
+{
+    validate_image(img)?;
+
+    let (rows, cols) = kernel.dim();
+
+    if rows % 2 == 0 || cols % 2 == 0 {
+        return Err(FilterError::InvalidKernel { rows, cols });
+    }
+
+    Ok(())
+}
+
+/// Compute the weighted sum of the kernel around a single pixel
+fn convolve_pixel<T>(
+    buf_read: &[T],
+    (width, height, channels): (u32, u32, usize),
+    kernel: &Array2<f32>,
+    (x, y): (usize, usize),
+    pixel: &mut [T],
+) where
+    T: Copy + Into<f32>,
+    Weight: Into<T>,
+{
+    let rows_half = kernel.nrows() as isize / 2;
+    let cols_half = kernel.ncols() as isize / 2;
+
+    let mut weighted_sum = [Weight(0.0); 4];
+
+    for ((i, j), kernel_element) in kernel.indexed_iter() {
+        // Clamp kernel to image bounds
+        let edge_x = (x as isize + (j as isize - cols_half))
+            .min(width as isize - 1)
+            .max(0) as usize;
+        let edge_y = (y as isize + (i as isize - rows_half))
+            .min(height as isize - 1)
+            .max(0) as usize;
+
+        // Get pixel x- and y-coordinate
+        let p_x = edge_x * channels;
+        let p_y = edge_y * channels * width as usize;
+
+        // Get pixel channels as a slice, the bounds were validated above
+        let pixel = &buf_read[(p_x + p_y)..(p_x + p_y) + channels];
+
+        for (weight, &channel) in weighted_sum.iter_mut().zip(pixel) {
+            *weight += Weight(channel.into() * kernel_element);
+        }
+    }
+
+    for (channel, &weight) in pixel.iter_mut().zip(&weighted_sum) {
+        *channel = weight.into();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WIDTH: u32 = 13;
+    const HEIGHT: u32 = 7;
+
+    /// Filter a pattern image and return the written buffer
+    fn run<F>(channels: usize, filter: F) -> Vec<u8>
+    where
+        F: FnOnce(&mut Image<u8>) -> Result<(), FilterError>,
+    {
+        let len = WIDTH as usize * HEIGHT as usize * channels;
+        let mut buf_read = (0..len).map(|i| (i * 37 % 251) as u8).collect::<Vec<_>>();
+        let mut buf_write = vec![0; len];
+
+        filter(&mut Image {
+            buf_read: &mut buf_read,
+            buf_write: &mut buf_write,
+            width: WIDTH,
+            height: HEIGHT,
+            channels,
+        })
+        .unwrap();
+
+        buf_write
+    }
+
+    /// Assert that a filter gives the same result on the `Cpu` and `Scalar` backends
+    macro_rules! assert_backends_eq {
+        ($channels:expr, |$backend:ident, $img:ident| $filter:expr) => {{
+            let cpu = run($channels, |$img| {
+                let $backend = Cpu;
+                $filter
+            });
+            let scalar = run($channels, |$img| {
+                let $backend = Scalar;
+                $filter
+            });
+
+            assert_eq!(cpu, scalar);
+        }};
+    }
+
+    #[test]
+    fn test_backends_blur() {
+        for &channels in &[1, 3, 4] {
+            assert_backends_eq!(channels, |b, img| b.box_blur_1d(img, 2));
+            assert_backends_eq!(channels, |b, img| b.box_blur_2d(img, 3));
+            assert_backends_eq!(channels, |b, img| b.gaussian_blur_1d(img, 1.5));
+            assert_backends_eq!(channels, |b, img| b.gaussian_blur_2d(img, 0.8));
+        }
+    }
+
+    #[test]
+    fn test_backends_sobel() {
+        for &channels in &[1, 2] {
+            assert_backends_eq!(channels, |b, img| b.sobel2d(img, Some(1.0), None));
+        }
+
+        for &channels in &[3, 4] {
+            assert_backends_eq!(channels, |b, img| b.sobel2d(img, None, Some(Luma::Bt601)));
+            assert_backends_eq!(channels, |b, img| b.sobel2d(
+                img,
+                Some(1.0),
+                Some(Luma::Bt709)
+            ));
+        }
+    }
+
+    #[test]
+    fn test_backends_convolve() {
+        let kernel = array![[0.0, -1.0, 0.0], [-1.0, 5.0, -1.0], [0.0, -1.0, 0.0]];
+
+        assert_backends_eq!(4, |b, img| b.convolve(img, &kernel));
+        assert_backends_eq!(4, |b, img| b.convolve_separable(
+            img,
+            &kernel,
+            &kernel.t().to_owned()
+        ));
+    }
+
+    #[test]
+    fn test_backends_invalid_kernel() {
+        let mut buf_read = vec![0u8; 12];
+        let mut buf_write = vec![0u8; 12];
+        let mut img = Image {
+            buf_read: &mut buf_read,
+            buf_write: &mut buf_write,
+            width: 2,
+            height: 2,
+            channels: 3,
+        };
+
+        let kernel = Array2::zeros((2, 3));
+
+        assert!(Cpu.convolve(&mut img, &kernel).is_err());
+        assert!(Scalar.convolve(&mut img, &kernel).is_err());
+    }
+}
@@ -0,0 +1,344 @@
+use crate::{blur_plane, color_channels, gray, validate_image, FilterError, Image};
+use rayon::prelude::*;
+
+/// Offset of the signed difference of Gaussians in 8-bit images, so zero is mid-gray
+pub const DOG_OFFSET: f32 = 128.0;
+
+/// Ratio between the sigmas of the two blurs that approximate a Laplacian of Gaussian
+const DOG_RATIO: f32 = 1.6;
+
+pub fn difference_of_gaussians<T>(img: &Image<T>, sigma1: f32, sigma2: f32) -> Vec<f32>
+where
+    T: Sync + Send + Copy + Into<f32>,
+{
+    try_difference_of_gaussians(img, sigma1, sigma2).unwrap_or_else(|err| panic!("{}", err))
+}
+
+/// Blur `buf_read` with `sigma1` and with `sigma2` and subtract the second from the first,
+/// which keeps the details between both scales as signed values
+pub fn try_difference_of_gaussians<T>(
+    img: &Image<T>,
+    sigma1: f32,
+    sigma2: f32,
+) -> Result<Vec<f32>, FilterError>
+where
+    T: Sync + Send + Copy + Into<f32>,
+{
+    validate_image(img)?;
+
+    let channels = img.channels;
+    let values = img.buf_read.iter().map(|&v| v.into()).collect::<Vec<_>>();
+
+    // Blur each channel as a plane, as the blurs of `Cpu` write back into `T`
+    let blur = |sigma| -> Result<Vec<f32>, FilterError> {
+        let mut blurred = vec![0.0; values.len()];
+
+        for c in 0..channels {
+            let plane = values.iter().skip(c).step_by(channels).copied().collect();
+            let plane = blur_plane(plane, img.width, img.height, sigma)?;
+
+            for (value, blurred) in blurred.iter_mut().skip(c).step_by(channels).zip(plane) {
+                *value = blurred;
+            }
+        }
+
+        Ok(blurred)
+    };
+
+    let first = blur(sigma1)?;
+    let second = blur(sigma2)?;
+
+    Ok(first.iter().zip(&second).map(|(a, b)| a - b).collect())
+}
+
+pub fn dog(img: &mut Image<u8>, sigma1: f32, sigma2: f32) {
+    try_dog(img, sigma1, sigma2).unwrap_or_else(|err| panic!("{}", err))
+}
+
+/// Write the difference of Gaussians of each color channel offset by [`DOG_OFFSET`], alpha is
+/// copied unchanged
+pub fn try_dog(img: &mut Image<u8>, sigma1: f32, sigma2: f32) -> Result<(), FilterError> {
+    let difference = try_difference_of_gaussians(img, sigma1, sigma2)?;
+    let channels = img.channels;
+    let colors = color_channels(channels);
+    let buf_read: &[u8] = img.buf_read;
+
+    img.buf_write
+        .par_iter_mut()
+        .zip(buf_read.par_iter().zip(difference.par_iter()))
+        .enumerate()
+        .for_each(|(i, (value, (&original, &difference)))| {
+            *value = if i % channels >= colors {
+                original
+            } else {
+                (difference + DOG_OFFSET).round().min(255.0).max(0.0) as u8
+            };
+        });
+
+    Ok(())
+}
+
+/// Approximation of the Laplacian that a blob detector searches the extrema of
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ScaleSpace {
+    /// Difference between blurs of neighbouring scales, which is fast
+    DoG,
+    /// Laplacian of each blur, which is exact but slower
+    LoG,
+}
+
+/// Parameters of the blob detector, blobs have a radius of about `sigma * √2`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Blobs {
+    pub space: ScaleSpace,
+    pub min_sigma: f32,
+    pub max_sigma: f32,
+    /// Number of sigmas from `min_sigma` to `max_sigma`, spaced geometrically
+    pub scales: usize,
+    /// Smallest absolute response on the 8-bit scale of a blob
+    pub threshold: f32,
+}
+
+impl Default for Blobs {
+    fn default() -> Self {
+        Blobs {
+            space: ScaleSpace::DoG,
+            min_sigma: 1.0,
+            max_sigma: 16.0,
+            scales: 9,
+            threshold: 10.0,
+        }
+    }
+}
+
+/// A blob at a pixel and scale, of which the response is positive for bright blobs on a dark
+/// background and negative for dark blobs
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Keypoint {
+    pub x: u32,
+    pub y: u32,
+    pub sigma: f32,
+    pub response: f32,
+}
+
+impl Keypoint {
+    /// Radius of the blob, at which the Laplacian of its scale changes sign
+    pub fn radius(&self) -> f32 {
+        self.sigma * std::f32::consts::SQRT_2
+    }
+}
+
+pub fn detect_blobs<T>(img: &Image<T>, blobs: Blobs) -> Vec<Keypoint>
+where
+    T: Sync + Send + Copy + Into<f32>,
+{
+    try_detect_blobs(img, blobs).unwrap_or_else(|err| panic!("{}", err))
+}
+
+/// Find the extrema of the scale-normalized Laplacian of the luma of `buf_read` among their 26
+/// neighbours in position and scale, strongest first
+pub fn try_detect_blobs<T>(img: &Image<T>, blobs: Blobs) -> Result<Vec<Keypoint>, FilterError>
+where
+    T: Sync + Send + Copy + Into<f32>,
+{
+    validate_image(img)?;
+
+    if blobs.scales == 0 || blobs.min_sigma > blobs.max_sigma {
+        return Err(FilterError::InvalidParameter(format!(
+            "Blobs need at least one scale and --min-sigma <= --max-sigma, got {} scales from {} \
+             to {}",
+            blobs.scales, blobs.min_sigma, blobs.max_sigma
+        )));
+    }
+
+    let (width, height) = (img.width as usize, img.height as usize);
+    let luma = gray(img);
+
+    // Sigmas spaced geometrically, of which each is the previous one times `ratio`
+    let ratio = match blobs.scales {
+        1 => DOG_RATIO,
+        n => (blobs.max_sigma / blobs.min_sigma).powf(1.0 / (n - 1) as f32),
+    };
+    let sigmas = (0..blobs.scales)
+        .map(|i| blobs.min_sigma * ratio.powi(i as i32))
+        .collect::<Vec<_>>();
+
+    let blur = |sigma| blur_plane(luma.clone(), img.width, img.height, sigma);
+
+    // Responses scaled by sigma², so blobs of every size respond alike
+    let responses = match blobs.space {
+        ScaleSpace::DoG => {
+            let ratio = ratio.max(1.0 + f32::EPSILON.sqrt());
+            let mut blurred = blur(sigmas[0])?;
+            let mut responses = Vec::with_capacity(sigmas.len());
+
+            // G(σ) - G(kσ) ≈ -(k - 1) σ² ∇²G
+            for &sigma in &sigmas {
+                let next = blur(sigma * ratio)?;
+                responses.push(
+                    blurred
+                        .par_iter()
+                        .zip(&next)
+                        .map(|(a, b)| (a - b) / (ratio - 1.0))
+                        .collect::<Vec<_>>(),
+                );
+                blurred = next;
+            }
+
+            responses
+        }
+        ScaleSpace::LoG => sigmas
+            .iter()
+            .map(|&sigma| {
+                let blurred = blur(sigma)?;
+                Ok(laplacian(&blurred, width, height)
+                    .into_iter()
+                    .map(|v| -v * sigma * sigma)
+                    .collect())
+            })
+            .collect::<Result<Vec<_>, FilterError>>()?,
+    };
+
+    let mut keypoints = (0..sigmas.len())
+        .into_par_iter()
+        .flat_map(|s| {
+            let (responses, sigmas) = (&responses, &sigmas);
+
+            (0..height).into_par_iter().flat_map_iter(move |y| {
+                (0..width).filter_map(move |x| {
+                    let value = responses[s][y * width + x];
+
+                    if value.abs() < blobs.threshold
+                        || !is_extremum(responses, (width, height), (x, y, s), value)
+                    {
+                        return None;
+                    }
+
+                    Some(Keypoint {
+                        x: x as u32,
+                        y: y as u32,
+                        sigma: sigmas[s],
+                        response: value,
+                    })
+                })
+            })
+        })
+        .collect::<Vec<_>>();
+
+    keypoints.sort_by(|a, b| b.response.abs().partial_cmp(&a.response.abs()).unwrap());
+
+    Ok(keypoints)
+}
+
+/// Whether `value` is at least as far from zero as each neighbour in position and scale, with
+/// the same sign
+fn is_extremum(
+    responses: &[Vec<f32>],
+    (width, height): (usize, usize),
+    (x, y, s): (usize, usize, usize),
+    value: f32,
+) -> bool {
+    let range = |i: usize, len: usize| i.saturating_sub(1)..(i + 2).min(len);
+
+    range(s, responses.len()).all(|ns| {
+        range(y, height).all(|ny| {
+            range(x, width).all(|nx| {
+                let neighbour = responses[ns][ny * width + nx];
+
+                if value > 0.0 {
+                    value >= neighbour
+                } else {
+                    value <= neighbour
+                }
+            })
+        })
+    })
+}
+
+/// Discrete Laplacian of a plane, of which the edges are clamped
+fn laplacian(buf: &[f32], width: usize, height: usize) -> Vec<f32> {
+    (0..width * height)
+        .into_par_iter()
+        .map(|i| {
+            let (x, y) = (i % width, i / width);
+            let at = |x: usize, y: usize| buf[y * width + x];
+
+            at(x.saturating_sub(1), y)
+                + at((x + 1).min(width - 1), y)
+                + at(x, y.saturating_sub(1))
+                + at(x, (y + 1).min(height - 1))
+                - 4.0 * at(x, y)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A black image with a white disk of `radius` at (20, 16)
+    fn disk(radius: f32) -> Vec<u8> {
+        (0..40 * 32)
+            .map(|i| {
+                let (x, y) = ((i % 40) as f32 - 20.0, (i / 40) as f32 - 16.0);
+                if x.hypot(y) <= radius {
+                    255
+                } else {
+                    0
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_dog() {
+        // A bright spot in gray-alpha
+        let mut buf_read = vec![0, 255, 200, 255, 0, 255, 0, 255, 0, 255];
+        let mut buf_write = vec![0; 10];
+        let mut img = Image {
+            buf_read: &mut buf_read,
+            buf_write: &mut buf_write,
+            width: 5,
+            height: 1,
+            channels: 2,
+        };
+
+        let difference = difference_of_gaussians(&img, 0.5, 2.0);
+        assert!(difference[2] > 0.0 && difference[8] < 0.0);
+        assert_eq!(difference[3], 0.0);
+
+        // Alpha is copied and the difference is offset
+        dog(&mut img, 0.5, 2.0);
+        assert_eq!(buf_write[3], 255);
+        assert!(buf_write[2] > DOG_OFFSET as u8);
+    }
+
+    #[test]
+    fn test_detect_blobs() {
+        for &space in [ScaleSpace::DoG, ScaleSpace::LoG].iter() {
+            let mut buf_read = disk(4.0);
+            let mut buf_write = buf_read.clone();
+            let img = Image {
+                buf_read: &mut buf_read,
+                buf_write: &mut buf_write,
+                width: 40,
+                height: 32,
+                channels: 1,
+            };
+
+            let blobs = Blobs {
+                space,
+                min_sigma: 1.0,
+                max_sigma: 8.0,
+                scales: 13,
+                threshold: 20.0,
+            };
+
+            let keypoint = detect_blobs(&img, blobs)[0];
+
+            assert_eq!((keypoint.x, keypoint.y), (20, 16), "{:?}", space);
+            assert!(keypoint.response > 0.0);
+            assert!((keypoint.radius() - 4.0).abs() < 1.5, "{:?}", keypoint);
+        }
+    }
+}
@@ -0,0 +1,145 @@
+use crate::{validate_image, FilterError, Image};
+
+/// Set of the channels of a pixel that a filter is applied to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Channels(u8);
+
+impl Channels {
+    /// Every channel of the image, however many it has, as the filters process them by default
+    pub const ALL: Channels = Channels(u8::MAX);
+
+    /// Select channels by their index within a pixel, i.e. 3 for the alpha of RGBA
+    pub fn from_indices(indices: &[usize]) -> Channels {
+        Channels(indices.iter().fold(0, |mask, &c| mask | 1 << c.min(7)))
+    }
+
+    pub fn contains(self, channel: usize) -> bool {
+        channel < 8 && self.0 & 1 << channel != 0
+    }
+}
+
+impl Default for Channels {
+    fn default() -> Self {
+        Channels::ALL
+    }
+}
+
+/// Run a filter and keep its result only in the selected channels, the others are copied from
+/// `buf_read` unchanged
+pub fn try_with_channels<T, F>(
+    img: &mut Image<T>,
+    channels: Channels,
+    filter: F,
+) -> Result<(), FilterError>
+where
+    T: Sync + Send + Copy + Into<f32>,
+    F: FnOnce(&mut Image<T>) -> Result<(), FilterError>,
+{
+    validate_image(img)?;
+
+    let missing = (img.channels..8).find(|&c| channels.contains(c));
+
+    if let (Some(c), false) = (missing, channels == Channels::ALL) {
+        return Err(FilterError::InvalidParameter(format!(
+            "Channel {} does not exist in an image with {} channels",
+            c, img.channels
+        )));
+    }
+
+    if (0..img.channels).all(|c| channels.contains(c)) {
+        return filter(img);
+    }
+
+    // The filters may overwrite both buffers, so keep the unselected channels aside
+    let original = img.buf_read.to_vec();
+    let n = img.channels;
+
+    filter(img)?;
+
+    for (pixel, original) in img
+        .buf_write
+        .chunks_exact_mut(n)
+        .zip(original.chunks_exact(n))
+    {
+        for c in (0..n).filter(|&c| !channels.contains(c)) {
+            pixel[c] = original[c];
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{try_box_blur_2d, try_sobel2d_channels};
+
+    #[test]
+    fn test_with_channels() {
+        // A hard-edged alpha mask next to a color gradient
+        let mut buf_read = vec![0, 0, 0, 0, 100, 100, 100, 0, 200, 200, 200, 255];
+        let mut buf_write = vec![0; 12];
+
+        try_with_channels(
+            &mut Image {
+                buf_read: &mut buf_read,
+                buf_write: &mut buf_write,
+                width: 3,
+                height: 1,
+                channels: 4,
+            },
+            Channels::from_indices(&[3]),
+            |img| try_box_blur_2d(img, 1),
+        )
+        .unwrap();
+
+        // Color is unchanged and alpha is softened
+        assert_eq!(&buf_write[..3], &[0, 0, 0]);
+        assert_eq!(&buf_write[4..7], &[100, 100, 100]);
+        assert!(buf_write[7] > 0 && buf_write[11] < 255);
+    }
+
+    #[test]
+    fn test_all_channels() {
+        // Gray and RGB have no channel 3, which `Channels::ALL` does not require
+        for &channels in [1, 3].iter() {
+            let mut buf_read = (0..3)
+                .flat_map(|x| vec![if x == 1 { 255 } else { 0 }; channels])
+                .collect::<Vec<u8>>();
+            let mut buf_write = vec![0; buf_read.len()];
+            let mut img = Image {
+                buf_read: &mut buf_read,
+                buf_write: &mut buf_write,
+                width: 3,
+                height: 1,
+                channels,
+            };
+
+            try_with_channels(&mut img, Channels::default(), |img| try_box_blur_2d(img, 1))
+                .unwrap();
+            assert!(img.buf_write[0] > 0, "{:?}", img.buf_write);
+
+            try_sobel2d_channels(&mut img, None, Channels::ALL).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_invalid_channel() {
+        let mut buf_read = vec![0u8; 3];
+        let mut buf_write = vec![0u8; 3];
+
+        let result = try_with_channels(
+            &mut Image {
+                buf_read: &mut buf_read,
+                buf_write: &mut buf_write,
+                width: 1,
+                height: 1,
+                channels: 3,
+            },
+            Channels::from_indices(&[0, 3]),
+            |_| Ok(()),
+        );
+
+        assert!(matches!(result, Err(FilterError::InvalidParameter(_))));
+    }
+}
@@ -0,0 +1,431 @@
+use crate::{validate_buffer, validate_image, Backend, Cpu, FilterError, Image};
+
+/// Weights of the red, green and blue channels in the luma of a pixel
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Luma {
+    /// ITU-R BT.601, as used by JPEG and SD video
+    Bt601,
+    /// ITU-R BT.709, as used by HD video and sRGB
+    Bt709,
+    /// Equal weights for each channel
+    Average,
+}
+
+impl Default for Luma {
+    fn default() -> Self {
+        Luma::Bt601
+    }
+}
+
+impl Luma {
+    pub fn weights(self) -> [f32; 3] {
+        match self {
+            Luma::Bt601 => [0.299, 0.587, 0.114],
+            Luma::Bt709 => [0.2126, 0.7152, 0.0722],
+            Luma::Average => [1.0 / 3.0; 3],
+        }
+    }
+
+    /// Weighted sum of the red, green and blue values of a pixel
+    pub fn luma(self, r: f32, g: f32, b: f32) -> f32 {
+        let [wr, wg, wb] = self.weights();
+
+        wr * r + wg * g + wb * b
+    }
+}
+
+/// Color space of the first three channels of a pixel, each scaled to the 8-bit range
+///
+/// Hue, saturation, value and lightness span 0 to 255. Chroma of YCbCr is centered at 128 as in
+/// JPEG. L* of Lab is scaled from 0 to 100 and a* and b* are offset by 128. As 8-bit hue and
+/// a*/b* are coarse, saturated colors can change by a few values after a round trip.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ColorSpace {
+    Rgb,
+    /// Luma repeated in all three channels
+    Gray(Luma),
+    Hsv,
+    Hsl,
+    /// Full-range BT.601 YCbCr
+    YCbCr,
+    /// CIE L*a*b* of sRGB with a D65 white point
+    Lab,
+}
+
+impl ColorSpace {
+    pub fn channel_names(self) -> [&'static str; 3] {
+        match self {
+            ColorSpace::Rgb => ["r", "g", "b"],
+            ColorSpace::Gray(_) => ["y", "y", "y"],
+            ColorSpace::Hsv => ["h", "s", "v"],
+            ColorSpace::Hsl => ["h", "s", "l"],
+            ColorSpace::YCbCr => ["y", "cb", "cr"],
+            ColorSpace::Lab => ["l", "a", "b"],
+        }
+    }
+
+    /// Index of the channel that holds the brightness of a pixel
+    pub fn lightness(self) -> usize {
+        match self {
+            ColorSpace::Hsv | ColorSpace::Hsl => 2,
+            _ => 0,
+        }
+    }
+
+    /// Convert an RGB pixel to this color space
+    pub fn from_rgb(self, rgb: [f32; 3]) -> [f32; 3] {
+        let [r, g, b] = scale(rgb, 1.0 / 255.0);
+
+        match self {
+            ColorSpace::Rgb => rgb,
+            ColorSpace::Gray(luma) => [luma.luma(rgb[0], rgb[1], rgb[2]); 3],
+            ColorSpace::Hsv => scale(rgb_to_hsv([r, g, b]), 255.0),
+            ColorSpace::Hsl => scale(rgb_to_hsl([r, g, b]), 255.0),
+            ColorSpace::YCbCr => rgb_to_ycbcr(rgb),
+            ColorSpace::Lab => {
+                let [l, a, b] = rgb_to_lab([r, g, b]);
+                [l * 2.55, a + 128.0, b + 128.0]
+            }
+        }
+    }
+
+    /// Convert a pixel in this color space to RGB
+    pub fn to_rgb(self, values: [f32; 3]) -> [f32; 3] {
+        let normalized = scale(values, 1.0 / 255.0);
+
+        match self {
+            ColorSpace::Rgb => values,
+            ColorSpace::Gray(_) => [values[0]; 3],
+            ColorSpace::Hsv => scale(hsv_to_rgb(normalized), 255.0),
+            ColorSpace::Hsl => scale(hsl_to_rgb(normalized), 255.0),
+            ColorSpace::YCbCr => ycbcr_to_rgb(values),
+            ColorSpace::Lab => {
+                let [l, a, b] = values;
+                scale(lab_to_rgb([l / 2.55, a - 128.0, b - 128.0]), 255.0)
+            }
+        }
+    }
+}
+
+pub fn convert(buf: &mut [u8], channels: usize, from: ColorSpace, to: ColorSpace) {
+    try_convert(buf, channels, from, to).unwrap_or_else(|err| panic!("{}", err))
+}
+
+/// Convert the first three channels of each pixel from one color space to another in place,
+/// alpha is left unchanged
+pub fn try_convert(
+    buf: &mut [u8],
+    channels: usize,
+    from: ColorSpace,
+    to: ColorSpace,
+) -> Result<(), FilterError> {
+    validate_buffer(buf, channels)?;
+
+    if from == to {
+        return Ok(());
+    }
+
+    if channels < 3 {
+        return Err(FilterError::UnsupportedChannels(channels));
+    }
+
+    Cpu.map_pixels(buf, channels, |p| {
+        let rgb = from.to_rgb([p[0] as f32, p[1] as f32, p[2] as f32]);
+
+        for (channel, value) in p.iter_mut().zip(&to.from_rgb(rgb)) {
+            *channel = value.round().min(255.0).max(0.0) as u8;
+        }
+    });
+
+    Ok(())
+}
+
+/// Run a filter on an RGB image in another color space
+///
+/// Both buffers are converted to the color space before the filter runs and are converted back
+/// to RGB afterwards.
+pub fn try_in_color_space<F>(
+    img: &mut Image<u8>,
+    space: ColorSpace,
+    filter: F,
+) -> Result<(), FilterError>
+where
+    F: FnOnce(&mut Image<u8>) -> Result<(), FilterError>,
+{
+    validate_image(img)?;
+
+    try_convert(img.buf_read, img.channels, ColorSpace::Rgb, space)?;
+    try_convert(img.buf_write, img.channels, ColorSpace::Rgb, space)?;
+
+    filter(img)?;
+
+    try_convert(img.buf_read, img.channels, space, ColorSpace::Rgb)?;
+    try_convert(img.buf_write, img.channels, space, ColorSpace::Rgb)
+}
+
+fn scale([a, b, c]: [f32; 3], factor: f32) -> [f32; 3] {
+    [a * factor, b * factor, c * factor]
+}
+
+/// Hue as a fraction of a turn, with the largest and smallest value of a pixel
+fn hue([r, g, b]: [f32; 3]) -> (f32, f32, f32) {
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    let delta = max - min;
+
+    let sector = if delta == 0.0 {
+        0.0
+    } else if max == r {
+        ((g - b) / delta).rem_euclid(6.0)
+    } else if max == g {
+        (b - r) / delta + 2.0
+    } else {
+        (r - g) / delta + 4.0
+    };
+
+    (sector / 6.0, max, min)
+}
+
+/// RGB of a hue with the given chroma, offset by `m`
+fn from_hue(hue: f32, chroma: f32, m: f32) -> [f32; 3] {
+    let sector = hue.rem_euclid(1.0) * 6.0;
+    let x = chroma * (1.0 - (sector % 2.0 - 1.0).abs());
+
+    let [r, g, b] = match sector as u32 {
+        0 => [chroma, x, 0.0],
+        1 => [x, chroma, 0.0],
+        2 => [0.0, chroma, x],
+        3 => [0.0, x, chroma],
+        4 => [x, 0.0, chroma],
+        _ => [chroma, 0.0, x],
+    };
+
+    [r + m, g + m, b + m]
+}
+
+fn rgb_to_hsv(rgb: [f32; 3]) -> [f32; 3] {
+    let (h, max, min) = hue(rgb);
+    let s = if max == 0.0 { 0.0 } else { (max - min) / max };
+
+    [h, s, max]
+}
+
+fn hsv_to_rgb([h, s, v]: [f32; 3]) -> [f32; 3] {
+    let chroma = v * s;
+
+    from_hue(h, chroma, v - chroma)
+}
+
+fn rgb_to_hsl(rgb: [f32; 3]) -> [f32; 3] {
+    let (h, max, min) = hue(rgb);
+    let l = (max + min) / 2.0;
+    let s = if max == min {
+        0.0
+    } else {
+        (max - min) / (1.0 - (2.0 * l - 1.0).abs())
+    };
+
+    [h, s, l]
+}
+
+fn hsl_to_rgb([h, s, l]: [f32; 3]) -> [f32; 3] {
+    let chroma = (1.0 - (2.0 * l - 1.0).abs()) * s;
+
+    from_hue(h, chroma, l - chroma / 2.0)
+}
+
+/// Offset of the chroma of 8-bit YCbCr
+const CHROMA_OFFSET: f32 = 128.0;
+
+fn rgb_to_ycbcr([r, g, b]: [f32; 3]) -> [f32; 3] {
+    [
+        Luma::Bt601.luma(r, g, b),
+        CHROMA_OFFSET - 0.168_736 * r - 0.331_264 * g + 0.5 * b,
+        CHROMA_OFFSET + 0.5 * r - 0.418_688 * g - 0.081_312 * b,
+    ]
+}
+
+fn ycbcr_to_rgb([y, cb, cr]: [f32; 3]) -> [f32; 3] {
+    let (cb, cr) = (cb - CHROMA_OFFSET, cr - CHROMA_OFFSET);
+
+    [
+        y + 1.402 * cr,
+        y - 0.344_136 * cb - 0.714_136 * cr,
+        y + 1.772 * cb,
+    ]
+}
+
+/// D65 white point in CIE XYZ
+const WHITE: [f32; 3] = [0.950_47, 1.0, 1.088_83];
+
+/// Threshold below which the Lab transfer function is linear
+const EPSILON: f32 = 6.0 / 29.0;
+
+fn rgb_to_lab([r, g, b]: [f32; 3]) -> [f32; 3] {
+    let [r, g, b] = [linear(r), linear(g), linear(b)];
+
+    let xyz = [
+        0.412_456_4 * r + 0.357_576_1 * g + 0.180_437_5 * b,
+        0.212_672_9 * r + 0.715_152_2 * g + 0.072_175 * b,
+        0.019_333_9 * r + 0.119_192 * g + 0.950_304_1 * b,
+    ];
+
+    let f = |t: f32| {
+        if t > EPSILON.powi(3) {
+            t.cbrt()
+        } else {
+            t / (3.0 * EPSILON * EPSILON) + 4.0 / 29.0
+        }
+    };
+
+    let [fx, fy, fz] = [
+        f(xyz[0] / WHITE[0]),
+        f(xyz[1] / WHITE[1]),
+        f(xyz[2] / WHITE[2]),
+    ];
+
+    [116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz)]
+}
+
+fn lab_to_rgb([l, a, b]: [f32; 3]) -> [f32; 3] {
+    let fy = (l + 16.0) / 116.0;
+
+    let f = |t: f32| {
+        if t > EPSILON {
+            t.powi(3)
+        } else {
+            3.0 * EPSILON * EPSILON * (t - 4.0 / 29.0)
+        }
+    };
+
+    let [x, y, z] = [
+        WHITE[0] * f(fy + a / 500.0),
+        WHITE[1] * f(fy),
+        WHITE[2] * f(fy - b / 200.0),
+    ];
+
+    [
+        gamma(3.240_454_2 * x - 1.537_138_5 * y - 0.498_531_4 * z),
+        gamma(-0.969_266 * x + 1.876_010_8 * y + 0.041_556 * z),
+        gamma(0.055_643_4 * x - 0.204_025_9 * y + 1.057_225_2 * z),
+    ]
+}
+
+/// Decode a gamma-encoded sRGB value to linear light
+pub(crate) fn linear(c: f32) -> f32 {
+    if c <= 0.040_45 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Encode a linear light value with the sRGB gamma
+pub(crate) fn gamma(c: f32) -> f32 {
+    if c <= 0.003_130_8 {
+        12.92 * c
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_abs_diff_eq;
+
+    /// Color spaces with the largest error of an 8-bit round trip through them
+    const SPACES: [(ColorSpace, u8); 4] = [
+        (ColorSpace::Hsv, 2),
+        (ColorSpace::Hsl, 2),
+        (ColorSpace::YCbCr, 1),
+        (ColorSpace::Lab, 4),
+    ];
+
+    #[test]
+    fn test_luma() {
+        assert_abs_diff_eq!(Luma::Bt601.luma(255.0, 255.0, 255.0), 255.0, epsilon = 1e-3);
+        assert_abs_diff_eq!(Luma::Bt709.luma(0.0, 100.0, 0.0), 71.52, epsilon = 1e-3);
+        assert_abs_diff_eq!(Luma::Average.luma(30.0, 60.0, 90.0), 60.0, epsilon = 1e-3);
+    }
+
+    #[test]
+    fn test_known_values() {
+        let red = [255.0, 0.0, 0.0];
+
+        assert_eq!(ColorSpace::Hsv.from_rgb(red), [0.0, 255.0, 255.0]);
+        assert_eq!(ColorSpace::Hsl.from_rgb(red), [0.0, 255.0, 127.5]);
+
+        let [y, cb, cr] = ColorSpace::YCbCr.from_rgb([255.0, 255.0, 255.0]);
+        assert_abs_diff_eq!(y, 255.0, epsilon = 1e-3);
+        assert_abs_diff_eq!(cb, 128.0, epsilon = 1e-3);
+        assert_abs_diff_eq!(cr, 128.0, epsilon = 1e-3);
+
+        // L*a*b* of sRGB red is about (53.24, 80.09, 67.20)
+        let [l, a, b] = rgb_to_lab([1.0, 0.0, 0.0]);
+        assert_abs_diff_eq!(l, 53.24, epsilon = 0.05);
+        assert_abs_diff_eq!(a, 80.09, epsilon = 0.05);
+        assert_abs_diff_eq!(b, 67.20, epsilon = 0.05);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let original = (0..16 * 16 * 4)
+            .map(|i| (i * 53 % 256) as u8)
+            .collect::<Vec<_>>();
+
+        for &(space, tolerance) in SPACES.iter() {
+            let mut buf = original.clone();
+
+            convert(&mut buf, 4, ColorSpace::Rgb, space);
+            convert(&mut buf, 4, space, ColorSpace::Rgb);
+
+            for (pixel, expect) in buf.chunks_exact(4).zip(original.chunks_exact(4)) {
+                // Alpha is never converted
+                assert_eq!(pixel[3], expect[3]);
+
+                for c in 0..3 {
+                    assert!(
+                        pixel[c].max(expect[c]) - pixel[c].min(expect[c]) <= tolerance,
+                        "{:?}: {:?} became {:?}",
+                        space,
+                        expect,
+                        pixel
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_in_color_space() {
+        let mut buf_read = vec![200, 40, 90, 255, 10, 220, 30, 128];
+        let mut buf_write = vec![0; 8];
+
+        // A filter that copies the pixels leaves them unchanged after the round trip
+        try_in_color_space(
+            &mut Image {
+                buf_read: &mut buf_read,
+                buf_write: &mut buf_write,
+                width: 2,
+                height: 1,
+                channels: 4,
+            },
+            ColorSpace::YCbCr,
+            |img| {
+                img.buf_write.copy_from_slice(img.buf_read);
+                Ok(())
+            },
+        )
+        .unwrap();
+
+        for (&actual, &expect) in buf_write.iter().zip(&[200, 40, 90, 255, 10, 220, 30, 128]) {
+            assert!(actual.max(expect) - actual.min(expect) <= 1);
+        }
+
+        assert_eq!(
+            try_convert(&mut [0; 4], 2, ColorSpace::Rgb, ColorSpace::Lab),
+            Err(FilterError::UnsupportedChannels(2))
+        );
+    }
+}
@@ -0,0 +1,239 @@
+use crate::{gradient_planes, gray, validate_image, Backend, Cpu, FilterError, Image};
+use rayon::prelude::*;
+
+/// Response of the structure tensor M, the gradients summed over the window around a pixel
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CornerResponse {
+    /// Harris' response det(M) - k·trace(M)², of which `k` is usually 0.04 to 0.06
+    Harris { k: f32 },
+    /// Shi and Tomasi's response, the smaller eigenvalue of M
+    ShiTomasi,
+}
+
+/// Parameters of the corner detector
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Corners {
+    pub response: CornerResponse,
+    /// Sigma of the Gaussian window over which the gradients are summed
+    pub sigma: f32,
+    /// Smallest response relative to the strongest corner, from 0 to 1
+    pub quality: f32,
+    /// Radius within which a corner has the largest response
+    pub min_distance: u32,
+    /// Largest number of corners, of which the strongest are kept
+    pub max_corners: Option<usize>,
+}
+
+impl Default for Corners {
+    fn default() -> Self {
+        Corners {
+            response: CornerResponse::Harris { k: 0.04 },
+            sigma: 1.0,
+            quality: 0.01,
+            min_distance: 3,
+            max_corners: None,
+        }
+    }
+}
+
+/// A corner at a pixel, with the response of the detector
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Corner {
+    pub x: u32,
+    pub y: u32,
+    pub response: f32,
+}
+
+pub fn detect_corners<T>(img: &Image<T>, corners: Corners) -> Vec<Corner>
+where
+    T: Sync + Send + Copy + Into<f32>,
+{
+    try_detect_corners(img, corners).unwrap_or_else(|err| panic!("{}", err))
+}
+
+/// Find the maxima of the corner response of the luma of `buf_read`, strongest first
+pub fn try_detect_corners<T>(img: &Image<T>, corners: Corners) -> Result<Vec<Corner>, FilterError>
+where
+    T: Sync + Send + Copy + Into<f32>,
+{
+    validate_image(img)?;
+
+    if !(0.0..=1.0).contains(&corners.quality) {
+        return Err(FilterError::InvalidParameter(format!(
+            "Quality level must be from 0 to 1, got {}",
+            corners.quality
+        )));
+    }
+
+    let (width, height) = (img.width as usize, img.height as usize);
+    let (gx, gy) = gradient_planes(gray(img), img.width, img.height)?;
+
+    // Sum the products of the gradients over a Gaussian window
+    let window = |mut buf_read: Vec<f32>| -> Result<Vec<f32>, FilterError> {
+        let mut buf_write = vec![0.0; buf_read.len()];
+
+        Cpu.gaussian_blur_2d(
+            &mut Image {
+                buf_read: &mut buf_read,
+                buf_write: &mut buf_write,
+                width: img.width,
+                height: img.height,
+                channels: 1,
+            },
+            corners.sigma,
+        )?;
+
+        Ok(buf_write)
+    };
+
+    let xx = window(gx.par_iter().map(|x| x * x).collect())?;
+    let yy = window(gy.par_iter().map(|y| y * y).collect())?;
+    let xy = window(gx.par_iter().zip(&gy).map(|(x, y)| x * y).collect())?;
+
+    let responses = (0..xx.len())
+        .into_par_iter()
+        .map(|i| {
+            let (a, b, c) = (xx[i], xy[i], yy[i]);
+
+            match corners.response {
+                CornerResponse::Harris { k } => a * c - b * b - k * (a + c).powi(2),
+                CornerResponse::ShiTomasi => {
+                    (a + c) / 2.0 - (((a - c) / 2.0).powi(2) + b * b).sqrt()
+                }
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let strongest = responses.par_iter().cloned().reduce(|| 0.0, f32::max);
+
+    if strongest <= 0.0 {
+        return Ok(Vec::new());
+    }
+
+    let limit = strongest * corners.quality;
+    let radius = corners.min_distance as usize;
+    let responses = &responses;
+
+    let mut found = (0..height)
+        .into_par_iter()
+        .flat_map_iter(|y| {
+            (0..width).filter_map(move |x| {
+                let i = y * width + x;
+                let value = responses[i];
+
+                if value <= 0.0 || value < limit {
+                    return None;
+                }
+
+                // Keep the first of equal maxima, so plateaus give a single corner
+                let range =
+                    |i: usize, len: usize| i.saturating_sub(radius)..(i + radius + 1).min(len);
+                let is_maximum = range(y, height).all(|ny| {
+                    range(x, width).all(|nx| {
+                        let n = ny * width + nx;
+                        responses[n] < value || (responses[n] == value && n >= i)
+                    })
+                });
+
+                if !is_maximum {
+                    return None;
+                }
+
+                Some(Corner {
+                    x: x as u32,
+                    y: y as u32,
+                    response: value,
+                })
+            })
+        })
+        .collect::<Vec<_>>();
+
+    found.sort_by(|a, b| b.response.partial_cmp(&a.response).unwrap());
+
+    if let Some(max_corners) = corners.max_corners {
+        found.truncate(max_corners);
+    }
+
+    Ok(found)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A black image with a white square from (8, 8) to (23, 23)
+    fn square() -> Vec<u8> {
+        (0..32 * 32)
+            .map(|i| {
+                let (x, y) = (i % 32, i / 32);
+                if (8..24).contains(&x) && (8..24).contains(&y) {
+                    255
+                } else {
+                    0
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_detect_corners() {
+        let responses = [
+            CornerResponse::Harris { k: 0.04 },
+            CornerResponse::ShiTomasi,
+        ];
+
+        for &response in responses.iter() {
+            let mut buf_read = square();
+            let mut buf_write = buf_read.clone();
+            let img = Image {
+                buf_read: &mut buf_read,
+                buf_write: &mut buf_write,
+                width: 32,
+                height: 32,
+                channels: 1,
+            };
+
+            let corners = Corners {
+                response,
+                quality: 0.1,
+                ..Corners::default()
+            };
+
+            let found = detect_corners(&img, corners);
+            assert_eq!(found.len(), 4, "{:?}", found);
+
+            for corner in found {
+                let near = |v: u32| (7..=9).contains(&v) || (22..=24).contains(&v);
+                assert!(near(corner.x) && near(corner.y), "{:?}", corner);
+            }
+
+            let strongest = Corners {
+                max_corners: Some(2),
+                ..corners
+            };
+            assert_eq!(detect_corners(&img, strongest).len(), 2);
+        }
+    }
+
+    #[test]
+    fn test_invalid_quality() {
+        let mut buf = vec![0u8; 4];
+        let img = Image {
+            buf_read: &mut buf.clone(),
+            buf_write: &mut buf,
+            width: 2,
+            height: 2,
+            channels: 1,
+        };
+
+        let corners = Corners {
+            quality: 2.0,
+            ..Corners::default()
+        };
+
+        assert!(matches!(
+            try_detect_corners(&img, corners),
+            Err(FilterError::InvalidParameter(_))
+        ));
+    }
+}
@@ -0,0 +1,55 @@
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FilterError {
+    /// Sigma was zero, negative, not a finite number or too large for a kernel
+    InvalidSigma(f32),
+    /// A filter parameter is out of range, described by the message
+    InvalidParameter(String),
+    /// Kernel dimensions are empty or not odd
+    InvalidKernel { rows: usize, cols: usize },
+    /// A buffer does not match `width * height * channels`
+    BufferSize { expected: usize, actual: usize },
+    /// The filter does not support the number of channels
+    UnsupportedChannels(usize),
+    /// No suitable graphics/compute adapter was found
+    AdapterUnavailable,
+    /// The adapter refused to create a device
+    DeviceRequest(String),
+    /// The compute shader could not be loaded
+    Shader(String),
+    /// The computed result could not be read back from the GPU
+    BufferMap,
+}
+
+impl fmt::Display for FilterError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FilterError::InvalidSigma(sigma) => {
+                write!(f, "--sigma should be > 0.0 and at most 1365, got {}", sigma)
+            }
+            FilterError::InvalidParameter(message) => write!(f, "{}", message),
+            FilterError::InvalidKernel { rows, cols } => write!(
+                f,
+                "kernel should have an odd, non-zero size, got {}×{}",
+                rows, cols
+            ),
+            FilterError::BufferSize { expected, actual } => write!(
+                f,
+                "buffer should hold {} elements, got {}",
+                expected, actual
+            ),
+            FilterError::UnsupportedChannels(channels) => {
+                write!(f, "unsupported number of channels: {}", channels)
+            }
+            FilterError::AdapterUnavailable => write!(f, "no suitable GPU adapter found"),
+            FilterError::DeviceRequest(reason) => {
+                write!(f, "failed to request GPU device: {}", reason)
+            }
+            FilterError::Shader(reason) => write!(f, "failed to load compute shader: {}", reason),
+            FilterError::BufferMap => write!(f, "failed to read back result from GPU"),
+        }
+    }
+}
+
+impl std::error::Error for FilterError {}
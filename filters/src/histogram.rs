@@ -0,0 +1,343 @@
+use crate::{color_channels, validate_buffer, validate_image, FilterError, Image, Luma, Weight};
+use rayon::prelude::*;
+
+/// Number of bins of a histogram, one for each 8-bit value
+pub const BINS: usize = 256;
+
+/// Number of occurrences of each 8-bit value
+pub type Histogram = [u32; BINS];
+
+/// Mapping of each 8-bit value to its equalized value
+type Lut = [u8; BINS];
+
+/// Histogram of each channel of a buffer
+pub fn try_histogram(buf: &[u8], channels: usize) -> Result<Vec<Histogram>, FilterError> {
+    validate_buffer(buf, channels)?;
+
+    let mut histograms = vec![[0; BINS]; channels];
+
+    for pixel in buf.chunks_exact(channels) {
+        for (histogram, &value) in histograms.iter_mut().zip(pixel) {
+            histogram[value as usize] += 1;
+        }
+    }
+
+    Ok(histograms)
+}
+
+/// Histogram of the luma of a buffer, with the same weights as `sobel2d`
+///
+/// Buffers with fewer than three channels already hold luma in their first channel.
+pub fn try_luma_histogram(buf: &[u8], channels: usize) -> Result<Histogram, FilterError> {
+    validate_buffer(buf, channels)?;
+
+    let mut histogram = [0; BINS];
+
+    for p in buf.chunks_exact(channels) {
+        let luma: u8 = if channels < 3 {
+            p[0]
+        } else {
+            Weight(Luma::Bt601.luma(p[0] as f32, p[1] as f32, p[2] as f32)).into()
+        };
+
+        histogram[luma as usize] += 1;
+    }
+
+    Ok(histogram)
+}
+
+pub fn equalize(img: &mut Image<u8>) {
+    try_equalize(img).unwrap_or_else(|err| panic!("{}", err))
+}
+
+/// Spread the values of each color channel over the full range, alpha is copied unchanged
+pub fn try_equalize(img: &mut Image<u8>) -> Result<(), FilterError> {
+    validate_image(img)?;
+
+    let channels = img.channels;
+    let colors = color_channels(channels);
+
+    let luts = try_histogram(img.buf_read, channels)?
+        .iter()
+        .take(colors)
+        .map(equalize_lut)
+        .collect::<Vec<_>>();
+
+    let buf_read: &[u8] = img.buf_read;
+
+    img.buf_write
+        .par_chunks_exact_mut(channels)
+        .zip(buf_read.par_chunks_exact(channels))
+        .for_each(|(pixel, src)| {
+            for (c, (channel, &value)) in pixel.iter_mut().zip(src).enumerate() {
+                *channel = match luts.get(c) {
+                    Some(lut) => lut[value as usize],
+                    None => value,
+                };
+            }
+        });
+
+    Ok(())
+}
+
+pub fn clahe(img: &mut Image<u8>, tile_size: u32, clip_limit: f32) {
+    try_clahe(img, tile_size, clip_limit).unwrap_or_else(|err| panic!("{}", err))
+}
+
+/// Contrast-limited adaptive histogram equalization
+///
+/// Each color channel is equalized within square tiles of `tile_size` pixels, of which the
+/// histogram bins are clipped at `clip_limit` times the average bin and the clipped counts are
+/// spread over all bins. Pixels are mapped by interpolating between the four nearest tiles.
+/// Alpha is copied unchanged.
+pub fn try_clahe(img: &mut Image<u8>, tile_size: u32, clip_limit: f32) -> Result<(), FilterError> {
+    validate_image(img)?;
+
+    if tile_size == 0 {
+        return Err(FilterError::InvalidParameter(format!(
+            "--tile-size should be > 0, got {}",
+            tile_size
+        )));
+    }
+
+    // A limit of 1.0 clips every bin to the average, which results in a linear mapping
+    if clip_limit.is_nan() || clip_limit < 1.0 {
+        return Err(FilterError::InvalidParameter(format!(
+            "--clip-limit should be >= 1.0, got {}",
+            clip_limit
+        )));
+    }
+
+    let Image {
+        width,
+        height,
+        channels,
+        ..
+    } = *img;
+
+    // A tile larger than the image covers it like a tile of its size, but would overflow below
+    let tile_size = tile_size.min(width.max(height)).max(1);
+    let tiles = |len: u32| len / tile_size + (len % tile_size != 0) as u32;
+
+    let colors = color_channels(channels);
+    let tiles_x = tiles(width);
+    let tiles_y = tiles(height);
+
+    let buf_read: &[u8] = img.buf_read;
+
+    // Find the mapping of each color channel within each tile
+    let luts = (0..tiles_x * tiles_y)
+        .into_par_iter()
+        .map(|tile| {
+            let x0 = (tile % tiles_x) * tile_size;
+            let y0 = (tile / tiles_x) * tile_size;
+            let x1 = x0 + tile_size.min(width - x0);
+            let y1 = y0 + tile_size.min(height - y0);
+
+            let mut histograms = vec![[0; BINS]; colors];
+
+            for y in y0..y1 {
+                let start = (y * width + x0) as usize * channels;
+                let end = (y * width + x1) as usize * channels;
+
+                for pixel in buf_read[start..end].chunks_exact(channels) {
+                    for (histogram, &value) in histograms.iter_mut().zip(pixel) {
+                        histogram[value as usize] += 1;
+                    }
+                }
+            }
+
+            let pixels = (x1 - x0) * (y1 - y0);
+
+            histograms
+                .iter_mut()
+                .map(|histogram| clahe_lut(histogram, pixels, clip_limit))
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+
+    // Find the two nearest tile centers along an axis and the weight of the second
+    let neighbours = |position: usize, tiles: u32| {
+        let center = (position as f32 + 0.5) / tile_size as f32 - 0.5;
+        let first = center.floor();
+        let weight = center - first;
+
+        let clamp = |tile: f32| tile.min(tiles as f32 - 1.0).max(0.0) as usize;
+
+        (clamp(first), clamp(first + 1.0), weight)
+    };
+
+    img.buf_write
+        .par_chunks_exact_mut(width as usize * channels)
+        .enumerate()
+        .for_each(|(y, row)| {
+            let (ty0, ty1, wy) = neighbours(y, tiles_y);
+            let src_row = &buf_read[y * width as usize * channels..];
+
+            for (x, (pixel, src)) in row
+                .chunks_exact_mut(channels)
+                .zip(src_row.chunks_exact(channels))
+                .enumerate()
+            {
+                let (tx0, tx1, wx) = neighbours(x, tiles_x);
+
+                for (c, (channel, &value)) in pixel.iter_mut().zip(src).enumerate() {
+                    if c >= colors {
+                        *channel = value;
+                        continue;
+                    }
+
+                    let lut = |tx: usize, ty: usize| {
+                        luts[ty * tiles_x as usize + tx][c][value as usize] as f32
+                    };
+
+                    let top = lut(tx0, ty0) * (1.0 - wx) + lut(tx1, ty0) * wx;
+                    let bottom = lut(tx0, ty1) * (1.0 - wx) + lut(tx1, ty1) * wx;
+
+                    *channel = (top * (1.0 - wy) + bottom * wy).round() as u8;
+                }
+            }
+        });
+
+    Ok(())
+}
+
+/// Map the cumulative distribution of a histogram onto the full range, the lowest present
+/// value maps to 0
+fn equalize_lut(histogram: &Histogram) -> Lut {
+    let total = histogram.iter().map(|&count| count as u64).sum::<u64>();
+    let lowest = histogram
+        .iter()
+        .find(|&&count| count > 0)
+        .copied()
+        .unwrap_or(0) as u64;
+
+    let mut lut = [0; BINS];
+    let mut cdf = 0;
+
+    for (value, (mapped, &count)) in lut.iter_mut().zip(histogram.iter()).enumerate() {
+        cdf += count as u64;
+
+        // A single value has no range to spread over
+        *mapped = if total == lowest {
+            value as u8
+        } else {
+            ((cdf.saturating_sub(lowest) * 255 + (total - lowest) / 2) / (total - lowest)) as u8
+        };
+    }
+
+    lut
+}
+
+/// Clip a histogram, redistribute the clipped counts and map its cumulative distribution
+fn clahe_lut(histogram: &mut Histogram, pixels: u32, clip_limit: f32) -> Lut {
+    let limit = ((clip_limit * pixels as f32 / BINS as f32).ceil() as u32).max(1);
+
+    let mut excess = 0;
+
+    for count in histogram.iter_mut() {
+        if *count > limit {
+            excess += *count - limit;
+            *count = limit;
+        }
+    }
+
+    // Spread the clipped counts evenly, with the remainder over the lowest bins
+    let share = excess / BINS as u32;
+    let remainder = excess % BINS as u32;
+
+    for (i, count) in histogram.iter_mut().enumerate() {
+        *count += share + if (i as u32) < remainder { 1 } else { 0 };
+    }
+
+    let mut lut = [0; BINS];
+    let mut cdf = 0;
+
+    for (mapped, &count) in lut.iter_mut().zip(histogram.iter()) {
+        cdf += count as u64;
+        *mapped = ((cdf * 255 + pixels as u64 / 2) / pixels as u64) as u8;
+    }
+
+    lut
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_histogram() {
+        let buf = [0, 10, 0, 20, 255, 20];
+        let histograms = try_histogram(&buf, 2).unwrap();
+
+        assert_eq!(histograms[0][0], 2);
+        assert_eq!(histograms[0][255], 1);
+        assert_eq!(histograms[1][20], 2);
+        assert_eq!(histograms[1].iter().sum::<u32>(), 3);
+
+        let luma = try_luma_histogram(&[255, 255, 255, 0, 0, 0], 3).unwrap();
+        assert_eq!((luma[0], luma[255]), (1, 1));
+
+        assert!(try_histogram(&buf, 4).is_err());
+    }
+
+    #[test]
+    fn test_equalize() {
+        // Two gray values and a constant alpha
+        let mut buf_read = vec![100, 100, 100, 128, 110, 110, 110, 128];
+        let mut buf_write = vec![0; 8];
+
+        equalize(&mut Image {
+            buf_read: &mut buf_read,
+            buf_write: &mut buf_write,
+            width: 2,
+            height: 1,
+            channels: 4,
+        });
+
+        assert_eq!(buf_write, [0, 0, 0, 128, 255, 255, 255, 128]);
+    }
+
+    #[test]
+    fn test_clahe() {
+        // A gradient covering every value once is already equalized, also by a tile that is
+        // larger than the image
+        for &tile_size in [16, u32::MAX].iter() {
+            let mut buf_read = (0..=255).collect::<Vec<u8>>();
+            let mut buf_write = vec![0; 256];
+
+            clahe(
+                &mut Image {
+                    buf_read: &mut buf_read,
+                    buf_write: &mut buf_write,
+                    width: 16,
+                    height: 16,
+                    channels: 1,
+                },
+                tile_size,
+                4.0,
+            );
+
+            for (&expect, &actual) in buf_read.iter().zip(&buf_write) {
+                assert!((expect as i16 - actual as i16).abs() <= 1);
+            }
+        }
+    }
+
+    #[test]
+    fn test_clahe_invalid_parameters() {
+        let mut buf_read = vec![0; 4];
+        let mut buf_write = vec![0; 4];
+        let mut img = Image {
+            buf_read: &mut buf_read,
+            buf_write: &mut buf_write,
+            width: 2,
+            height: 2,
+            channels: 1,
+        };
+
+        assert!(try_clahe(&mut img, 0, 2.0).is_err());
+        assert!(try_clahe(&mut img, 8, 0.5).is_err());
+        assert!(try_clahe(&mut img, 8, f32::NAN).is_err());
+    }
+}
@@ -0,0 +1,680 @@
+use crate::{blur_plane, gradient_planes, gray, validate_image, FilterError, Image};
+use rayon::prelude::*;
+use std::f32::consts::PI;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+/// Smallest luma of an edge pixel in the binary edge images that the transforms vote with
+const EDGE: f32 = 128.0;
+
+/// Smallest cosine between the gradient of an edge pixel and the radius of a circle it supports
+const ALIGNMENT: f32 = 0.9;
+
+/// Sigma of the blur of the votes for the centers of circles
+const ACCUMULATOR_SIGMA: f32 = 1.0;
+
+/// Smallest blurred votes of a center, fewer are noise that is not worth fitting a radius to
+const MIN_VOTES: f32 = 5.0;
+
+/// Parameters of the Hough transform for lines
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HoughLines {
+    /// Resolution of the distance from the origin in pixels
+    pub rho: f32,
+    /// Resolution of the angle in degrees
+    pub theta: f32,
+    /// Smallest number of votes of a line
+    pub threshold: u32,
+    /// Distance in bins within which a line has the most votes
+    pub min_distance: usize,
+    /// Largest number of lines, of which the strongest are kept
+    pub max_lines: Option<usize>,
+}
+
+impl Default for HoughLines {
+    fn default() -> Self {
+        HoughLines {
+            rho: 1.0,
+            theta: 1.0,
+            threshold: 100,
+            min_distance: 10,
+            max_lines: None,
+        }
+    }
+}
+
+/// A line of the points `x·cos(theta) + y·sin(theta) = rho`, with the origin at the top left
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Line {
+    pub rho: f32,
+    /// Angle of the normal in degrees, from 0 to 180 and clockwise from the x-axis
+    pub theta: f32,
+    pub votes: u32,
+}
+
+impl Line {
+    /// Angle of the line in degrees from the x-axis, clockwise from -90 to 90, so rotating the
+    /// image by its negation levels the line
+    pub fn angle(&self) -> f32 {
+        self.theta - 90.0
+    }
+
+    /// End points of the part of the line within an image of `width` × `height`
+    pub fn segment(&self, width: u32, height: u32) -> Option<((f32, f32), (f32, f32))> {
+        let (sin, cos) = self.theta.to_radians().sin_cos();
+        let (right, bottom) = (
+            width.saturating_sub(1) as f32,
+            height.saturating_sub(1) as f32,
+        );
+        let within = |v: f32, max: f32| v >= -1e-3 && v <= max + 1e-3;
+        let mut points = Vec::new();
+
+        // Intersections with the top and bottom edge, and then with the left and right edge
+        if cos.abs() > f32::EPSILON {
+            for &y in [0.0, bottom].iter() {
+                let x = (self.rho - y * sin) / cos;
+                if within(x, right) {
+                    points.push((x, y));
+                }
+            }
+        }
+
+        if sin.abs() > f32::EPSILON {
+            for &x in [0.0, right].iter() {
+                let y = (self.rho - x * cos) / sin;
+                if within(y, bottom) {
+                    points.push((x, y));
+                }
+            }
+        }
+
+        let first = *points.first()?;
+        let distance = |p: &(f32, f32)| (p.0 - first.0).hypot(p.1 - first.1);
+        let last = points
+            .iter()
+            .copied()
+            .max_by(|a, b| distance(a).partial_cmp(&distance(b)).unwrap())?;
+
+        Some((first, last))
+    }
+}
+
+pub fn hough_lines<T>(img: &Image<T>, lines: HoughLines) -> Vec<Line>
+where
+    T: Sync + Send + Copy + Into<f32>,
+{
+    try_hough_lines(img, lines).unwrap_or_else(|err| panic!("{}", err))
+}
+
+/// Find the lines through the edge pixels of `buf_read`, of which the luma is at least 128, as
+/// the peaks of the accumulator of votes for each distance and angle, strongest first
+pub fn try_hough_lines<T>(img: &Image<T>, lines: HoughLines) -> Result<Vec<Line>, FilterError>
+where
+    T: Sync + Send + Copy + Into<f32>,
+{
+    validate_image(img)?;
+
+    let valid = lines.rho > 0.0 && lines.theta > 0.0 && lines.theta <= 180.0;
+
+    if !valid {
+        return Err(FilterError::InvalidParameter(format!(
+            "Hough lines need a rho above 0 and a theta from 0 to 180, got {} and {}",
+            lines.rho, lines.theta
+        )));
+    }
+
+    let width = img.width as usize;
+    let edges = gray(img)
+        .into_iter()
+        .enumerate()
+        .filter(|&(_, luma)| luma >= EDGE)
+        .map(|(i, _)| ((i % width) as f32, (i / width) as f32))
+        .collect::<Vec<_>>();
+
+    // Angles that divide half a turn evenly, so the accumulator wraps around at 180°
+    let thetas = (180.0 / lines.theta).round().max(1.0) as usize;
+    let degrees = 180.0 / thetas as f32;
+
+    // Distances from -diagonal to diagonal, of which `offset` is the bin of zero
+    let diagonal = (img.width as f32).hypot(img.height as f32);
+    let offset = (diagonal / lines.rho).ceil() as usize;
+    let rhos = offset * 2 + 1;
+
+    // Each thread votes for the distances of one angle
+    let mut accumulator = vec![0u32; thetas * rhos];
+
+    accumulator
+        .par_chunks_mut(rhos)
+        .enumerate()
+        .for_each(|(t, votes)| {
+            let (sin, cos) = (t as f32 * degrees).to_radians().sin_cos();
+
+            for &(x, y) in &edges {
+                let r = ((x * cos + y * sin) / lines.rho).round() as isize + offset as isize;
+                votes[r as usize] += 1;
+            }
+        });
+
+    let accumulator = &accumulator;
+    let radius = lines.min_distance as isize;
+
+    let mut found = (0..thetas)
+        .into_par_iter()
+        .flat_map_iter(|t| {
+            (0..rhos).filter_map(move |r| {
+                let i = t * rhos + r;
+                let votes = accumulator[i];
+
+                if votes == 0 || votes < lines.threshold {
+                    return None;
+                }
+
+                // Keep the first of equal peaks, beyond 180° the line continues at 0° with the
+                // opposite distance
+                let is_peak = (-radius..=radius).all(|dt| {
+                    (-radius..=radius).all(|dr| {
+                        let (nt, nr) = (t as isize + dt, r as isize + dr);
+                        let (nt, nr) = if nt < 0 || nt >= thetas as isize {
+                            (nt.rem_euclid(thetas as isize), rhos as isize - 1 - nr)
+                        } else {
+                            (nt, nr)
+                        };
+
+                        if nr < 0 || nr >= rhos as isize {
+                            return true;
+                        }
+
+                        let n = nt as usize * rhos + nr as usize;
+                        accumulator[n] < votes || (accumulator[n] == votes && n >= i)
+                    })
+                });
+
+                if !is_peak {
+                    return None;
+                }
+
+                Some(Line {
+                    rho: (r as f32 - offset as f32) * lines.rho,
+                    theta: t as f32 * degrees,
+                    votes,
+                })
+            })
+        })
+        .collect::<Vec<_>>();
+
+    found.sort_by_key(|line| std::cmp::Reverse(line.votes));
+
+    if let Some(max_lines) = lines.max_lines {
+        found.truncate(max_lines);
+    }
+
+    Ok(found)
+}
+
+/// Parameters of the Hough transform for circles
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HoughCircles {
+    pub min_radius: f32,
+    pub max_radius: f32,
+    /// Sigma of the blur before the gradients are found
+    pub sigma: Option<f32>,
+    /// Smallest gradient magnitude of an edge pixel, on the scale of `sobel2d`
+    pub edge: f32,
+    /// Smallest score of a circle, from 0 to 1
+    pub threshold: f32,
+    /// Smallest distance between the centers of two circles
+    pub min_distance: f32,
+    /// Largest number of circles, of which the strongest are kept
+    pub max_circles: Option<usize>,
+}
+
+impl Default for HoughCircles {
+    fn default() -> Self {
+        HoughCircles {
+            min_radius: 5.0,
+            max_radius: 100.0,
+            sigma: None,
+            edge: 100.0,
+            threshold: 0.5,
+            min_distance: 10.0,
+            max_circles: None,
+        }
+    }
+}
+
+/// A circle, of which the center may lie beyond the edges of the image
+///
+/// The score is the fraction of the part of the circle within the image that lies on edges.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Circle {
+    pub x: f32,
+    pub y: f32,
+    pub radius: f32,
+    pub score: f32,
+}
+
+/// An edge pixel and the direction of its gradient
+struct Edge {
+    x: f32,
+    y: f32,
+    dx: f32,
+    dy: f32,
+}
+
+pub fn hough_circles<T>(img: &Image<T>, circles: HoughCircles) -> Vec<Circle>
+where
+    T: Sync + Send + Copy + Into<f32>,
+{
+    try_hough_circles(img, circles).unwrap_or_else(|err| panic!("{}", err))
+}
+
+/// Find circles in the luma of `buf_read`, strongest first
+///
+/// Each edge pixel votes for the centers along its gradient at each radius. The radius of each
+/// peak of the votes is the one that most edge pixels around it lie at.
+pub fn try_hough_circles<T>(
+    img: &Image<T>,
+    circles: HoughCircles,
+) -> Result<Vec<Circle>, FilterError>
+where
+    T: Sync + Send + Copy + Into<f32>,
+{
+    validate_image(img)?;
+
+    let valid = circles.min_radius >= 1.0
+        && circles.min_radius <= circles.max_radius
+        && (0.0..=1.0).contains(&circles.threshold);
+
+    if !valid {
+        return Err(FilterError::InvalidParameter(format!(
+            "Hough circles need a radius from 1 to at most the largest radius and a threshold \
+             from 0 to 1, got {} to {} and {}",
+            circles.min_radius, circles.max_radius, circles.threshold
+        )));
+    }
+
+    let (width, height) = (img.width as usize, img.height as usize);
+    let luma = match circles.sigma {
+        Some(sigma) => blur_plane(gray(img), img.width, img.height, sigma)?,
+        None => gray(img),
+    };
+    let (gx, gy) = gradient_planes(luma, img.width, img.height)?;
+    let edges = thin_edges(&gx, &gy, (width, height), circles.edge);
+
+    let min_radius = circles.min_radius.round() as usize;
+    let max_radius = circles.max_radius.round() as usize;
+
+    // Centers up to the largest radius beyond the edges, for circles that the image cuts off
+    let margin = max_radius;
+    let (columns, rows) = (width + margin * 2, height + margin * 2);
+    let accumulator = (0..columns * rows)
+        .map(|_| AtomicU32::new(0))
+        .collect::<Vec<_>>();
+
+    // Vote on both sides of each edge, for bright circles on a dark background and vice versa
+    edges.par_iter().for_each(|edge| {
+        for radius in min_radius..=max_radius {
+            for &sign in [-1.0, 1.0].iter() {
+                let distance = sign * radius as f32;
+                let cx = (edge.x + distance * edge.dx).round() as isize + margin as isize;
+                let cy = (edge.y + distance * edge.dy).round() as isize + margin as isize;
+
+                if cx >= 0 && cy >= 0 && (cx as usize) < columns && (cy as usize) < rows {
+                    accumulator[cy as usize * columns + cx as usize]
+                        .fetch_add(1, Ordering::Relaxed);
+                }
+            }
+        }
+    });
+
+    // Smooth the votes, as those of edges that spread over two radii surround the center
+    let accumulator = accumulator
+        .into_iter()
+        .map(|votes| votes.into_inner() as f32)
+        .collect();
+    let accumulator = blur_plane(accumulator, columns as u32, rows as u32, ACCUMULATOR_SIGMA)?;
+    let accumulator = &accumulator;
+    let window = circles.min_distance.max(0.0).round() as usize;
+
+    let peaks = (0..rows)
+        .into_par_iter()
+        .flat_map_iter(|y| {
+            (0..columns)
+                .filter(move |&x| {
+                    let i = y * columns + x;
+                    let votes = accumulator[i];
+
+                    if votes < MIN_VOTES {
+                        return false;
+                    }
+
+                    let range =
+                        |i: usize, len: usize| i.saturating_sub(window)..(i + window + 1).min(len);
+                    range(y, rows).all(|ny| {
+                        range(x, columns).all(|nx| {
+                            let n = ny * columns + nx;
+                            accumulator[n] < votes || (accumulator[n] == votes && n >= i)
+                        })
+                    })
+                })
+                .map(move |x| {
+                    // Centroid of the votes around the peak, for a center between pixels
+                    let (mut sum, mut sx, mut sy) = (0.0, 0.0, 0.0);
+
+                    for ny in y.saturating_sub(1)..(y + 2).min(rows) {
+                        for nx in x.saturating_sub(1)..(x + 2).min(columns) {
+                            let votes = accumulator[ny * columns + nx];
+
+                            sum += votes;
+                            sx += votes * nx as f32;
+                            sy += votes * ny as f32;
+                        }
+                    }
+
+                    (sx / sum - margin as f32, sy / sum - margin as f32)
+                })
+        })
+        .collect::<Vec<_>>();
+
+    let mut found = peaks
+        .par_iter()
+        .filter_map(|&(cx, cy)| {
+            let circle = fit_radius(&edges, (cx, cy), (min_radius, max_radius), (width, height))?;
+
+            if circle.score < circles.threshold {
+                return None;
+            }
+
+            Some(circle)
+        })
+        .collect::<Vec<_>>();
+
+    found.sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap());
+
+    // Keep the strongest of circles with nearby centers
+    let mut kept: Vec<Circle> = Vec::new();
+
+    for circle in found {
+        let distance = |other: &Circle| (circle.x - other.x).hypot(circle.y - other.y);
+
+        if kept
+            .iter()
+            .all(|other| distance(other) >= circles.min_distance)
+        {
+            kept.push(circle);
+        }
+
+        if Some(kept.len()) == circles.max_circles {
+            break;
+        }
+    }
+
+    Ok(kept)
+}
+
+/// Edge pixels with a gradient magnitude of at least `edge` that is largest along the gradient
+fn thin_edges(gx: &[f32], gy: &[f32], (width, height): (usize, usize), edge: f32) -> Vec<Edge> {
+    let magnitude = gx
+        .par_iter()
+        .zip(gy)
+        .map(|(x, y)| x.hypot(*y))
+        .collect::<Vec<_>>();
+
+    (0..width * height)
+        .into_par_iter()
+        .filter_map(|i| {
+            let m = magnitude[i];
+
+            if m == 0.0 || m < edge {
+                return None;
+            }
+
+            let (x, y) = (i % width, i / width);
+            let (dx, dy) = (gx[i] / m, gy[i] / m);
+
+            // Magnitude of the neighbour towards or away from the gradient, zero beyond the edges
+            let neighbour = |sign: f32| {
+                let nx = x as isize + (sign * dx).round() as isize;
+                let ny = y as isize + (sign * dy).round() as isize;
+
+                if nx < 0 || ny < 0 || nx as usize >= width || ny as usize >= height {
+                    0.0
+                } else {
+                    magnitude[ny as usize * width + nx as usize]
+                }
+            };
+
+            // Of two equal neighbours, keep the one further along the gradient
+            if m < neighbour(1.0) || m <= neighbour(-1.0) {
+                return None;
+            }
+
+            Some(Edge {
+                x: x as f32,
+                y: y as f32,
+                dx,
+                dy,
+            })
+        })
+        .collect()
+}
+
+/// Circle around a center at the radius with the largest share of its circumference on edges
+/// that point along the radius
+fn fit_radius(
+    edges: &[Edge],
+    (cx, cy): (f32, f32),
+    (min_radius, max_radius): (usize, usize),
+    (width, height): (usize, usize),
+) -> Option<Circle> {
+    let mut support = vec![0u32; max_radius - min_radius + 1];
+
+    for edge in edges {
+        let (ox, oy) = (edge.x - cx, edge.y - cy);
+        let distance = ox.hypot(oy);
+
+        if distance < min_radius as f32 - 0.5 || distance >= max_radius as f32 + 0.5 {
+            continue;
+        }
+
+        if (ox * edge.dx + oy * edge.dy).abs() < ALIGNMENT * distance {
+            continue;
+        }
+
+        support[(distance.round() as usize).max(min_radius) - min_radius] += 1;
+    }
+
+    // Sum each radius with its neighbours, as the edges of a circle spread over two radii
+    let (bin, count, radius) = (0..support.len())
+        .map(|bin| {
+            let bins = bin.saturating_sub(1)..(bin + 2).min(support.len());
+            let count = support[bins.clone()].iter().sum::<u32>();
+            let weighted = bins
+                .map(|b| support[b] as f32 * (b + min_radius) as f32)
+                .sum::<f32>();
+
+            (bin, count, weighted / count.max(1) as f32)
+        })
+        .max_by(|a, b| {
+            let share =
+                |(bin, count, _): &(usize, u32, f32)| *count as f32 / (bin + min_radius) as f32;
+            share(a).partial_cmp(&share(b)).unwrap()
+        })?;
+
+    if count == 0 {
+        return None;
+    }
+
+    let arc = visible_arc((cx, cy), (bin + min_radius) as f32, (width, height));
+
+    if arc < 1.0 {
+        return None;
+    }
+
+    Some(Circle {
+        x: cx,
+        y: cy,
+        radius,
+        score: (count as f32 / arc).min(1.0),
+    })
+}
+
+/// Length of the part of a circle that lies within the image
+fn visible_arc((cx, cy): (f32, f32), radius: f32, (width, height): (usize, usize)) -> f32 {
+    let circumference = 2.0 * PI * radius;
+    let steps = circumference.ceil().max(8.0) as usize;
+
+    let inside = (0..steps)
+        .filter(|&step| {
+            let (sin, cos) = (step as f32 / steps as f32 * 2.0 * PI).sin_cos();
+            let (x, y) = ((cx + radius * cos).round(), (cy + radius * sin).round());
+
+            x >= 0.0 && y >= 0.0 && x < width as f32 && y < height as f32
+        })
+        .count();
+
+    inside as f32 / steps as f32 * circumference
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hough_lines() {
+        // A horizontal line at y = 10 and a shorter vertical line at x = 5
+        let mut buf_read = (0..32 * 24)
+            .map(|i| {
+                if i / 32 == 10 || (i % 32 == 5 && i / 32 < 20) {
+                    255
+                } else {
+                    0
+                }
+            })
+            .collect::<Vec<u8>>();
+        let mut buf_write = buf_read.clone();
+        let img = Image {
+            buf_read: &mut buf_read,
+            buf_write: &mut buf_write,
+            width: 32,
+            height: 24,
+            channels: 1,
+        };
+
+        let lines = HoughLines {
+            threshold: 15,
+            ..HoughLines::default()
+        };
+
+        let found = hough_lines(&img, lines);
+        assert_eq!(found.len(), 2, "{:?}", found);
+        assert_eq!(
+            (found[0].rho, found[0].theta, found[0].votes),
+            (10.0, 90.0, 32)
+        );
+        assert_eq!(
+            (found[1].rho, found[1].theta, found[1].votes),
+            (5.0, 0.0, 20)
+        );
+
+        assert_eq!(found[0].angle(), 0.0);
+        assert_eq!(found[1].angle(), -90.0);
+        assert_eq!(found[1].segment(32, 24), Some(((5.0, 0.0), (5.0, 23.0))));
+
+        let strongest = HoughLines {
+            max_lines: Some(1),
+            ..lines
+        };
+        assert_eq!(hough_lines(&img, strongest).len(), 1);
+    }
+
+    #[test]
+    fn test_segment() {
+        // The diagonal from the top left to the bottom right of a square
+        let line = Line {
+            rho: 0.0,
+            theta: 135.0,
+            votes: 0,
+        };
+
+        let ((x1, y1), (x2, y2)) = line.segment(11, 11).unwrap();
+        assert!(x1.abs() < 1e-4 && y1.abs() < 1e-4, "{} {}", x1, y1);
+        assert!(
+            (x2 - 10.0).abs() < 1e-4 && (y2 - 10.0).abs() < 1e-4,
+            "{} {}",
+            x2,
+            y2
+        );
+
+        // A line that misses the image
+        let line = Line { rho: 50.0, ..line };
+        assert_eq!(line.segment(11, 11), None);
+    }
+
+    /// A white disk of `radius` at `center` on a black image of 64 × 48
+    fn disk((cx, cy): (f32, f32), radius: f32) -> Vec<u8> {
+        (0..64 * 48)
+            .map(|i| {
+                let (x, y) = ((i % 64) as f32 - cx, (i / 64) as f32 - cy);
+                if x.hypot(y) <= radius {
+                    255
+                } else {
+                    0
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_hough_circles() {
+        // A whole disk, and a disk that the left edge cuts off
+        for &(center, radius) in [((30.0, 20.0), 10.0), ((-4.0, 24.0), 16.0)].iter() {
+            let mut buf_read = disk(center, radius);
+            let mut buf_write = buf_read.clone();
+            let img = Image {
+                buf_read: &mut buf_read,
+                buf_write: &mut buf_write,
+                width: 64,
+                height: 48,
+                channels: 1,
+            };
+
+            let circles = HoughCircles {
+                max_radius: 20.0,
+                sigma: Some(1.0),
+                ..HoughCircles::default()
+            };
+
+            let found = hough_circles(&img, circles);
+            assert_eq!(found.len(), 1, "{:?}", found);
+
+            let circle = found[0];
+            assert!((circle.x - center.0).abs() <= 1.0, "{:?}", circle);
+            assert!((circle.y - center.1).abs() <= 1.0, "{:?}", circle);
+            assert!((circle.radius - radius).abs() <= 1.0, "{:?}", circle);
+            assert!(circle.score > 0.8, "{:?}", circle);
+        }
+    }
+
+    #[test]
+    fn test_invalid_radius() {
+        let mut buf = vec![0u8; 4];
+        let img = Image {
+            buf_read: &mut buf.clone(),
+            buf_write: &mut buf,
+            width: 2,
+            height: 2,
+            channels: 1,
+        };
+
+        let circles = HoughCircles {
+            min_radius: 10.0,
+            max_radius: 5.0,
+            ..HoughCircles::default()
+        };
+
+        assert!(matches!(
+            try_hough_circles(&img, circles),
+            Err(FilterError::InvalidParameter(_))
+        ));
+    }
+}
@@ -1,3 +1,4 @@
+use crate::FilterError;
 use ndarray::prelude::*;
 use ndarray::Array;
 use std::iter::FromIterator;
@@ -19,11 +20,9 @@ pub fn box_blur_kernel_2d(radius: usize) -> Array2<f32> {
     &kernel / kernel.sum()
 }
 
-pub fn gaussian_blur_kernel_1d(sigma: f32) -> (Array2<f32>, Array2<f32>) {
-    assert!(sigma > 0.0, "--sigma should be > 0.0");
-
+pub fn try_gaussian_blur_kernel_1d(sigma: f32) -> Result<(Array2<f32>, Array2<f32>), FilterError> {
     // Generate a 1×N Gaussian kernel
-    let radius = sigma.ceil() as i32 * 3;
+    let radius = gaussian_radius(sigma)? as i32;
     let kernel = Array::from_iter(
         (-radius..=radius).map(|x| (-(x.pow(2) as f32) / (2.0 * sigma.powi(2))).exp()),
     );
@@ -35,14 +34,12 @@ pub fn gaussian_blur_kernel_1d(sigma: f32) -> (Array2<f32>, Array2<f32>) {
 
     let kernel_y = kernel_x.clone().reversed_axes();
 
-    (kernel_x, kernel_y)
+    Ok((kernel_x, kernel_y))
 }
 
-pub fn gaussian_blur_kernel_2d(sigma: f32) -> Array2<f32> {
-    assert!(sigma > 0.0, "--sigma should be > 0.0");
-
+pub fn try_gaussian_blur_kernel_2d(sigma: f32) -> Result<Array2<f32>, FilterError> {
     // Generate an N×N Gaussian kernel
-    let radius = sigma.ceil() as i32 * 3;
+    let radius = gaussian_radius(sigma)? as i32;
     let kernel = Array::from_shape_fn(
         (radius as usize * 2 + 1, radius as usize * 2 + 1),
         |(i, j)| {
@@ -54,7 +51,27 @@ pub fn gaussian_blur_kernel_2d(sigma: f32) -> Array2<f32> {
     );
 
     // Return normalized kernel
-    &kernel / kernel.sum()
+    Ok(&kernel / kernel.sum())
+}
+
+/// Largest radius of the Gaussian kernels, which keeps a 2D kernel at about 256 MiB and sigma
+/// at most 1365
+const MAX_GAUSSIAN_RADIUS: usize = 4096;
+
+/// Radius of the Gaussian kernels, which cover three standard deviations
+///
+/// Fails for a sigma that is not a positive number, or of which the kernel would be too large
+/// to allocate.
+pub fn gaussian_radius(sigma: f32) -> Result<usize, FilterError> {
+    if !sigma.is_finite() || sigma <= 0.0 {
+        return Err(FilterError::InvalidSigma(sigma));
+    }
+
+    // Casting saturates, so a huge sigma is caught by the checks below
+    (sigma.ceil() as usize)
+        .checked_mul(3)
+        .filter(|&radius| radius <= MAX_GAUSSIAN_RADIUS)
+        .ok_or(FilterError::InvalidSigma(sigma))
 }
 
 pub fn sobel_2d() -> (Array2<f32>, Array2<f32>) {
@@ -81,27 +98,59 @@ mod tests {
     use approx::assert_relative_eq;
 
     #[test]
-    #[should_panic(expected = "sigma should be > 0.0")]
     fn invalid_zero_gaussian_kernel_1d() {
-        gaussian_blur_kernel_1d(0.0);
+        assert!(matches!(
+            try_gaussian_blur_kernel_1d(0.0),
+            Err(FilterError::InvalidSigma(_))
+        ));
     }
 
     #[test]
-    #[should_panic(expected = "sigma should be > 0.0")]
     fn invalid_negative_gaussian_kernel_1d() {
-        gaussian_blur_kernel_1d(-1.0);
+        assert!(matches!(
+            try_gaussian_blur_kernel_1d(-1.0),
+            Err(FilterError::InvalidSigma(_))
+        ));
+    }
+
+    #[test]
+    fn invalid_nan_gaussian_kernel_1d() {
+        assert!(matches!(
+            try_gaussian_blur_kernel_1d(std::f32::NAN),
+            Err(FilterError::InvalidSigma(_))
+        ));
+    }
+
+    #[test]
+    fn invalid_infinite_gaussian_kernel_1d() {
+        assert!(matches!(
+            try_gaussian_blur_kernel_1d(std::f32::INFINITY),
+            Err(FilterError::InvalidSigma(_))
+        ));
+    }
+
+    #[test]
+    fn invalid_huge_gaussian_kernel_2d() {
+        assert!(matches!(
+            try_gaussian_blur_kernel_2d(1e30),
+            Err(FilterError::InvalidSigma(_))
+        ));
     }
 
     #[test]
-    #[should_panic(expected = "sigma should be > 0.0")]
     fn invalid_zero_gaussian_kernel_2d() {
-        gaussian_blur_kernel_2d(0.0);
+        assert!(matches!(
+            try_gaussian_blur_kernel_2d(0.0),
+            Err(FilterError::InvalidSigma(_))
+        ));
     }
 
     #[test]
-    #[should_panic(expected = "sigma should be > 0.0")]
     fn invalid_negative_gaussian_kernel_2d() {
-        gaussian_blur_kernel_2d(-1.0);
+        assert!(matches!(
+            try_gaussian_blur_kernel_2d(-1.0),
+            Err(FilterError::InvalidSigma(_))
+        ));
     }
 
     #[test]
@@ -110,7 +159,11 @@ mod tests {
             0.00081721, 0.02804152, 0.23392642, 0.47442967, 0.23392642, 0.02804152, 0.00081721,
         ]];
 
-        for ((i, j), result) in gaussian_blur_kernel_1d(0.84089642).0.indexed_iter() {
+        for ((i, j), result) in try_gaussian_blur_kernel_1d(0.84089642)
+            .unwrap()
+            .0
+            .indexed_iter()
+        {
             assert_relative_eq!(expect[i][j], result, epsilon = 1e-7f32);
         }
     }
@@ -141,7 +194,10 @@ mod tests {
             ],
         ];
 
-        for ((i, j), result) in gaussian_blur_kernel_2d(0.84089642).indexed_iter() {
+        for ((i, j), result) in try_gaussian_blur_kernel_2d(0.84089642)
+            .unwrap()
+            .indexed_iter()
+        {
             assert_relative_eq!(expect[i][j], result, epsilon = 1e-7f32);
         }
     }
@@ -205,4 +261,3 @@ mod tests {
         }
     }
 }
-
@@ -1,7 +1,48 @@
 use ndarray::prelude::*;
 use rayon::prelude::*;
 
+mod backend;
+mod blob;
+mod channels;
+mod color;
+mod corner;
+mod error;
+mod histogram;
+mod hough;
 mod kernel;
+mod metrics;
+mod pyramid;
+mod resize;
+mod threshold;
+mod transform;
+
+pub use backend::{Backend, Cpu, Scalar};
+pub use blob::{
+    detect_blobs, difference_of_gaussians, dog, try_detect_blobs, try_difference_of_gaussians,
+    try_dog, Blobs, Keypoint, ScaleSpace, DOG_OFFSET,
+};
+pub use channels::{try_with_channels, Channels};
+pub use color::{convert, try_convert, try_in_color_space, ColorSpace, Luma};
+pub use corner::{detect_corners, try_detect_corners, Corner, CornerResponse, Corners};
+pub use error::FilterError;
+pub use histogram::{
+    clahe, equalize, try_clahe, try_equalize, try_histogram, try_luma_histogram, Histogram, BINS,
+};
+pub use hough::{
+    hough_circles, hough_lines, try_hough_circles, try_hough_lines, Circle, HoughCircles,
+    HoughLines, Line,
+};
+pub use kernel::gaussian_radius;
+pub use metrics::{
+    psnr, try_compare, try_difference, try_max_error, try_mse, try_ssim, ChannelMetrics, Metrics,
+};
+pub use pyramid::{
+    blend, gaussian_pyramid, laplacian_pyramid, reconstruct, try_blend, try_gaussian_pyramid,
+    try_laplacian_pyramid, try_reconstruct, Level, PYRAMID_SIGMA,
+};
+pub use resize::{resize, try_resize, Resample};
+pub use threshold::{otsu, threshold, try_threshold, Threshold};
+pub use transform::{orient, rotated_size, try_orient, try_warp, warp, Affine, Orientation};
 
 #[derive(Debug, PartialEq, Default)]
 pub struct Image<'a, T>
@@ -24,6 +65,12 @@ impl Into<u8> for Weight {
     }
 }
 
+impl From<Weight> for f32 {
+    fn from(weight: Weight) -> f32 {
+        weight.0
+    }
+}
+
 impl std::ops::AddAssign for Weight {
     fn add_assign(&mut self, other: Self) {
         *self = Self(self.0 + other.0);
@@ -34,38 +81,373 @@ pub fn box_blur_1d<T>(img: &mut Image<T>, radius: usize)
 where
     T: Sync + Send + Copy + Into<f32>,
     Weight: Into<T>,
+{
+    try_box_blur_1d(img, radius).unwrap_or_else(|err| panic!("{}", err))
+}
+
+pub fn try_box_blur_1d<T>(img: &mut Image<T>, radius: usize) -> Result<(), FilterError>
+where
+    T: Sync + Send + Copy + Into<f32>,
+    Weight: Into<T>,
+{
+    Cpu.box_blur_1d(img, radius)
+}
+
+pub fn box_blur_2d<T>(img: &mut Image<T>, radius: usize)
+where
+    T: Sync + Send + Copy + Into<f32>,
+    Weight: Into<T>,
+{
+    try_box_blur_2d(img, radius).unwrap_or_else(|err| panic!("{}", err))
+}
+
+pub fn try_box_blur_2d<T>(img: &mut Image<T>, radius: usize) -> Result<(), FilterError>
+where
+    T: Sync + Send + Copy + Into<f32>,
+    Weight: Into<T>,
+{
+    Cpu.box_blur_2d(img, radius)
+}
+
+pub async fn box_blur_1d_gpu<'a, T>(image: &mut Image<'a, T>, radius: usize)
+where
+    T: Sync + Send + Copy + Into<f32> + bytemuck::Pod,
+    Weight: Into<T>,
+{
+    try_box_blur_1d_gpu(image, radius)
+        .await
+        .unwrap_or_else(|err| panic!("{}", err))
+}
+
+pub async fn try_box_blur_1d_gpu<'a, T>(
+    image: &mut Image<'a, T>,
+    radius: usize,
+) -> Result<(), FilterError>
+where
+    T: Sync + Send + Copy + Into<f32> + bytemuck::Pod,
+    Weight: Into<T>,
 {
     let (kernel_x, kernel_y) = kernel::box_blur_kernel_1d(radius);
 
-    // Blur along the x-axis
-    convolve(img, &kernel_x);
+    try_convolve_separable_gpu(image, &kernel_x, &kernel_y).await
+}
 
-    // Use the previous buffer as source for the second pass
-    img.buf_read.copy_from_slice(img.buf_write);
+pub fn gaussian_blur_1d<T>(img: &mut Image<T>, sigma: f32)
+where
+    T: Sync + Send + Copy + Into<f32>,
+    Weight: Into<T>,
+{
+    try_gaussian_blur_1d(img, sigma).unwrap_or_else(|err| panic!("{}", err))
+}
 
-    // Blur along the y-axis
-    convolve(img, &kernel_y)
+pub fn try_gaussian_blur_1d<T>(img: &mut Image<T>, sigma: f32) -> Result<(), FilterError>
+where
+    T: Sync + Send + Copy + Into<f32>,
+    Weight: Into<T>,
+{
+    Cpu.gaussian_blur_1d(img, sigma)
 }
 
-pub fn box_blur_2d<T>(img: &mut Image<T>, radius: usize)
+pub async fn gaussian_blur_1d_gpu<'a, T>(image: &mut Image<'a, T>, sigma: f32)
+where
+    T: Sync + Send + Copy + Into<f32> + bytemuck::Pod,
+    Weight: Into<T>,
+{
+    try_gaussian_blur_1d_gpu(image, sigma)
+        .await
+        .unwrap_or_else(|err| panic!("{}", err))
+}
+
+pub async fn try_gaussian_blur_1d_gpu<'a, T>(
+    image: &mut Image<'a, T>,
+    sigma: f32,
+) -> Result<(), FilterError>
+where
+    T: Sync + Send + Copy + Into<f32> + bytemuck::Pod,
+    Weight: Into<T>,
+{
+    let (kernel_x, kernel_y) = kernel::try_gaussian_blur_kernel_1d(sigma)?;
+
+    try_convolve_separable_gpu(image, &kernel_x, &kernel_y).await
+}
+
+pub fn gaussian_blur_2d<T>(img: &mut Image<T>, sigma: f32)
 where
     T: Sync + Send + Copy + Into<f32>,
     Weight: Into<T>,
 {
-    let kernel = kernel::box_blur_kernel_2d(radius);
+    try_gaussian_blur_2d(img, sigma).unwrap_or_else(|err| panic!("{}", err))
+}
 
-    convolve(img, &kernel);
+pub fn try_gaussian_blur_2d<T>(img: &mut Image<T>, sigma: f32) -> Result<(), FilterError>
+where
+    T: Sync + Send + Copy + Into<f32>,
+    Weight: Into<T>,
+{
+    Cpu.gaussian_blur_2d(img, sigma)
 }
 
-pub async fn box_blur_1d_gpu<'a, T>(image: &mut Image<'a, T>, radius: usize)
+pub fn sobel2d<T>(img: &mut Image<T>, sigma: Option<f32>)
+where
+    T: Sync + Send + Copy + Into<f32>,
+    Weight: Into<T>,
+{
+    try_sobel2d(img, sigma).unwrap_or_else(|err| panic!("{}", err))
+}
+
+pub fn try_sobel2d<T>(img: &mut Image<T>, sigma: Option<f32>) -> Result<(), FilterError>
+where
+    T: Sync + Send + Copy + Into<f32>,
+    Weight: Into<T>,
+{
+    Cpu.sobel2d(img, sigma, Some(Luma::default()))
+}
+
+pub fn sobel2d_luma<T>(img: &mut Image<T>, sigma: Option<f32>, luma: Luma)
+where
+    T: Sync + Send + Copy + Into<f32>,
+    Weight: Into<T>,
+{
+    try_sobel2d_luma(img, sigma, luma).unwrap_or_else(|err| panic!("{}", err))
+}
+
+/// Sobel on the luma of each pixel with the weights of `luma`, `try_sobel2d` uses BT.601
+pub fn try_sobel2d_luma<T>(
+    img: &mut Image<T>,
+    sigma: Option<f32>,
+    luma: Luma,
+) -> Result<(), FilterError>
+where
+    T: Sync + Send + Copy + Into<f32>,
+    Weight: Into<T>,
+{
+    Cpu.sobel2d(img, sigma, Some(luma))
+}
+
+pub fn sobel2d_channels<T>(img: &mut Image<T>, sigma: Option<f32>, channels: Channels)
+where
+    T: Sync + Send + Copy + Into<f32>,
+    Weight: Into<T>,
+{
+    try_sobel2d_channels(img, sigma, channels).unwrap_or_else(|err| panic!("{}", err))
+}
+
+/// Sobel on each of the selected channels on its own, without the conversion to luma
+pub fn try_sobel2d_channels<T>(
+    img: &mut Image<T>,
+    sigma: Option<f32>,
+    channels: Channels,
+) -> Result<(), FilterError>
+where
+    T: Sync + Send + Copy + Into<f32>,
+    Weight: Into<T>,
+{
+    try_with_channels(img, channels, |img| Cpu.sobel2d(img, sigma, None))
+}
+
+/// Check that both buffers hold `width * height * channels` elements and that the
+/// number of channels fits the weighted sum used by `convolve`
+fn validate_image<T>(img: &Image<T>) -> Result<(), FilterError>
+where
+    T: Sync + Send + Copy + Into<f32>,
+{
+    if img.channels == 0 || img.channels > 4 {
+        return Err(FilterError::UnsupportedChannels(img.channels));
+    }
+
+    let expected = img.width as usize * img.height as usize * img.channels;
+
+    for actual in [img.buf_read.len(), img.buf_write.len()].iter().copied() {
+        if actual != expected {
+            return Err(FilterError::BufferSize { expected, actual });
+        }
+    }
+
+    Ok(())
+}
+
+/// Number of color channels, the last channel of gray-alpha and RGBA is alpha
+fn color_channels(channels: usize) -> usize {
+    match channels {
+        2 | 4 => channels - 1,
+        _ => channels,
+    }
+}
+
+/// Luma of each pixel of `buf_read`, or its first channel for gray images
+fn gray<T>(img: &Image<T>) -> Vec<f32>
+where
+    T: Sync + Send + Copy + Into<f32>,
+{
+    img.buf_read
+        .par_chunks(img.channels)
+        .map(|p| match *p {
+            [r, g, b, ..] => Luma::Bt601.luma(r.into(), g.into(), b.into()),
+            _ => p[0].into(),
+        })
+        .collect()
+}
+
+/// Gaussian blur of a single channel of `width` × `height` values
+fn blur_plane(
+    mut buf_read: Vec<f32>,
+    width: u32,
+    height: u32,
+    sigma: f32,
+) -> Result<Vec<f32>, FilterError> {
+    let mut buf_write = vec![0.0; buf_read.len()];
+
+    Cpu.gaussian_blur_1d(
+        &mut Image {
+            buf_read: &mut buf_read,
+            buf_write: &mut buf_write,
+            width,
+            height,
+            channels: 1,
+        },
+        sigma,
+    )?;
+
+    Ok(buf_write)
+}
+
+/// Sobel gradients of a single channel of `width` × `height` values, of which the gradient along
+/// the y-axis points down
+fn gradient_planes(
+    mut buf_read: Vec<f32>,
+    width: u32,
+    height: u32,
+) -> Result<(Vec<f32>, Vec<f32>), FilterError> {
+    let (kernel_x, kernel_y) = kernel::sobel_2d();
+    let mut gx = vec![0.0; buf_read.len()];
+    let mut gy = vec![0.0; buf_read.len()];
+    let mut img = Image {
+        buf_read: &mut buf_read,
+        buf_write: &mut gx,
+        width,
+        height,
+        channels: 1,
+    };
+
+    Cpu.convolve(&mut img, &kernel_x)?;
+    Cpu.convolve(
+        &mut Image {
+            buf_write: &mut gy,
+            ..img
+        },
+        &kernel_y,
+    )?;
+
+    // The kernel along the y-axis subtracts the row below from the row above
+    gy.par_iter_mut().for_each(|v| *v = -*v);
+
+    Ok((gx, gy))
+}
+
+/// Check that a buffer holds whole pixels of a supported number of channels
+fn validate_buffer(buf: &[u8], channels: usize) -> Result<(), FilterError> {
+    if channels == 0 || channels > 4 {
+        return Err(FilterError::UnsupportedChannels(channels));
+    }
+
+    if buf.len() % channels != 0 {
+        return Err(FilterError::BufferSize {
+            expected: buf.len() - buf.len() % channels,
+            actual: buf.len(),
+        });
+    }
+
+    Ok(())
+}
+
+pub fn convolve<T>(img: &mut Image<T>, kernel: &Array2<f32>)
+where
+    T: Sync + Send + Copy + Into<f32>,
+    Weight: Into<T>,
+{
+    try_convolve(img, kernel).unwrap_or_else(|err| panic!("{}", err))
+}
+
+pub fn try_convolve<T>(img: &mut Image<T>, kernel: &Array2<f32>) -> Result<(), FilterError>
+where
+    T: Sync + Send + Copy + Into<f32>,
+    Weight: Into<T>,
+{
+    Cpu.convolve(img, kernel)
+}
+
+pub async fn convolve_gpu<'a, T>(image: &mut Image<'a, T>, kernel: &Array2<f32>)
+where
+    T: Sync + Send + Copy + Into<f32> + bytemuck::Pod,
+    Weight: Into<T>,
+{
+    try_convolve_gpu(image, kernel)
+        .await
+        .unwrap_or_else(|err| panic!("{}", err))
+}
+
+/// Convolve `buf_read` into `buf_write` on the GPU, the image is bound as an RGBA texture
+/// with 8 bits per channel
+pub async fn try_convolve_gpu<'a, T>(
+    image: &mut Image<'a, T>,
+    kernel: &Array2<f32>,
+) -> Result<(), FilterError>
+where
+    T: Sync + Send + Copy + Into<f32> + bytemuck::Pod,
+    Weight: Into<T>,
+{
+    validate_gpu(image, kernel)?;
+
+    let (device, queue) = request_device().await?;
+
+    convolve_texture(&device, &queue, image, kernel).await
+}
+
+/// Convolve along the x-axis and then along the y-axis on the same device
+pub async fn try_convolve_separable_gpu<'a, T>(
+    image: &mut Image<'a, T>,
+    kernel_x: &Array2<f32>,
+    kernel_y: &Array2<f32>,
+) -> Result<(), FilterError>
 where
-    T: Sync + Send + Copy + Into<f32> + bytemuck::Pod + std::fmt::Debug,
+    T: Sync + Send + Copy + Into<f32> + bytemuck::Pod,
     Weight: Into<T>,
 {
-    let kernel = kernel::box_blur_kernel_2d(radius);
-    let kernel_slice = kernel.iter().map(|x| *x as f32).collect::<Vec<_>>();
+    validate_gpu(image, kernel_x)?;
+    validate_gpu(image, kernel_y)?;
+
+    let (device, queue) = request_device().await?;
+
+    convolve_texture(&device, &queue, image, kernel_x).await?;
+
+    // Use the previous buffer as source for the second pass
+    image.buf_read.copy_from_slice(image.buf_write);
+
+    convolve_texture(&device, &queue, image, kernel_y).await
+}
+
+fn validate_gpu<T>(image: &Image<T>, kernel: &Array2<f32>) -> Result<(), FilterError>
+where
+    T: Sync + Send + Copy + Into<f32>,
+{
+    validate_image(image)?;
+
+    // The texture is bound as RGBA with 8 bits per channel
+    if image.channels != 4 || std::mem::size_of::<T>() != 1 {
+        return Err(FilterError::UnsupportedChannels(image.channels));
+    }
+
+    let (rows, cols) = kernel.dim();
+
+    if rows % 2 == 0 || cols % 2 == 0 {
+        return Err(FilterError::InvalidKernel { rows, cols });
+    }
+
+    Ok(())
+}
 
-    // Create a handle to the graphics/compute device
+/// Create a handle to the graphics/compute device
+async fn request_device() -> Result<(wgpu::Device, wgpu::Queue), FilterError> {
     let adapter = wgpu::Instance::new()
         .request_adapter(
             &wgpu::RequestAdapterOptions {
@@ -76,9 +458,9 @@ where
             wgpu::BackendBit::PRIMARY,
         )
         .await
-        .unwrap();
+        .ok_or(FilterError::AdapterUnavailable)?;
 
-    let (device, queue) = adapter
+    adapter
         .request_device(
             &wgpu::DeviceDescriptor {
                 extensions: wgpu::Extensions::empty(),
@@ -88,9 +470,44 @@ where
             None,
         )
         .await
-        .unwrap();
+        .map_err(|err| FilterError::DeviceRequest(format!("{:?}", err)))
+}
 
-    let image_bytes = bytemuck::cast_slice(image.buf_read.as_ref());
+/// Run a single pass of `convolve.comp` from `buf_read` into `buf_write`
+async fn convolve_texture<'a, T>(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    image: &mut Image<'a, T>,
+    kernel: &Array2<f32>,
+) -> Result<(), FilterError>
+where
+    T: Sync + Send + Copy + Into<f32> + bytemuck::Pod,
+{
+    // Rows copied from a texture to a buffer are padded to this number of bytes
+    const COPY_BYTES_PER_ROW_ALIGNMENT: u32 = 256;
+    // This should match LOCAL_SIZE defined in the compute shader
+    const LOCAL_SIZE: u32 = 32;
+
+    let bytes_per_row = image.width * 4;
+    let padded_bytes_per_row = (bytes_per_row + COPY_BYTES_PER_ROW_ALIGNMENT - 1)
+        / COPY_BYTES_PER_ROW_ALIGNMENT
+        * COPY_BYTES_PER_ROW_ALIGNMENT;
+
+    // The shader expects the weights in row-major order
+    let weights = kernel.iter().copied().collect::<Vec<f32>>();
+
+    #[rustfmt::skip]
+    let params = [
+        kernel.ncols() as i32 / 2, kernel.nrows() as i32 / 2,
+        image.width as i32, image.height as i32,
+    ];
+
+    let storage_texture = |readonly: bool| wgpu::BindingType::StorageTexture {
+        dimension: wgpu::TextureViewDimension::D2,
+        component_type: wgpu::TextureComponentType::Float,
+        format: wgpu::TextureFormat::Rgba8Unorm,
+        readonly,
+    };
 
     // Create pipeline layout
     let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
@@ -105,18 +522,22 @@ where
             wgpu::BindGroupLayoutEntry {
                 binding: 1,
                 visibility: wgpu::ShaderStage::COMPUTE,
-                ty: wgpu::BindingType::UniformBuffer { dynamic: false },
+                ty: wgpu::BindingType::StorageBuffer {
+                    dynamic: false,
+                    readonly: true,
+                },
                 ..Default::default()
             },
             wgpu::BindGroupLayoutEntry {
                 binding: 2,
                 visibility: wgpu::ShaderStage::COMPUTE,
-                ty: wgpu::BindingType::StorageTexture {
-                    dimension: wgpu::TextureViewDimension::D2,
-                    component_type: wgpu::TextureComponentType::Float,
-                    format: wgpu::TextureFormat::Rgba8UnormSrgb,
-                    readonly: false,
-                },
+                ty: storage_texture(true),
+                ..Default::default()
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 3,
+                visibility: wgpu::ShaderStage::COMPUTE,
+                ty: storage_texture(false),
                 ..Default::default()
             },
         ],
@@ -127,47 +548,47 @@ where
     });
 
     // Create the kernel buffers
-    let kernel_radius_buffer = device.create_buffer_with_data(
-        bytemuck::cast_slice(&[kernel.ncols() as i32 / 2]),
-        wgpu::BufferUsage::UNIFORM,
-    );
+    let params_buffer =
+        device.create_buffer_with_data(bytemuck::cast_slice(&params), wgpu::BufferUsage::UNIFORM);
 
-    let kernel_weights_buffer = device.create_buffer_with_data(
-        bytemuck::cast_slice(&kernel_slice),
-        wgpu::BufferUsage::UNIFORM,
-    );
+    let weights_buffer =
+        device.create_buffer_with_data(bytemuck::cast_slice(&weights), wgpu::BufferUsage::STORAGE);
 
-    // Create the texture
+    // Create separate input and output textures, so no pixel is read after it was written
     let texture_extent = wgpu::Extent3d {
         width: image.width,
         height: image.height,
         depth: 1,
     };
 
-    let texture = device.create_texture(&wgpu::TextureDescriptor {
-        label: None,
-        size: texture_extent,
-        mip_level_count: 1,
-        sample_count: 1,
-        dimension: wgpu::TextureDimension::D2,
-        format: wgpu::TextureFormat::Rgba8Unorm,
-        usage: wgpu::TextureUsage::COPY_DST
-            | wgpu::TextureUsage::COPY_SRC
-            | wgpu::TextureUsage::STORAGE,
-    });
+    let create_texture = |usage: wgpu::TextureUsage| {
+        device.create_texture(&wgpu::TextureDescriptor {
+            label: None,
+            size: texture_extent,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba8Unorm,
+            usage: wgpu::TextureUsage::STORAGE | usage,
+        })
+    };
+
+    let input_texture = create_texture(wgpu::TextureUsage::COPY_DST);
+    let output_texture = create_texture(wgpu::TextureUsage::COPY_SRC);
 
-    let texture_view = texture.create_default_view();
+    let input_view = input_texture.create_default_view();
+    let output_view = output_texture.create_default_view();
 
     queue.write_texture(
         wgpu::TextureCopyView {
-            texture: &texture,
+            texture: &input_texture,
             mip_level: 0,
             origin: wgpu::Origin3d::ZERO,
         },
-        &image_bytes,
+        bytemuck::cast_slice(image.buf_read.as_ref()),
         wgpu::TextureDataLayout {
             offset: 0,
-            bytes_per_row: image.width * 4,
+            bytes_per_row,
             rows_per_image: 0,
         },
         texture_extent,
@@ -180,24 +601,28 @@ where
         bindings: &[
             wgpu::Binding {
                 binding: 0,
-                resource: wgpu::BindingResource::Buffer(kernel_radius_buffer.slice(..)),
+                resource: wgpu::BindingResource::Buffer(params_buffer.slice(..)),
             },
             wgpu::Binding {
                 binding: 1,
-                resource: wgpu::BindingResource::Buffer(kernel_weights_buffer.slice(..)),
+                resource: wgpu::BindingResource::Buffer(weights_buffer.slice(..)),
             },
             wgpu::Binding {
                 binding: 2,
-                resource: wgpu::BindingResource::TextureView(&texture_view),
+                resource: wgpu::BindingResource::TextureView(&input_view),
+            },
+            wgpu::Binding {
+                binding: 3,
+                resource: wgpu::BindingResource::TextureView(&output_view),
             },
         ],
     });
 
     // Load compute shader
     let compute_shader = include_bytes!("shaders/convolve.comp.spv");
-    let compute_module = device.create_shader_module(
-        &wgpu::read_spirv(std::io::Cursor::new(&compute_shader[..])).unwrap(),
-    );
+    let compute_spirv = wgpu::read_spirv(std::io::Cursor::new(&compute_shader[..]))
+        .map_err(|err| FilterError::Shader(err.to_string()))?;
+    let compute_module = device.create_shader_module(&compute_spirv);
 
     // Create the compute pipeline
     let compute_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
@@ -216,11 +641,11 @@ where
         cpass.set_pipeline(&compute_pipeline);
         // Assign bind group to set 0
         cpass.set_bind_group(0, &bind_group, &[]);
-        // Assign a multiple of local_size workgroups to the texture
-        let local_size = 32;
+        // Assign a multiple of local_size workgroups to the texture, the shader skips
+        // invocations outside of the image
         cpass.dispatch(
-            (image.width + local_size - 1) / local_size,
-            (image.height + local_size - 1) / local_size,
+            (image.width + LOCAL_SIZE - 1) / LOCAL_SIZE,
+            (image.height + LOCAL_SIZE - 1) / LOCAL_SIZE,
             1,
         );
     }
@@ -228,14 +653,14 @@ where
     // Create buffer to write the computed result to
     let texture_output_buffer = device.create_buffer(&wgpu::BufferDescriptor {
         label: None,
-        size: (4 * 768 * image.height) as wgpu::BufferAddress,
+        size: (padded_bytes_per_row * image.height) as wgpu::BufferAddress,
         usage: wgpu::BufferUsage::MAP_READ | wgpu::BufferUsage::COPY_DST,
         mapped_at_creation: false,
     });
 
     encoder.copy_texture_to_buffer(
         wgpu::TextureCopyView {
-            texture: &texture,
+            texture: &output_texture,
             mip_level: 0,
             origin: wgpu::Origin3d::ZERO,
         },
@@ -243,7 +668,7 @@ where
             buffer: &texture_output_buffer,
             layout: wgpu::TextureDataLayout {
                 offset: 0,
-                bytes_per_row: 768 * std::mem::size_of::<u32>() as u32,
+                bytes_per_row: padded_bytes_per_row,
                 rows_per_image: image.height,
             },
         },
@@ -261,167 +686,28 @@ where
     // be called in an event loop or on another thread.
     device.poll(wgpu::Maintain::Wait);
 
-    if let Ok(()) = texture_output_future.await {
-        let data = texture_output_slice.get_mapped_range();
-
-        // TODO: Handle arbitrary image width and height
-        for row in 0..720 {
-            let bytes = bytemuck::cast_slice(&data[row * 768 * 4..row * 768 * 4 + 720 * 4]);
-            // let bytes = bytemuck::cast_slice(&example[row * 720 * 4..row * 720 * 4 + 720 * 4]);
-            image.buf_write[row * 720 * 4..row * 720 * 4 + 720 * 4].copy_from_slice(bytes);
-        }
-
-        drop(data);
-        texture_output_buffer.unmap();
-    } else {
-        panic!("failed to run compute on gpu!")
-    }
-}
-
-pub fn gaussian_blur_1d<T>(img: &mut Image<T>, sigma: f32)
-where
-    T: Sync + Send + Copy + Into<f32>,
-    Weight: Into<T>,
-{
-    let (kernel_x, kernel_y) = kernel::gaussian_blur_kernel_1d(sigma);
-
-    // Blur along the x-axis
-    convolve(img, &kernel_x);
-
-    // Use the previous buffer as source for the second pass
-    img.buf_read.copy_from_slice(img.buf_write);
-
-    // Blur along the y-axis
-    convolve(img, &kernel_y)
-}
-
-pub fn gaussian_blur_2d<T>(img: &mut Image<T>, sigma: f32)
-where
-    T: Sync + Send + Copy + Into<f32>,
-    Weight: Into<T>,
-{
-    let kernel = kernel::gaussian_blur_kernel_2d(sigma);
-
-    convolve(img, &kernel);
-}
+    texture_output_future
+        .await
+        .map_err(|_| FilterError::BufferMap)?;
 
-pub fn sobel2d<T>(img: &mut Image<T>, sigma: Option<f32>)
-where
-    T: Sync + Send + Copy + Into<f32>,
-    Weight: Into<T>,
-{
-    // Apply Gaussian blur if -s / --sigma is passed
-    if let Some(sigma) = sigma {
-        gaussian_blur_1d(img, sigma);
+    let data = texture_output_slice.get_mapped_range();
 
-        // Write the result to the read buffer for the second pass
-        img.buf_read.copy_from_slice(img.buf_write);
+    // Strip the padding from each row
+    for (row, pixels) in image
+        .buf_write
+        .chunks_exact_mut(bytes_per_row as usize)
+        .enumerate()
+    {
+        let start = row * padded_bytes_per_row as usize;
+        pixels.copy_from_slice(bytemuck::cast_slice(
+            &data[start..start + bytes_per_row as usize],
+        ));
     }
 
-    let (kernel_x, kernel_y) = kernel::sobel_2d();
-
-    // Change color to Luma
-    // See: https://www.wikiwand.com/en/Grayscale#/Luma_coding_in_video_systems
-    // FIXME: Allow for custom channels, use a sane default of 3 (RGB)
-    img.buf_read.par_chunks_mut(img.channels).for_each(|p| {
-        #[rustfmt::skip]
-        let y = Weight(
-            0.299 * p[0].into() + // R
-            0.587 * p[1].into() + // G
-            0.114 * p[2].into()   // B
-        );
-
-        p[0] = y.into();
-        p[1] = y.into();
-        p[2] = y.into();
-    });
-
-    // Find the gradient along the x-axis
-    convolve(img, &kernel_x);
-
-    // Create an extra buffer, as one is required for each gradient
-    let mut tmp = img.buf_read.to_vec();
+    drop(data);
+    texture_output_buffer.unmap();
 
-    // Find the gradient along the y-axis
-    convolve(
-        &mut Image {
-            buf_read: &mut img.buf_read,
-            buf_write: &mut tmp,
-            ..*img
-        },
-        &kernel_y,
-    );
-
-    let Image { channels, .. } = *img;
-    // FIXME: Allow for custom channels, use a sane default of 3 (RGB)
-    // let channels = 3;
-
-    // Apply Pythagorean theorem to both buffers for the gradient magnitude
-    img.buf_write
-        .par_chunks_mut(channels)
-        .zip(tmp.par_chunks(channels))
-        .for_each(|(gx, gy)| {
-            for c in 0..channels {
-                gx[c] = Weight(((gx[c].into()).powi(2) + (gy[c].into()).powi(2)).sqrt()).into();
-            }
-        });
-}
-
-pub fn convolve<T>(img: &mut Image<T>, kernel: &Array2<f32>)
-where
-    T: Sync + Send + Copy + Into<f32>,
-    Weight: Into<T>,
-{
-    let rows_half = kernel.nrows() as isize / 2;
-    let cols_half = kernel.ncols() as isize / 2;
-
-    let Image {
-        ref buf_read,
-        width,
-        height,
-        channels,
-        ..
-    } = *img;
-
-    img.buf_write
-        // Process one row of pixels for each thread
-        .par_chunks_exact_mut(width as usize * channels)
-        .enumerate()
-        .for_each(|(y, pixels)| {
-            // Save on instantiation by only cloning the initialized array
-            let weighted_sum = [Weight(0.0); 4];
-
-            pixels
-                .par_chunks_exact_mut(channels)
-                .map(|pixel| (pixel, weighted_sum.clone()))
-                .enumerate()
-                .for_each(|(x, (pixel, mut weighted_sum))| {
-                    for ((i, j), kernel_element) in kernel.indexed_iter() {
-                        // Clamp kernel to image bounds
-                        let edge_x = (x as isize + (j as isize - cols_half))
-                            .min(width as isize - 1)
-                            .max(0) as usize;
-                        let edge_y = (y as isize + (i as isize - rows_half))
-                            .min(height as isize - 1)
-                            .max(0) as usize;
-
-                        // Get pixel x- and y-coordinate
-                        let p_x = edge_x * channels;
-                        let p_y = edge_y * channels * width as usize;
-
-                        // Get pixel channels as a slice
-                        let pixel = buf_read.get((p_x + p_y)..(p_x + p_y) + channels).unwrap();
-
-                        for (weight, &channel) in weighted_sum.iter_mut().zip(pixel) {
-                            *weight += Weight(channel.into() * kernel_element);
-                        }
-                    }
-
-                    for (channel, &weight) in pixel.iter_mut().zip(&weighted_sum) {
-                        *channel = weight.into();
-                    }
-                });
-        });
+    Ok(())
 }
 
 #[cfg(test)]
@@ -505,6 +791,102 @@ mod tests {
         ]);
     }
 
+    #[test]
+    fn test_try_convolve_buffer_size() {
+        let mut buf_read = vec![0u8; 8];
+        let mut buf_write = vec![0u8; 9];
+
+        let mut image = Image {
+            buf_read: &mut buf_read,
+            buf_write: &mut buf_write,
+            width: 3,
+            height: 1,
+            channels: 3,
+        };
+
+        assert_eq!(
+            try_box_blur_2d(&mut image, 1),
+            Err(FilterError::BufferSize {
+                expected: 9,
+                actual: 8
+            })
+        );
+    }
+
+    #[test]
+    fn test_try_convolve_invalid_kernel() {
+        let mut buf_read = vec![0u8; 9];
+        let mut buf_write = vec![0u8; 9];
+
+        let mut image = Image {
+            buf_read: &mut buf_read,
+            buf_write: &mut buf_write,
+            width: 3,
+            height: 1,
+            channels: 3,
+        };
+
+        assert_eq!(
+            try_convolve(&mut image, &Array2::ones((2, 2))),
+            Err(FilterError::InvalidKernel { rows: 2, cols: 2 })
+        );
+    }
+
+    #[test]
+    fn test_try_sobel2d_unsupported_channels() {
+        let mut buf_read = vec![0u8; 2];
+        let mut buf_write = vec![0u8; 2];
+
+        let mut image = Image {
+            buf_read: &mut buf_read,
+            buf_write: &mut buf_write,
+            width: 1,
+            height: 1,
+            channels: 2,
+        };
+
+        assert_eq!(
+            try_sobel2d(&mut image, None),
+            Err(FilterError::UnsupportedChannels(2))
+        );
+    }
+
+    #[test]
+    fn test_try_gaussian_blur_invalid_sigma() {
+        let mut buf_read = vec![0u8; 3];
+        let mut buf_write = vec![0u8; 3];
+
+        let mut image = Image {
+            buf_read: &mut buf_read,
+            buf_write: &mut buf_write,
+            width: 1,
+            height: 1,
+            channels: 3,
+        };
+
+        assert_eq!(
+            try_gaussian_blur_1d(&mut image, 0.0),
+            Err(FilterError::InvalidSigma(0.0))
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "sigma should be > 0.0")]
+    fn test_gaussian_blur_invalid_sigma_panics() {
+        let mut buf_read = vec![0u8; 3];
+        let mut buf_write = vec![0u8; 3];
+
+        let mut image = Image {
+            buf_read: &mut buf_read,
+            buf_write: &mut buf_write,
+            width: 1,
+            height: 1,
+            channels: 3,
+        };
+
+        gaussian_blur_2d(&mut image, -1.0);
+    }
+
     #[test]
     fn test_image_default() {
         let actual = Image::<u8>::default();
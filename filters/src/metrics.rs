@@ -0,0 +1,291 @@
+use crate::{kernel, Backend, Cpu, FilterError, Image};
+
+/// Largest value of an 8-bit channel
+const PEAK: f64 = 255.0;
+
+/// Standard deviation of the Gaussian window that SSIM compares local statistics in
+const SSIM_SIGMA: f32 = 1.5;
+
+/// Differences between two images in a single channel
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChannelMetrics {
+    /// Mean squared error
+    pub mse: f64,
+    /// Peak signal-to-noise ratio in dB, infinite for identical channels
+    pub psnr: f64,
+    /// Mean structural similarity, 1.0 for identical channels
+    pub ssim: f64,
+    /// Largest absolute difference of a single value
+    pub max_error: u8,
+}
+
+/// Differences between two images, for each channel and over all channels
+#[derive(Debug, Clone, PartialEq)]
+pub struct Metrics {
+    pub channels: Vec<ChannelMetrics>,
+    pub mse: f64,
+    pub psnr: f64,
+    pub ssim: f64,
+    pub max_error: u8,
+}
+
+/// Compare an image with a reference of the same size and number of channels
+pub fn try_compare(
+    expect: &[u8],
+    actual: &[u8],
+    width: u32,
+    height: u32,
+    channels: usize,
+) -> Result<Metrics, FilterError> {
+    let mse = try_mse(expect, actual, width, height, channels)?;
+    let ssim = try_ssim(expect, actual, width, height, channels)?;
+    let max_error = try_max_error(expect, actual, width, height, channels)?;
+
+    let mean = |values: &[f64]| values.iter().sum::<f64>() / channels as f64;
+
+    Ok(Metrics {
+        channels: (0..channels)
+            .map(|c| ChannelMetrics {
+                mse: mse[c],
+                psnr: psnr(mse[c]),
+                ssim: ssim[c],
+                max_error: max_error[c],
+            })
+            .collect(),
+        mse: mean(&mse),
+        psnr: psnr(mean(&mse)),
+        ssim: mean(&ssim),
+        max_error: max_error.iter().copied().max().unwrap_or(0),
+    })
+}
+
+/// Mean squared error of each channel
+pub fn try_mse(
+    expect: &[u8],
+    actual: &[u8],
+    width: u32,
+    height: u32,
+    channels: usize,
+) -> Result<Vec<f64>, FilterError> {
+    validate(expect, actual, width, height, channels)?;
+
+    let mut sums = vec![0.0; channels];
+
+    for (e, a) in expect
+        .chunks_exact(channels)
+        .zip(actual.chunks_exact(channels))
+    {
+        for (sum, (&e, &a)) in sums.iter_mut().zip(e.iter().zip(a)) {
+            *sum += (e as f64 - a as f64).powi(2);
+        }
+    }
+
+    let pixels = width as f64 * height as f64;
+
+    Ok(sums.into_iter().map(|sum| sum / pixels).collect())
+}
+
+/// Peak signal-to-noise ratio in dB of a mean squared error
+pub fn psnr(mse: f64) -> f64 {
+    if mse == 0.0 {
+        return f64::INFINITY;
+    }
+
+    10.0 * (PEAK * PEAK / mse).log10()
+}
+
+/// Mean structural similarity of each channel, with local statistics weighted by a Gaussian
+/// window
+pub fn try_ssim(
+    expect: &[u8],
+    actual: &[u8],
+    width: u32,
+    height: u32,
+    channels: usize,
+) -> Result<Vec<f64>, FilterError> {
+    validate(expect, actual, width, height, channels)?;
+
+    let window = kernel::try_gaussian_blur_kernel_2d(SSIM_SIGMA)?;
+
+    // Stabilize the division for flat areas
+    let c1 = (0.01 * PEAK as f32).powi(2);
+    let c2 = (0.03 * PEAK as f32).powi(2);
+
+    let x = expect.iter().map(|&v| v as f32).collect::<Vec<_>>();
+    let y = actual.iter().map(|&v| v as f32).collect::<Vec<_>>();
+
+    // Weighted mean of a buffer within the window around each value
+    let local_mean = |mut buf_read: Vec<f32>| -> Result<Vec<f32>, FilterError> {
+        let mut buf_write = vec![0.0; buf_read.len()];
+
+        Cpu.convolve(
+            &mut Image {
+                buf_read: &mut buf_read,
+                buf_write: &mut buf_write,
+                width,
+                height,
+                channels,
+            },
+            &window,
+        )?;
+
+        Ok(buf_write)
+    };
+
+    let mean_x = local_mean(x.clone())?;
+    let mean_y = local_mean(y.clone())?;
+    let mean_xx = local_mean(x.iter().map(|v| v * v).collect())?;
+    let mean_yy = local_mean(y.iter().map(|v| v * v).collect())?;
+    let mean_xy = local_mean(x.iter().zip(&y).map(|(a, b)| a * b).collect())?;
+
+    let mut sums = vec![0.0; channels];
+
+    for i in 0..x.len() {
+        let (mx, my) = (mean_x[i], mean_y[i]);
+
+        let var_x = mean_xx[i] - mx * mx;
+        let var_y = mean_yy[i] - my * my;
+        let cov = mean_xy[i] - mx * my;
+
+        let ssim = ((2.0 * mx * my + c1) * (2.0 * cov + c2))
+            / ((mx * mx + my * my + c1) * (var_x + var_y + c2));
+
+        sums[i % channels] += ssim as f64;
+    }
+
+    let pixels = width as f64 * height as f64;
+
+    Ok(sums.into_iter().map(|sum| sum / pixels).collect())
+}
+
+/// Largest absolute difference of each channel
+pub fn try_max_error(
+    expect: &[u8],
+    actual: &[u8],
+    width: u32,
+    height: u32,
+    channels: usize,
+) -> Result<Vec<u8>, FilterError> {
+    validate(expect, actual, width, height, channels)?;
+
+    let mut max = vec![0; channels];
+
+    for (e, a) in expect
+        .chunks_exact(channels)
+        .zip(actual.chunks_exact(channels))
+    {
+        for (max, (&e, &a)) in max.iter_mut().zip(e.iter().zip(a)) {
+            *max = (*max).max(e.max(a) - e.min(a));
+        }
+    }
+
+    Ok(max)
+}
+
+/// Largest absolute difference over the channels of each pixel
+pub fn try_difference(
+    expect: &[u8],
+    actual: &[u8],
+    width: u32,
+    height: u32,
+    channels: usize,
+) -> Result<Vec<u8>, FilterError> {
+    validate(expect, actual, width, height, channels)?;
+
+    Ok(expect
+        .chunks_exact(channels)
+        .zip(actual.chunks_exact(channels))
+        .map(|(e, a)| {
+            e.iter()
+                .zip(a)
+                .map(|(&e, &a)| e.max(a) - e.min(a))
+                .max()
+                .unwrap_or(0)
+        })
+        .collect())
+}
+
+fn validate(
+    expect: &[u8],
+    actual: &[u8],
+    width: u32,
+    height: u32,
+    channels: usize,
+) -> Result<(), FilterError> {
+    if channels == 0 || channels > 4 {
+        return Err(FilterError::UnsupportedChannels(channels));
+    }
+
+    let expected = width as usize * height as usize * channels;
+
+    for actual in [expect.len(), actual.len()].iter().copied() {
+        if actual != expected {
+            return Err(FilterError::BufferSize { expected, actual });
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_abs_diff_eq;
+
+    fn pattern(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 37 % 200) as u8).collect()
+    }
+
+    #[test]
+    fn test_identical() {
+        let image = pattern(16 * 9 * 3);
+        let metrics = try_compare(&image, &image, 16, 9, 3).unwrap();
+
+        assert_eq!(metrics.mse, 0.0);
+        assert_eq!(metrics.psnr, f64::INFINITY);
+        assert_abs_diff_eq!(metrics.ssim, 1.0, epsilon = 1e-4);
+        assert_eq!(metrics.max_error, 0);
+    }
+
+    #[test]
+    fn test_offset() {
+        let expect = pattern(16 * 9 * 2);
+        let mut actual = expect.clone();
+
+        // Offset the first channel by 10
+        for pixel in actual.chunks_exact_mut(2) {
+            pixel[0] += 10;
+        }
+
+        let metrics = try_compare(&expect, &actual, 16, 9, 2).unwrap();
+
+        assert_abs_diff_eq!(metrics.channels[0].mse, 100.0);
+        assert_abs_diff_eq!(metrics.channels[0].psnr, 28.1308, epsilon = 1e-4);
+        assert_eq!(metrics.channels[0].max_error, 10);
+        assert!(metrics.channels[0].ssim < 1.0);
+
+        assert_eq!(metrics.channels[1].mse, 0.0);
+        assert_eq!(metrics.mse, 50.0);
+        assert_eq!(metrics.max_error, 10);
+
+        let difference = try_difference(&expect, &actual, 16, 9, 2).unwrap();
+        assert!(difference.iter().all(|&d| d == 10));
+    }
+
+    #[test]
+    fn test_invalid_buffers() {
+        let image = pattern(12);
+
+        assert_eq!(
+            try_mse(&image, &image[..9], 2, 2, 3),
+            Err(FilterError::BufferSize {
+                expected: 12,
+                actual: 9
+            })
+        );
+        assert_eq!(
+            try_ssim(&image, &image, 2, 2, 0),
+            Err(FilterError::UnsupportedChannels(0))
+        );
+    }
+}
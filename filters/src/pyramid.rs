@@ -0,0 +1,399 @@
+use crate::{validate_image, Backend, Cpu, FilterError, Image};
+use rayon::prelude::*;
+
+/// Sigma of the blur before each halving, which removes the detail the smaller level cannot hold
+pub const PYRAMID_SIGMA: f32 = 1.0;
+
+/// An owned image of one level of a pyramid, of which Laplacian levels hold signed values
+#[derive(Debug, Clone, PartialEq)]
+pub struct Level {
+    pub buf: Vec<f32>,
+    pub width: u32,
+    pub height: u32,
+    pub channels: usize,
+}
+
+impl Level {
+    /// Copy of `buf_read` as the first level
+    pub fn new<T>(img: &Image<T>) -> Level
+    where
+        T: Sync + Send + Copy + Into<f32>,
+    {
+        Level {
+            buf: img.buf_read.iter().map(|&v| v.into()).collect(),
+            width: img.width,
+            height: img.height,
+            channels: img.channels,
+        }
+    }
+
+    /// Blur with `sigma` and keep every other pixel along both axes
+    fn downsample(&self, sigma: f32) -> Result<Level, FilterError> {
+        let mut buf_read = self.buf.clone();
+        let mut buf_write = vec![0.0; self.buf.len()];
+
+        Cpu.gaussian_blur_1d(
+            &mut Image {
+                buf_read: &mut buf_read,
+                buf_write: &mut buf_write,
+                width: self.width,
+                height: self.height,
+                channels: self.channels,
+            },
+            sigma,
+        )?;
+
+        let width = (self.width + 1) / 2;
+        let height = (self.height + 1) / 2;
+        let channels = self.channels;
+        let row = self.width as usize * channels;
+
+        let mut buf = vec![0.0; width as usize * height as usize * channels];
+
+        buf.par_chunks_mut(width as usize * channels)
+            .enumerate()
+            .for_each(|(y, dst)| {
+                let src = &buf_write[y * 2 * row..];
+
+                for (x, pixel) in dst.chunks_exact_mut(channels).enumerate() {
+                    pixel.copy_from_slice(&src[x * 2 * channels..(x * 2 + 1) * channels]);
+                }
+            });
+
+        Ok(Level {
+            buf,
+            width,
+            height,
+            channels,
+        })
+    }
+
+    /// Interpolate linearly between the pixels up to the given size, the inverse of keeping
+    /// every other pixel
+    fn upsample(&self, width: u32, height: u32) -> Level {
+        let channels = self.channels;
+        let row = self.width as usize * channels;
+
+        // Source pixels and the weight of the second one
+        let taps = |dst: u32, src: u32| {
+            (0..dst)
+                .map(|i| {
+                    let position = i as f32 / 2.0;
+                    let first = (position.floor() as u32).min(src - 1);
+                    let second = (first + 1).min(src - 1);
+
+                    (first as usize, second as usize, position - first as f32)
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let columns = taps(width, self.width);
+        let rows = taps(height, self.height);
+
+        let mut buf = vec![0.0; width as usize * height as usize * channels];
+
+        buf.par_chunks_mut(width as usize * channels)
+            .zip(rows.par_iter())
+            .for_each(|(dst, &(y0, y1, ty))| {
+                for (pixel, &(x0, x1, tx)) in dst.chunks_exact_mut(channels).zip(&columns) {
+                    for (c, value) in pixel.iter_mut().enumerate() {
+                        let at = |x: usize, y: usize| self.buf[y * row + x * channels + c];
+
+                        let top = at(x0, y0) * (1.0 - tx) + at(x1, y0) * tx;
+                        let bottom = at(x0, y1) * (1.0 - tx) + at(x1, y1) * tx;
+
+                        *value = top * (1.0 - ty) + bottom * ty;
+                    }
+                }
+            });
+
+        Level {
+            buf,
+            width,
+            height,
+            channels,
+        }
+    }
+}
+
+pub fn gaussian_pyramid<T>(img: &Image<T>, levels: usize, sigma: f32) -> Vec<Level>
+where
+    T: Sync + Send + Copy + Into<f32>,
+{
+    try_gaussian_pyramid(img, levels, sigma).unwrap_or_else(|err| panic!("{}", err))
+}
+
+/// Successively blurred and halved copies of `buf_read`, starting with the image itself
+///
+/// The pyramid ends early when a level is a single pixel wide or high.
+pub fn try_gaussian_pyramid<T>(
+    img: &Image<T>,
+    levels: usize,
+    sigma: f32,
+) -> Result<Vec<Level>, FilterError>
+where
+    T: Sync + Send + Copy + Into<f32>,
+{
+    validate_image(img)?;
+    validate_levels(levels)?;
+
+    gaussian_levels(Level::new(img), levels, sigma)
+}
+
+pub fn laplacian_pyramid<T>(img: &Image<T>, levels: usize, sigma: f32) -> Vec<Level>
+where
+    T: Sync + Send + Copy + Into<f32>,
+{
+    try_laplacian_pyramid(img, levels, sigma).unwrap_or_else(|err| panic!("{}", err))
+}
+
+/// Detail of each level of the Gaussian pyramid that the next level lacks, the last level is
+/// the last Gaussian level itself
+pub fn try_laplacian_pyramid<T>(
+    img: &Image<T>,
+    levels: usize,
+    sigma: f32,
+) -> Result<Vec<Level>, FilterError>
+where
+    T: Sync + Send + Copy + Into<f32>,
+{
+    Ok(laplacian_levels(try_gaussian_pyramid(img, levels, sigma)?))
+}
+
+pub fn reconstruct(pyramid: &[Level]) -> Level {
+    try_reconstruct(pyramid).unwrap_or_else(|err| panic!("{}", err))
+}
+
+/// Collapse a Laplacian pyramid into the image it was built from
+pub fn try_reconstruct(pyramid: &[Level]) -> Result<Level, FilterError> {
+    let (last, details) = pyramid.split_last().ok_or_else(|| {
+        FilterError::InvalidParameter("Pyramid should have at least one level".to_string())
+    })?;
+
+    details
+        .iter()
+        .rev()
+        .try_fold(last.clone(), |image, detail| {
+            if detail.channels != image.channels {
+                return Err(FilterError::InvalidParameter(format!(
+                    "Pyramid levels should have the same channels, got {} and {}",
+                    detail.channels, image.channels
+                )));
+            }
+
+            let mut level = image.upsample(detail.width, detail.height);
+
+            for (value, detail) in level.buf.iter_mut().zip(&detail.buf) {
+                *value += detail;
+            }
+
+            Ok(level)
+        })
+}
+
+pub fn blend(img: &Image<u8>, overlay: &[u8], mask: &[u8], levels: usize, sigma: f32) -> Vec<u8> {
+    try_blend(img, overlay, mask, levels, sigma).unwrap_or_else(|err| panic!("{}", err))
+}
+
+/// Blend `overlay` into `buf_read` where `mask` is white, separately for each level of their
+/// Laplacian pyramids
+///
+/// `overlay` has the size and channels of the image and `mask` a single channel. Coarse levels
+/// blend across the blurred mask, which hides the seam, while fine details stay sharp.
+pub fn try_blend(
+    img: &Image<u8>,
+    overlay: &[u8],
+    mask: &[u8],
+    levels: usize,
+    sigma: f32,
+) -> Result<Vec<u8>, FilterError> {
+    validate_image(img)?;
+    validate_levels(levels)?;
+
+    let pixels = img.width as usize * img.height as usize;
+
+    for &(expected, actual) in [(img.buf_read.len(), overlay.len()), (pixels, mask.len())].iter() {
+        if expected != actual {
+            return Err(FilterError::BufferSize { expected, actual });
+        }
+    }
+
+    let level = |buf: &[u8], channels: usize| Level {
+        buf: buf.iter().map(|&v| v as f32).collect(),
+        width: img.width,
+        height: img.height,
+        channels,
+    };
+
+    let base = laplacian_levels(gaussian_levels(
+        level(img.buf_read, img.channels),
+        levels,
+        sigma,
+    )?);
+    let overlay = laplacian_levels(gaussian_levels(
+        level(overlay, img.channels),
+        levels,
+        sigma,
+    )?);
+    let weights = gaussian_levels(level(mask, 1), levels, sigma)?;
+
+    let blended = base
+        .into_iter()
+        .zip(overlay)
+        .zip(weights)
+        .map(|((mut base, overlay), weights)| {
+            let channels = base.channels;
+
+            base.buf
+                .par_chunks_mut(channels)
+                .zip(overlay.buf.par_chunks(channels))
+                .zip(weights.buf.par_iter())
+                .for_each(|((base, overlay), &weight)| {
+                    let weight = weight / 255.0;
+
+                    for (value, overlay) in base.iter_mut().zip(overlay) {
+                        *value += (overlay - *value) * weight;
+                    }
+                });
+
+            base
+        })
+        .collect::<Vec<_>>();
+
+    Ok(try_reconstruct(&blended)?
+        .buf
+        .iter()
+        .map(|v| v.round().min(255.0).max(0.0) as u8)
+        .collect())
+}
+
+/// Gaussian pyramid of up to `levels` levels starting with `first`
+fn gaussian_levels(first: Level, levels: usize, sigma: f32) -> Result<Vec<Level>, FilterError> {
+    let mut pyramid = vec![first];
+
+    while pyramid.len() < levels {
+        let level = &pyramid[pyramid.len() - 1];
+
+        if level.width.min(level.height) <= 1 {
+            break;
+        }
+
+        let next = level.downsample(sigma)?;
+        pyramid.push(next);
+    }
+
+    Ok(pyramid)
+}
+
+/// Turn a Gaussian pyramid into a Laplacian pyramid
+fn laplacian_levels(mut pyramid: Vec<Level>) -> Vec<Level> {
+    for i in 0..pyramid.len() - 1 {
+        let (width, height) = (pyramid[i].width, pyramid[i].height);
+        let expanded = pyramid[i + 1].upsample(width, height);
+
+        for (value, expanded) in pyramid[i].buf.iter_mut().zip(expanded.buf) {
+            *value -= expanded;
+        }
+    }
+
+    pyramid
+}
+
+fn validate_levels(levels: usize) -> Result<(), FilterError> {
+    if levels == 0 {
+        return Err(FilterError::InvalidParameter(
+            "--levels should be > 0, got 0".to_string(),
+        ));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run<F, R>(buf: &[u8], width: u32, channels: usize, f: F) -> R
+    where
+        F: FnOnce(&Image<u8>) -> R,
+    {
+        let mut buf_read = buf.to_vec();
+        let mut buf_write = buf.to_vec();
+
+        f(&Image {
+            buf_read: &mut buf_read,
+            buf_write: &mut buf_write,
+            width,
+            height: (buf.len() / channels) as u32 / width,
+            channels,
+        })
+    }
+
+    #[test]
+    fn test_gaussian_pyramid() {
+        let buf = [100, 200].repeat(11 * 6);
+        let pyramid = run(&buf, 11, 2, |img| gaussian_pyramid(img, 8, PYRAMID_SIGMA));
+
+        let sizes = pyramid
+            .iter()
+            .map(|level| (level.width, level.height))
+            .collect::<Vec<_>>();
+
+        // Halving stops at a single row
+        assert_eq!(sizes, [(11, 6), (6, 3), (3, 2), (2, 1)]);
+
+        for level in &pyramid {
+            assert!(level
+                .buf
+                .chunks(2)
+                .all(|p| (p[0] - 100.0).abs() < 1e-3 && (p[1] - 200.0).abs() < 1e-3));
+        }
+
+        assert!(run(&buf, 11, 2, |img| try_gaussian_pyramid(img, 0, 1.0)).is_err());
+    }
+
+    #[test]
+    fn test_blend() {
+        // Black on the left and white on the right, split by a hard mask
+        let base = vec![0; 64 * 4];
+        let overlay = vec![255; 64 * 4];
+        let mask = (0..64 * 4)
+            .map(|i| if i % 64 < 32 { 0 } else { 255 })
+            .collect::<Vec<u8>>();
+
+        let blended = run(&base, 64, 1, |img| {
+            blend(img, &overlay, &mask, 4, PYRAMID_SIGMA)
+        });
+
+        // The far ends keep their image and the seam is a ramp rather than a step
+        let row = &blended[64..128];
+        assert_eq!((row[0], row[63]), (0, 255));
+        assert!(row.windows(2).all(|w| w[0] <= w[1]));
+        assert!(row[31] > 0 && row[32] < 255);
+
+        let result = run(&base, 64, 1, |img| {
+            try_blend(img, &overlay, &mask[1..], 4, PYRAMID_SIGMA)
+        });
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_laplacian_pyramid() {
+        let buf = (0..13 * 9 * 3)
+            .map(|i| (i * 37 % 256) as u8)
+            .collect::<Vec<_>>();
+
+        let pyramid = run(&buf, 13, 3, |img| laplacian_pyramid(img, 4, PYRAMID_SIGMA));
+        assert_eq!(pyramid.len(), 4);
+
+        // The details have both signs
+        assert!(pyramid[0].buf.iter().any(|&v| v < 0.0));
+
+        let image = reconstruct(&pyramid);
+        assert_eq!((image.width, image.height, image.channels), (13, 9, 3));
+
+        for (&expected, actual) in buf.iter().zip(image.buf) {
+            assert!((expected as f32 - actual).abs() < 1e-3);
+        }
+    }
+}
@@ -0,0 +1,268 @@
+use crate::{color, color_channels, validate_image, FilterError, Image};
+use rayon::prelude::*;
+use std::f32::consts::PI;
+
+/// Kernel that the source values around each resampled value are weighted with
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Resample {
+    /// Average of the area that each value covers, nearest neighbour when enlarging
+    Box,
+    Bilinear,
+    /// Cubic through the source values, which is sharp but rings slightly
+    CatmullRom,
+    /// Cubic with B = C = 1/3, which balances blur against ringing
+    Mitchell,
+    /// Sinc windowed over three lobes, the sharpest of the kernels
+    Lanczos3,
+}
+
+impl Resample {
+    /// Distance from the center beyond which the kernel is zero
+    pub fn support(self) -> f32 {
+        match self {
+            Resample::Box => 0.5,
+            Resample::Bilinear => 1.0,
+            Resample::CatmullRom | Resample::Mitchell => 2.0,
+            Resample::Lanczos3 => 3.0,
+        }
+    }
+
+    /// Weight of a source value at a distance `x` from the center
+    pub fn weight(self, x: f32) -> f32 {
+        match self {
+            Resample::Box if (-0.5..0.5).contains(&x) => 1.0,
+            Resample::Box => 0.0,
+            Resample::Bilinear => (1.0 - x.abs()).max(0.0),
+            Resample::CatmullRom => cubic(x, 0.0, 0.5),
+            Resample::Mitchell => cubic(x, 1.0 / 3.0, 1.0 / 3.0),
+            Resample::Lanczos3 if x.abs() < 3.0 => sinc(x) * sinc(x / 3.0),
+            Resample::Lanczos3 => 0.0,
+        }
+    }
+}
+
+pub fn resize(img: &Image<u8>, width: u32, height: u32, filter: Resample, linear: bool) -> Vec<u8> {
+    try_resize(img, width, height, filter, linear).unwrap_or_else(|err| panic!("{}", err))
+}
+
+/// Resample `buf_read` to a new size, first along the x-axis and then along the y-axis
+///
+/// With `linear`, the color channels are averaged in linear light rather than as sRGB values,
+/// which keeps fine bright details from darkening when shrinking.
+pub fn try_resize(
+    img: &Image<u8>,
+    width: u32,
+    height: u32,
+    filter: Resample,
+    linear: bool,
+) -> Result<Vec<u8>, FilterError> {
+    validate_image(img)?;
+
+    if width == 0 || height == 0 {
+        return Err(FilterError::InvalidParameter(format!(
+            "Size should be > 0, got {}×{}",
+            width, height
+        )));
+    }
+
+    let channels = img.channels;
+    let colors = if linear { color_channels(channels) } else { 0 };
+
+    let to_linear = (0..=255)
+        .map(|v| color::linear(v as f32 / 255.0) * 255.0)
+        .collect::<Vec<_>>();
+    let decode = |c: usize, v: u8| {
+        if c < colors {
+            to_linear[v as usize]
+        } else {
+            v as f32
+        }
+    };
+
+    let columns = contributions(img.width, width, filter);
+    let rows = contributions(img.height, height, filter);
+
+    let src_row = img.width as usize * channels;
+    let dst_row = width as usize * channels;
+    let src: &[u8] = img.buf_read;
+
+    // Resample each row along the x-axis
+    let mut horizontal = vec![0.0; dst_row * img.height as usize];
+
+    horizontal
+        .par_chunks_mut(dst_row)
+        .zip(src.par_chunks(src_row))
+        .for_each(|(row, src)| {
+            for (x, taps) in columns.iter().enumerate() {
+                for c in 0..channels {
+                    row[x * channels + c] = taps
+                        .iter()
+                        .map(|&(i, weight)| decode(c, src[i * channels + c]) * weight)
+                        .sum();
+                }
+            }
+        });
+
+    // Resample the rows along the y-axis
+    let mut buf = vec![0; dst_row * height as usize];
+
+    buf.par_chunks_mut(dst_row)
+        .zip(rows.par_iter())
+        .for_each(|(row, taps)| {
+            for (i, value) in row.iter_mut().enumerate() {
+                let mut v = taps
+                    .iter()
+                    .map(|&(y, weight)| horizontal[y * dst_row + i] * weight)
+                    .sum::<f32>();
+
+                if i % channels < colors {
+                    v = color::gamma((v / 255.0).min(1.0).max(0.0)) * 255.0;
+                }
+
+                *value = v.round().min(255.0).max(0.0) as u8;
+            }
+        });
+
+    Ok(buf)
+}
+
+/// Source indices and normalized weights of each resampled value along an axis, indices
+/// beyond the edges are clamped
+fn contributions(src: u32, dst: u32, filter: Resample) -> Vec<Vec<(usize, f32)>> {
+    let scale = dst as f32 / src as f32;
+
+    // Widen the kernel when shrinking, so every source value contributes
+    let filter_scale = (1.0 / scale).max(1.0);
+    let support = filter.support() * filter_scale;
+
+    (0..dst)
+        .map(|i| {
+            let center = (i as f32 + 0.5) / scale;
+            let first = (center - support - 0.5).floor() as i64;
+            let last = (center + support - 0.5).ceil() as i64;
+
+            let mut taps = (first..=last)
+                .map(|j| {
+                    let weight = filter.weight((j as f32 + 0.5 - center) / filter_scale);
+                    (j.min(src as i64 - 1).max(0) as usize, weight)
+                })
+                .filter(|&(_, weight)| weight != 0.0)
+                .collect::<Vec<_>>();
+
+            let sum = taps.iter().map(|&(_, weight)| weight).sum::<f32>();
+
+            for (_, weight) in taps.iter_mut() {
+                *weight /= sum;
+            }
+
+            taps
+        })
+        .collect()
+}
+
+/// Mitchell-Netravali cubic with parameters B and C
+fn cubic(x: f32, b: f32, c: f32) -> f32 {
+    let x = x.abs();
+
+    let value = if x < 1.0 {
+        (12.0 - 9.0 * b - 6.0 * c) * x.powi(3)
+            + (-18.0 + 12.0 * b + 6.0 * c) * x.powi(2)
+            + (6.0 - 2.0 * b)
+    } else if x < 2.0 {
+        (-b - 6.0 * c) * x.powi(3)
+            + (6.0 * b + 30.0 * c) * x.powi(2)
+            + (-12.0 * b - 48.0 * c) * x
+            + (8.0 * b + 24.0 * c)
+    } else {
+        0.0
+    };
+
+    value / 6.0
+}
+
+fn sinc(x: f32) -> f32 {
+    if x == 0.0 {
+        return 1.0;
+    }
+
+    (x * PI).sin() / (x * PI)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FILTERS: [Resample; 5] = [
+        Resample::Box,
+        Resample::Bilinear,
+        Resample::CatmullRom,
+        Resample::Mitchell,
+        Resample::Lanczos3,
+    ];
+
+    fn run(buf: &[u8], (width, height): (u32, u32), size: (u32, u32), filter: Resample) -> Vec<u8> {
+        let mut buf_read = buf.to_vec();
+        let mut buf_write = buf.to_vec();
+
+        resize(
+            &Image {
+                buf_read: &mut buf_read,
+                buf_write: &mut buf_write,
+                width,
+                height,
+                channels: buf.len() / (width * height) as usize,
+            },
+            size.0,
+            size.1,
+            filter,
+            false,
+        )
+    }
+
+    #[test]
+    fn test_same_size() {
+        let buf = (0..7 * 5 * 3)
+            .map(|i| (i * 37 % 256) as u8)
+            .collect::<Vec<_>>();
+
+        // Mitchell blurs slightly, the other kernels are zero at every other source value
+        for &filter in FILTERS.iter().filter(|&&f| f != Resample::Mitchell) {
+            assert_eq!(run(&buf, (7, 5), (7, 5), filter), buf, "{:?}", filter);
+        }
+    }
+
+    #[test]
+    fn test_constant() {
+        let buf = [90, 140, 200, 255].repeat(9 * 7);
+
+        for &filter in FILTERS.iter() {
+            for &size in [(4, 3), (20, 1), (9, 15)].iter() {
+                let actual = run(&buf, (9, 7), size, filter);
+                assert!(actual.chunks(4).all(|p| p == [90, 140, 200, 255]));
+            }
+        }
+    }
+
+    #[test]
+    fn test_area() {
+        assert_eq!(
+            run(&[0, 100, 200, 50], (4, 1), (2, 1), Resample::Box),
+            [50, 125]
+        );
+
+        // The average of black and white is brighter in linear light
+        let mut buf_read = vec![0, 0, 0, 255, 255, 255];
+        let mut buf_write = buf_read.clone();
+        let img = Image {
+            buf_read: &mut buf_read,
+            buf_write: &mut buf_write,
+            width: 2,
+            height: 1,
+            channels: 3,
+        };
+
+        assert_eq!(resize(&img, 1, 1, Resample::Box, false), [128, 128, 128]);
+        assert_eq!(resize(&img, 1, 1, Resample::Box, true), [188, 188, 188]);
+        assert!(try_resize(&img, 0, 1, Resample::Box, false).is_err());
+    }
+}
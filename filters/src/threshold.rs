@@ -0,0 +1,255 @@
+use crate::{
+    color_channels, try_histogram, validate_image, Backend, Cpu, FilterError, Histogram, Image,
+};
+use rayon::prelude::*;
+
+/// Dynamic range of the standard deviation in Sauvola's threshold
+const SAUVOLA_RANGE: f32 = 128.0;
+
+/// Method to find the threshold of each value, values above it become white
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Threshold {
+    /// The same threshold for every value
+    Fixed(u8),
+    /// Otsu's threshold of each channel, which best separates its histogram into two classes
+    Otsu,
+    /// Mean of the box of `radius` around each value, minus `offset`
+    Mean { radius: usize, offset: f32 },
+    /// Gaussian weighted mean around each value, minus `offset`
+    Gaussian { sigma: f32, offset: f32 },
+    /// Sauvola's threshold for documents, which lowers the local mean in areas of low contrast
+    Sauvola { radius: usize, k: f32 },
+}
+
+pub fn threshold(img: &mut Image<u8>, method: Threshold) {
+    try_threshold(img, method).unwrap_or_else(|err| panic!("{}", err))
+}
+
+/// Set each color channel to 0 or 255, alpha is copied unchanged
+pub fn try_threshold(img: &mut Image<u8>, method: Threshold) -> Result<(), FilterError> {
+    validate_image(img)?;
+
+    let channels = img.channels;
+    let colors = color_channels(channels);
+    let values = img.buf_read.iter().map(|&v| v as f32).collect::<Vec<_>>();
+
+    let limits = match method {
+        Threshold::Fixed(value) => vec![value as f32; values.len()],
+        Threshold::Otsu => {
+            let thresholds = try_histogram(img.buf_read, channels)?
+                .iter()
+                .map(otsu)
+                .collect::<Vec<_>>();
+
+            (0..values.len())
+                .map(|i| thresholds[i % channels] as f32)
+                .collect()
+        }
+        Threshold::Mean { radius, offset } => {
+            validate_radius(radius)?;
+
+            local_mean(img, values, |b, img| b.box_blur_1d(img, radius))?
+                .into_iter()
+                .map(|mean| mean - offset)
+                .collect()
+        }
+        Threshold::Gaussian { sigma, offset } => {
+            local_mean(img, values, |b, img| b.gaussian_blur_1d(img, sigma))?
+                .into_iter()
+                .map(|mean| mean - offset)
+                .collect()
+        }
+        Threshold::Sauvola { radius, k } => {
+            validate_radius(radius)?;
+
+            let squares = values.iter().map(|v| v * v).collect();
+            let mean = local_mean(img, values, |b, img| b.box_blur_1d(img, radius))?;
+            let mean_squares = local_mean(img, squares, |b, img| b.box_blur_1d(img, radius))?;
+
+            mean.iter()
+                .zip(&mean_squares)
+                .map(|(&m, &m2)| {
+                    let deviation = (m2 - m * m).max(0.0).sqrt();
+
+                    m * (1.0 + k * (deviation / SAUVOLA_RANGE - 1.0))
+                })
+                .collect()
+        }
+    };
+
+    let buf_read: &[u8] = img.buf_read;
+
+    img.buf_write
+        .par_iter_mut()
+        .zip(buf_read.par_iter().zip(limits.par_iter()))
+        .enumerate()
+        .for_each(|(i, (value, (&original, &limit)))| {
+            *value = if i % channels >= colors {
+                original
+            } else if original as f32 > limit {
+                255
+            } else {
+                0
+            };
+        });
+
+    Ok(())
+}
+
+/// Otsu's threshold, which maximizes the variance between the values at or below it and the
+/// values above it
+pub fn otsu(histogram: &Histogram) -> u8 {
+    let total = histogram.iter().map(|&count| count as f64).sum::<f64>();
+    let sum = histogram
+        .iter()
+        .enumerate()
+        .map(|(value, &count)| value as f64 * count as f64)
+        .sum::<f64>();
+
+    let mut best = (0, 0.0);
+    let mut weight_below = 0.0;
+    let mut sum_below = 0.0;
+
+    for (value, &count) in histogram.iter().enumerate() {
+        weight_below += count as f64;
+        sum_below += value as f64 * count as f64;
+
+        let weight_above = total - weight_below;
+
+        if weight_below == 0.0 || weight_above == 0.0 {
+            continue;
+        }
+
+        let mean_below = sum_below / weight_below;
+        let mean_above = (sum - sum_below) / weight_above;
+        let variance = weight_below * weight_above * (mean_below - mean_above).powi(2);
+
+        if variance > best.1 {
+            best = (value, variance);
+        }
+    }
+
+    best.0 as u8
+}
+
+/// Blur values of the image size to find the local mean around each value
+fn local_mean<F>(img: &Image<u8>, mut buf_read: Vec<f32>, blur: F) -> Result<Vec<f32>, FilterError>
+where
+    F: FnOnce(Cpu, &mut Image<f32>) -> Result<(), FilterError>,
+{
+    let mut buf_write = vec![0.0; buf_read.len()];
+
+    blur(
+        Cpu,
+        &mut Image {
+            buf_read: &mut buf_read,
+            buf_write: &mut buf_write,
+            width: img.width,
+            height: img.height,
+            channels: img.channels,
+        },
+    )?;
+
+    Ok(buf_write)
+}
+
+fn validate_radius(radius: usize) -> Result<(), FilterError> {
+    if radius == 0 {
+        return Err(FilterError::InvalidParameter(
+            "--radius should be > 0, got 0".to_string(),
+        ));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(buf: &[u8], width: u32, channels: usize, method: Threshold) -> Vec<u8> {
+        let mut buf_read = buf.to_vec();
+        let mut buf_write = vec![0; buf.len()];
+
+        threshold(
+            &mut Image {
+                buf_read: &mut buf_read,
+                buf_write: &mut buf_write,
+                width,
+                height: (buf.len() / channels) as u32 / width,
+                channels,
+            },
+            method,
+        );
+
+        buf_write
+    }
+
+    #[test]
+    fn test_otsu() {
+        let mut histogram = [0; 256];
+        histogram[40] = 100;
+        histogram[50] = 80;
+        histogram[200] = 60;
+        histogram[210] = 90;
+
+        let t = otsu(&histogram);
+        assert!((50..200).contains(&t));
+
+        // Alpha is copied and each color channel is thresholded on its own
+        let buf = [
+            40, 20, 60, 128, 50, 30, 70, 128, 200, 220, 190, 128, 210, 230, 200, 128,
+        ];
+        assert_eq!(
+            run(&buf, 4, 4, Threshold::Otsu),
+            [0, 0, 0, 128, 0, 0, 0, 128, 255, 255, 255, 128, 255, 255, 255, 128]
+        );
+        assert_eq!(
+            run(&[10, 0, 20, 255], 2, 2, Threshold::Fixed(15)),
+            [0, 0, 255, 255]
+        );
+    }
+
+    #[test]
+    fn test_adaptive() {
+        // A dark stroke on a bright gradient, which no global threshold separates
+        let buf = (0..32u32)
+            .map(|x| if x == 20 { 100 } else { 60 + x as u8 * 5 })
+            .collect::<Vec<_>>();
+
+        let methods = [
+            Threshold::Mean {
+                radius: 3,
+                offset: 2.0,
+            },
+            Threshold::Gaussian {
+                sigma: 2.0,
+                offset: 2.0,
+            },
+            Threshold::Sauvola { radius: 3, k: 0.2 },
+        ];
+
+        for &method in methods.iter() {
+            let binary = run(&buf, 32, 1, method);
+
+            assert_eq!(binary[20], 0, "{:?}", method);
+            assert_eq!(binary[10], 255, "{:?}", method);
+        }
+    }
+
+    #[test]
+    fn test_invalid_radius() {
+        let mut buf_read = vec![0; 4];
+        let mut buf_write = vec![0; 4];
+        let mut img = Image {
+            buf_read: &mut buf_read,
+            buf_write: &mut buf_write,
+            width: 2,
+            height: 2,
+            channels: 1,
+        };
+
+        let method = Threshold::Sauvola { radius: 0, k: 0.2 };
+        assert!(try_threshold(&mut img, method).is_err());
+    }
+}
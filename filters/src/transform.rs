@@ -0,0 +1,325 @@
+use crate::{validate_image, FilterError, Image, Resample};
+use rayon::prelude::*;
+
+/// Lossless rotation or flip, which only moves pixels
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Orientation {
+    /// Rotate 90° clockwise
+    Rotate90,
+    Rotate180,
+    /// Rotate 270° clockwise, i.e. 90° counterclockwise
+    Rotate270,
+    /// Mirror left and right
+    FlipHorizontal,
+    /// Mirror top and bottom
+    FlipVertical,
+    /// Mirror along the diagonal from the top left
+    Transpose,
+    /// Mirror along the diagonal from the top right
+    Transverse,
+}
+
+impl Orientation {
+    /// Size of an image of the given size after reorienting it
+    pub fn dimensions(self, width: u32, height: u32) -> (u32, u32) {
+        match self {
+            Orientation::Rotate180 | Orientation::FlipHorizontal | Orientation::FlipVertical => {
+                (width, height)
+            }
+            _ => (height, width),
+        }
+    }
+
+    /// Position in an image of the given size of the pixel that ends up at `x`, `y`
+    fn source(self, (width, height): (u32, u32), x: u32, y: u32) -> (u32, u32) {
+        match self {
+            Orientation::Rotate90 => (y, height - 1 - x),
+            Orientation::Rotate180 => (width - 1 - x, height - 1 - y),
+            Orientation::Rotate270 => (width - 1 - y, x),
+            Orientation::FlipHorizontal => (width - 1 - x, y),
+            Orientation::FlipVertical => (x, height - 1 - y),
+            Orientation::Transpose => (y, x),
+            Orientation::Transverse => (width - 1 - y, height - 1 - x),
+        }
+    }
+}
+
+pub fn orient<T>(img: &Image<T>, orientation: Orientation) -> Vec<T>
+where
+    T: Sync + Send + Copy + Into<f32>,
+{
+    try_orient(img, orientation).unwrap_or_else(|err| panic!("{}", err))
+}
+
+/// Rotate or flip `buf_read`, the size of the result is given by [`Orientation::dimensions`]
+pub fn try_orient<T>(img: &Image<T>, orientation: Orientation) -> Result<Vec<T>, FilterError>
+where
+    T: Sync + Send + Copy + Into<f32>,
+{
+    validate_image(img)?;
+
+    let channels = img.channels;
+    let size = (img.width, img.height);
+    let (width, _) = orientation.dimensions(img.width, img.height);
+    let src: &[T] = img.buf_read;
+
+    let mut buf = src.to_vec();
+
+    buf.par_chunks_mut(width as usize * channels)
+        .enumerate()
+        .for_each(|(y, row)| {
+            for (x, pixel) in row.chunks_exact_mut(channels).enumerate() {
+                let (sx, sy) = orientation.source(size, x as u32, y as u32);
+                let i = (sy as usize * img.width as usize + sx as usize) * channels;
+
+                pixel.copy_from_slice(&src[i..i + channels]);
+            }
+        });
+
+    Ok(buf)
+}
+
+/// Affine map `x' = a x + b y + c`, `y' = d x + e y + f` from input to output coordinates
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Affine(pub [f32; 6]);
+
+impl Affine {
+    pub const IDENTITY: Affine = Affine([1.0, 0.0, 0.0, 0.0, 1.0, 0.0]);
+
+    pub fn translation(x: f32, y: f32) -> Affine {
+        Affine([1.0, 0.0, x, 0.0, 1.0, y])
+    }
+
+    /// Rotate clockwise by `degrees` around the center of an image of size `from`, and move
+    /// that center to the center of an image of size `to`
+    pub fn rotation(degrees: f32, from: (u32, u32), to: (u32, u32)) -> Affine {
+        let (sin, cos) = degrees.to_radians().sin_cos();
+
+        Affine::translation(-(from.0 as f32) / 2.0, -(from.1 as f32) / 2.0)
+            .then(Affine([cos, -sin, 0.0, sin, cos, 0.0]))
+            .then(Affine::translation(to.0 as f32 / 2.0, to.1 as f32 / 2.0))
+    }
+
+    /// Map that applies `self` first and `next` second
+    pub fn then(self, next: Affine) -> Affine {
+        let [a, b, c, d, e, f] = self.0;
+        let [p, q, r, s, t, u] = next.0;
+
+        Affine([
+            p * a + q * d,
+            p * b + q * e,
+            p * c + q * f + r,
+            s * a + t * d,
+            s * b + t * e,
+            s * c + t * f + u,
+        ])
+    }
+
+    /// The map back from output to input coordinates, or `None` if it collapses the plane
+    pub fn inverse(self) -> Option<Affine> {
+        let [a, b, c, d, e, f] = self.0;
+        let determinant = a * e - b * d;
+
+        if determinant.abs() < f32::EPSILON {
+            return None;
+        }
+
+        Some(Affine([
+            e / determinant,
+            -b / determinant,
+            (b * f - c * e) / determinant,
+            -d / determinant,
+            a / determinant,
+            (c * d - a * f) / determinant,
+        ]))
+    }
+
+    pub fn apply(self, x: f32, y: f32) -> (f32, f32) {
+        let [a, b, c, d, e, f] = self.0;
+
+        (a * x + b * y + c, d * x + e * y + f)
+    }
+}
+
+/// Size of the smallest image that holds an image of the given size rotated by `degrees`
+pub fn rotated_size(width: u32, height: u32, degrees: f32) -> (u32, u32) {
+    let (sin, cos) = degrees.to_radians().sin_cos();
+    let (width, height) = (width as f32, height as f32);
+
+    // Round away tiny errors of the sine and cosine, i.e. of a rotation by 90°
+    let side = |side: f32| ((side - 1e-3).ceil() as u32).max(1);
+
+    (
+        side(width * cos.abs() + height * sin.abs()),
+        side(width * sin.abs() + height * cos.abs()),
+    )
+}
+
+pub fn warp(
+    img: &Image<u8>,
+    affine: Affine,
+    width: u32,
+    height: u32,
+    filter: Resample,
+    background: [u8; 4],
+) -> Vec<u8> {
+    try_warp(img, affine, width, height, filter, background).unwrap_or_else(|err| panic!("{}", err))
+}
+
+/// Map `buf_read` into an image of `width` × `height` with an affine map
+///
+/// Each output pixel interpolates the input around its position mapped back with the inverse,
+/// pixels beyond the input edges are `background`. Coordinates refer to the corners of pixels.
+pub fn try_warp(
+    img: &Image<u8>,
+    affine: Affine,
+    width: u32,
+    height: u32,
+    filter: Resample,
+    background: [u8; 4],
+) -> Result<Vec<u8>, FilterError> {
+    validate_image(img)?;
+
+    if width == 0 || height == 0 {
+        return Err(FilterError::InvalidParameter(format!(
+            "Size should be > 0, got {}×{}",
+            width, height
+        )));
+    }
+
+    let inverse = affine.inverse().ok_or_else(|| {
+        FilterError::InvalidParameter(format!("Affine map {:?} is not invertible", affine.0))
+    })?;
+
+    let channels = img.channels;
+    let support = filter.support();
+    let src: &[u8] = img.buf_read;
+
+    let value = |x: i64, y: i64, c: usize| {
+        if x < 0 || y < 0 || x >= img.width as i64 || y >= img.height as i64 {
+            background[c] as f32
+        } else {
+            src[(y as usize * img.width as usize + x as usize) * channels + c] as f32
+        }
+    };
+
+    let mut buf = vec![0; width as usize * height as usize * channels];
+
+    buf.par_chunks_mut(width as usize * channels)
+        .enumerate()
+        .for_each(|(y, row)| {
+            for (x, pixel) in row.chunks_exact_mut(channels).enumerate() {
+                // Position relative to the centers of the input pixels
+                let (sx, sy) = inverse.apply(x as f32 + 0.5, y as f32 + 0.5);
+                let (sx, sy) = (sx - 0.5, sy - 0.5);
+
+                let taps = |center: f32| {
+                    let first = (center - support).floor() as i64;
+                    let last = (center + support).ceil() as i64;
+
+                    (first..=last)
+                        .map(move |j| (j, filter.weight(j as f32 - center)))
+                        .filter(|&(_, weight)| weight != 0.0)
+                };
+
+                let mut sum = vec![0.0; channels];
+                let mut total = 0.0;
+
+                for (j, weight_y) in taps(sy) {
+                    for (i, weight_x) in taps(sx) {
+                        let weight = weight_x * weight_y;
+                        total += weight;
+
+                        for (c, sum) in sum.iter_mut().enumerate() {
+                            *sum += value(i, j, c) * weight;
+                        }
+                    }
+                }
+
+                for (value, sum) in pixel.iter_mut().zip(sum) {
+                    *value = (sum / total).round().min(255.0).max(0.0) as u8;
+                }
+            }
+        });
+
+    Ok(buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_orient() {
+        // 1 2 3
+        // 4 5 6
+        let mut buf_read = vec![1u8, 2, 3, 4, 5, 6];
+        let mut buf_write = vec![0; 6];
+        let img = Image {
+            buf_read: &mut buf_read,
+            buf_write: &mut buf_write,
+            width: 3,
+            height: 2,
+            channels: 1,
+        };
+
+        let cases = [
+            (Orientation::Rotate90, [4, 1, 5, 2, 6, 3]),
+            (Orientation::Rotate180, [6, 5, 4, 3, 2, 1]),
+            (Orientation::Rotate270, [3, 6, 2, 5, 1, 4]),
+            (Orientation::FlipHorizontal, [3, 2, 1, 6, 5, 4]),
+            (Orientation::FlipVertical, [4, 5, 6, 1, 2, 3]),
+            (Orientation::Transpose, [1, 4, 2, 5, 3, 6]),
+            (Orientation::Transverse, [6, 3, 5, 2, 4, 1]),
+        ];
+
+        for &(orientation, expected) in cases.iter() {
+            assert_eq!(orient(&img, orientation), expected, "{:?}", orientation);
+        }
+
+        assert_eq!(Orientation::Rotate90.dimensions(3, 2), (2, 3));
+    }
+
+    #[test]
+    fn test_warp() {
+        let buf = (0..5 * 4 * 2)
+            .map(|i| (i * 29 % 256) as u8)
+            .collect::<Vec<_>>();
+        let mut buf_read = buf.clone();
+        let mut buf_write = buf.clone();
+        let img = Image {
+            buf_read: &mut buf_read,
+            buf_write: &mut buf_write,
+            width: 5,
+            height: 4,
+            channels: 2,
+        };
+
+        // The identity and whole-pixel shifts interpolate nothing
+        for &filter in [Resample::Box, Resample::Bilinear, Resample::CatmullRom].iter() {
+            assert_eq!(warp(&img, Affine::IDENTITY, 5, 4, filter, [0; 4]), buf);
+        }
+
+        let shifted = warp(
+            &img,
+            Affine::translation(1.0, 0.0),
+            5,
+            4,
+            Resample::Bilinear,
+            [7, 9, 0, 0],
+        );
+        assert_eq!(&shifted[..2], &[7, 9]);
+        assert_eq!(&shifted[2..10], &buf[..8]);
+
+        // A rotation by 90° matches the lossless one
+        let size = rotated_size(5, 4, 90.0);
+        let affine = Affine::rotation(90.0, (5, 4), size);
+        assert_eq!(size, (4, 5));
+        assert_eq!(
+            warp(&img, affine, size.0, size.1, Resample::Bilinear, [0; 4]),
+            orient(&img, Orientation::Rotate90)
+        );
+
+        assert!(try_warp(&img, Affine([0.0; 6]), 5, 4, Resample::Box, [0; 4]).is_err());
+    }
+}
@@ -0,0 +1,105 @@
+//! Helpers shared by the integration tests
+#![allow(dead_code)]
+
+use filters::{psnr, try_max_error, try_mse};
+use image::{DynamicImage, GenericImageView, RgbaImage};
+use std::path::Path;
+
+/// Directory of the input and expected images
+const FIXTURES: &str = "tests/fixtures";
+
+/// Set to overwrite the expected images with the actual results instead of comparing them
+const REGENERATE: &str = "REGENERATE_FIXTURES";
+
+/// Number of differing pixels that are listed when an assertion fails
+const REPORTED_PIXELS: usize = 10;
+
+/// Tolerance that requires identical pixels
+pub const EXACT: [u8; 4] = [0; 4];
+
+/// Open an image from the fixtures directory
+pub fn open_fixture(name: &str) -> DynamicImage {
+    let path = Path::new(FIXTURES).join(name);
+
+    image::open(&path).unwrap_or_else(|err| panic!("Failed to open {:?}: {}", path, err))
+}
+
+/// Compare the decoded pixels of an image with an expected image in the fixtures directory,
+/// allowing each RGBA channel to differ by its tolerance
+///
+/// With `REGENERATE_FIXTURES` set, the expected image is overwritten instead.
+pub fn assert_golden(actual: &DynamicImage, name: &str, tolerance: [u8; 4]) {
+    if std::env::var_os(REGENERATE).is_some() {
+        let path = Path::new(FIXTURES).join(name);
+
+        actual
+            .save(&path)
+            .unwrap_or_else(|err| panic!("Failed to save {:?}: {}", path, err));
+
+        return;
+    }
+
+    let expect = open_fixture(name);
+
+    assert_eq!(
+        expect.dimensions(),
+        actual.dimensions(),
+        "Dimensions differ from {}",
+        name
+    );
+
+    assert_within_tolerance(&expect.to_rgba8(), &actual.to_rgba8(), tolerance);
+}
+
+/// Compare two images of the same size, allowing each RGBA channel to differ by its tolerance
+///
+/// A failure lists the first differing pixels and summarizes the error of each channel.
+pub fn assert_within_tolerance(expect: &RgbaImage, actual: &RgbaImage, tolerance: [u8; 4]) {
+    assert_eq!(
+        expect.dimensions(),
+        actual.dimensions(),
+        "Dimensions differ"
+    );
+
+    let differing = expect
+        .enumerate_pixels()
+        .zip(actual.pixels())
+        .filter(|((_, _, e), a)| {
+            e.0.iter()
+                .zip(&a.0)
+                .zip(&tolerance)
+                .any(|((&e, &a), &t)| e.max(a) - e.min(a) > t)
+        })
+        .map(|((x, y, e), a)| (x, y, e.0, a.0))
+        .collect::<Vec<_>>();
+
+    if differing.is_empty() {
+        return;
+    }
+
+    let (width, height) = expect.dimensions();
+    let (expect, actual) = (expect.as_raw(), actual.as_raw());
+
+    let mse = try_mse(expect, actual, width, height, 4).unwrap();
+    let max_error = try_max_error(expect, actual, width, height, 4).unwrap();
+
+    let mut report = format!(
+        "{} of {} pixels differ beyond a tolerance of {:?}\n",
+        differing.len(),
+        width * height,
+        tolerance
+    );
+
+    for (x, y, e, a) in differing.iter().take(REPORTED_PIXELS) {
+        report += &format!("  ({}, {}): expected {:?}, got {:?}\n", x, y, e, a);
+    }
+
+    report += &format!(
+        "Max error {:?}, MSE {:.3?}, PSNR {:.2?} dB",
+        max_error,
+        mse,
+        mse.iter().map(|&mse| psnr(mse)).collect::<Vec<_>>()
+    );
+
+    panic!("{}", report);
+}
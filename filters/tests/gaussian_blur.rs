@@ -1,14 +1,16 @@
+mod common;
+
+use common::{assert_golden, open_fixture, EXACT};
 use filters::{gaussian_blur_1d, gaussian_blur_2d, Image};
 use image::{flat::SampleLayout, GenericImage};
-use std::fs;
 
 #[test]
 fn test_gaussian_blur_1d() {
     // Setup
-    let mut file = image::open("tests/fixtures/input.png").unwrap();
+    let mut file = open_fixture("input.png");
 
-    let mut buf_read = file.clone().into_rgba();
-    let mut buf_write = file.clone().into_rgba();
+    let mut buf_read = file.clone().into_rgba8();
+    let mut buf_write = file.clone().into_rgba8();
 
     let SampleLayout {
         width,
@@ -31,26 +33,17 @@ fn test_gaussian_blur_1d() {
     // Write buffer to image
     file.copy_from(&buf_write, 0, 0).unwrap();
 
-    // Write file to disk
-    file.save("tests/fixtures/actual_gaussian_blur_1d_sigma_3.png")
-        .unwrap();
-
-    // Compare actual file data, as lossy compression might have changed the pixel values
-    let expect = fs::read("tests/fixtures/expect_gaussian_blur_1d_sigma_3.png").unwrap();
-    let actual = fs::read("tests/fixtures/actual_gaussian_blur_1d_sigma_3.png").unwrap();
-    assert_eq!(expect, actual);
-
-    // Teardown and remove generated artifacts
-    fs::remove_file("tests/fixtures/actual_gaussian_blur_1d_sigma_3.png").unwrap();
+    // Compare the decoded pixels with the expected image
+    assert_golden(&file, "expect_gaussian_blur_1d_sigma_3.png", EXACT);
 }
 
 #[test]
 fn test_gaussian_blur_2d() {
     // Setup
-    let mut file = image::open("tests/fixtures/input.png").unwrap();
+    let mut file = open_fixture("input.png");
 
-    let mut buf_read = file.clone().into_rgba();
-    let mut buf_write = file.clone().into_rgba();
+    let mut buf_read = file.clone().into_rgba8();
+    let mut buf_write = file.clone().into_rgba8();
 
     let SampleLayout {
         width,
@@ -73,16 +66,6 @@ fn test_gaussian_blur_2d() {
     // Write buffer to image
     file.copy_from(&buf_write, 0, 0).unwrap();
 
-    // Write file to disk
-    file.save("tests/fixtures/actual_gaussian_blur_2d_sigma_3.png")
-        .unwrap();
-
-    // Compare actual file data, as lossy compression might have changed the pixel values
-    let expect = fs::read("tests/fixtures/expect_gaussian_blur_2d_sigma_3.png").unwrap();
-    let actual = fs::read("tests/fixtures/actual_gaussian_blur_2d_sigma_3.png").unwrap();
-    assert_eq!(expect, actual);
-
-    // Teardown and remove generated artifacts
-    fs::remove_file("tests/fixtures/actual_gaussian_blur_2d_sigma_3.png").unwrap();
+    // Compare the decoded pixels with the expected image
+    assert_golden(&file, "expect_gaussian_blur_2d_sigma_3.png", EXACT);
 }
-
@@ -0,0 +1,123 @@
+mod common;
+
+use common::assert_within_tolerance;
+use filters::{
+    try_convolve, try_convolve_gpu, try_gaussian_blur_1d, try_gaussian_blur_1d_gpu, FilterError,
+    Image,
+};
+use futures::executor::block_on;
+use image::RgbaImage;
+use ndarray::prelude::*;
+
+// These tests run on any Vulkan, Metal or DX12 adapter. Without a GPU, a software adapter such
+// as lavapipe or SwiftShader can be selected with `VK_ICD_FILENAMES`. The tests fail when no
+// adapter is available, unless `SKIP_GPU` is set. CI sets `REQUIRE_GPU`, which overrides it.
+
+/// Create an RGBA pattern of the given size
+fn pattern(width: u32, height: u32) -> Vec<u8> {
+    (0..width * height * 4)
+        .map(|i| (i * 37 % 251) as u8)
+        .collect()
+}
+
+/// Whether to skip rather than fail a test when no adapter is available
+fn skip_without_adapter() -> bool {
+    std::env::var_os("SKIP_GPU").is_some() && std::env::var_os("REQUIRE_GPU").is_none()
+}
+
+/// Filter the same image on the CPU and the GPU, or return `None` without an adapter if the
+/// tests are skipped
+fn run<C, G>(width: u32, height: u32, cpu: C, gpu: G) -> Option<(Vec<u8>, Vec<u8>)>
+where
+    C: Fn(&mut Image<u8>) -> Result<(), FilterError>,
+    G: Fn(&mut Image<u8>) -> Result<(), FilterError>,
+{
+    let filter = |f: &dyn Fn(&mut Image<u8>) -> Result<(), FilterError>| {
+        let mut buf_read = pattern(width, height);
+        let mut buf_write = vec![0; buf_read.len()];
+
+        f(&mut Image {
+            buf_read: &mut buf_read,
+            buf_write: &mut buf_write,
+            width,
+            height,
+            channels: 4,
+        })
+        .map(|_| buf_write)
+    };
+
+    let expect = filter(&cpu).unwrap();
+
+    match filter(&gpu) {
+        Ok(actual) => Some((expect, actual)),
+        Err(FilterError::AdapterUnavailable) if skip_without_adapter() => {
+            eprintln!("Skipping GPU test, no adapter is available");
+            None
+        }
+        Err(err) => panic!("{}", err),
+    }
+}
+
+/// Compare the results, the GPU rounds to the nearest value when storing a pixel where the CPU
+/// truncates
+fn assert_close(width: u32, height: u32, expect: Vec<u8>, actual: Vec<u8>, tolerance: u8) {
+    let image = |buf| RgbaImage::from_raw(width, height, buf).unwrap();
+
+    assert_within_tolerance(&image(expect), &image(actual), [tolerance; 4]);
+}
+
+#[test]
+fn test_convolve_gpu() {
+    let kernel = array![
+        [0.0, 0.1, 0.0, 0.2, 0.0],
+        [0.1, -0.2, 0.6, -0.2, 0.1],
+        [0.0, 0.2, 0.0, 0.1, 0.0]
+    ];
+
+    // Sizes that are not a multiple of the workgroup size or of the row alignment
+    for &(width, height) in &[(1, 1), (45, 23), (70, 33), (257, 3)] {
+        let result = run(
+            width,
+            height,
+            |img| try_convolve(img, &kernel),
+            |img| block_on(try_convolve_gpu(img, &kernel)),
+        );
+
+        if let Some((expect, actual)) = result {
+            assert_close(width, height, expect, actual, 1);
+        }
+    }
+}
+
+#[test]
+fn test_gaussian_blur_1d_gpu() {
+    let result = run(
+        100,
+        61,
+        |img| try_gaussian_blur_1d(img, 2.0),
+        |img| block_on(try_gaussian_blur_1d_gpu(img, 2.0)),
+    );
+
+    // Both passes round, so the difference can add up
+    if let Some((expect, actual)) = result {
+        assert_close(100, 61, expect, actual, 2);
+    }
+}
+
+#[test]
+fn test_convolve_gpu_invalid_channels() {
+    let mut buf_read = vec![0; 12];
+    let mut buf_write = vec![0; 12];
+
+    let mut img = Image {
+        buf_read: &mut buf_read,
+        buf_write: &mut buf_write,
+        width: 2,
+        height: 2,
+        channels: 3,
+    };
+
+    let result = block_on(try_convolve_gpu(&mut img, &Array2::ones((3, 3))));
+
+    assert_eq!(result, Err(FilterError::UnsupportedChannels(3)));
+}
@@ -0,0 +1,421 @@
+use crate::metadata::{self, Metadata, PNG_SIGNATURE};
+use crate::region::Region;
+use crate::{filter_regions, io, mask, Filter, Opts};
+use anyhow::{bail, ensure, Context, Result};
+use image::{
+    codecs::{
+        gif::{GifDecoder, GifEncoder, Repeat},
+        png::PngDecoder,
+    },
+    AnimationDecoder, Delay, DynamicImage, GenericImageView, ImageFormat,
+};
+use rayon::prelude::*;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+
+/// A frame of an animation, composited onto the full canvas
+pub struct Frame {
+    pub image: DynamicImage,
+    pub delay: Delay,
+}
+
+pub struct Animation {
+    pub frames: Vec<Frame>,
+    /// Number of times the animation is played, 0 plays it forever
+    pub plays: u32,
+    /// Number of the first frame, used to number the frames of an output sequence
+    pub first: usize,
+}
+
+/// Returns true if the path is a numbered image sequence, i.e. `frames/%04d.png`
+pub fn is_sequence(path: &Path) -> bool {
+    split_sequence(path).is_some()
+}
+
+/// Split a sequence pattern into the text before and after `%d`, and the padding of `%04d`
+fn split_sequence(pattern: &Path) -> Option<(&str, usize, &str)> {
+    let pattern = pattern.to_str()?;
+    let start = pattern.find('%')?;
+
+    let rest = &pattern[start + 1..];
+    let digits = rest.find(|c: char| !c.is_ascii_digit())?;
+
+    if !rest[digits..].starts_with('d') {
+        return None;
+    }
+
+    let width = match digits {
+        0 => 0,
+        _ => rest[..digits].parse().ok()?,
+    };
+
+    Some((&pattern[..start], width, &rest[digits + 1..]))
+}
+
+/// The path of a frame within a numbered image sequence
+pub fn sequence_path(pattern: &Path, index: usize) -> PathBuf {
+    match split_sequence(pattern) {
+        Some((prefix, width, suffix)) => PathBuf::from(format!(
+            "{}{:0width$}{}",
+            prefix,
+            index,
+            suffix,
+            width = width
+        )),
+        None => pattern.to_path_buf(),
+    }
+}
+
+/// Open the frames of a numbered image sequence, starting at either 0 or 1
+pub fn open_sequence(pattern: &Path, delay: Delay) -> Result<Animation> {
+    let first = (0..=1)
+        .find(|&index| sequence_path(pattern, index).is_file())
+        .with_context(|| format!("Input {:?} does not exist", pattern.display()))?;
+
+    let mut frames: Vec<Frame> = Vec::new();
+
+    for index in first.. {
+        let path = sequence_path(pattern, index);
+
+        if !path.is_file() {
+            break;
+        }
+
+        let image = image::open(&path)
+            .with_context(|| format!("Failed to open frame {:?}", path.display()))?;
+
+        if let Some(frame) = frames.first() {
+            ensure!(
+                image.dimensions() == frame.image.dimensions(),
+                "Frame {:?} is {}×{}, but the first frame is {}×{}",
+                path.display(),
+                image.width(),
+                image.height(),
+                frame.image.width(),
+                frame.image.height()
+            );
+        }
+
+        frames.push(Frame {
+            image: DynamicImage::ImageRgba8(image.to_rgba8()),
+            delay,
+        });
+    }
+
+    Ok(Animation {
+        frames,
+        plays: 0,
+        first,
+    })
+}
+
+/// Decode an animated GIF or PNG, still images return `None`
+pub fn decode(bytes: &[u8]) -> Result<Option<Animation>> {
+    let (frames, plays) = match image::guess_format(bytes) {
+        Ok(ImageFormat::Gif) => {
+            let decoder = GifDecoder::new(Cursor::new(bytes))?;
+            (decoder.into_frames().collect_frames(), gif_plays(bytes))
+        }
+        Ok(ImageFormat::Png) => {
+            let decoder = PngDecoder::new(Cursor::new(bytes))?;
+
+            if !decoder.is_apng() {
+                return Ok(None);
+            }
+
+            (
+                decoder.apng().into_frames().collect_frames(),
+                apng_plays(bytes),
+            )
+        }
+        _ => return Ok(None),
+    };
+
+    let frames = frames.context("Failed to decode animation")?;
+
+    if frames.len() < 2 {
+        return Ok(None);
+    }
+
+    let frames = frames
+        .into_iter()
+        .map(|frame| Frame {
+            delay: frame.delay(),
+            image: DynamicImage::ImageRgba8(frame.into_buffer()),
+        })
+        .collect();
+
+    Ok(Some(Animation {
+        frames,
+        plays,
+        first: 0,
+    }))
+}
+
+/// Read the loop count of the NETSCAPE2.0 application extension, which repeats the animation
+fn gif_plays(bytes: &[u8]) -> u32 {
+    const NETSCAPE: &[u8] = b"NETSCAPE2.0\x03\x01";
+
+    let loops = bytes
+        .windows(NETSCAPE.len())
+        .position(|window| window == NETSCAPE)
+        .and_then(|offset| bytes.get(offset + NETSCAPE.len()..offset + NETSCAPE.len() + 2))
+        .map(|loops| u16::from_le_bytes([loops[0], loops[1]]));
+
+    match loops {
+        None => 1,
+        Some(0) => 0,
+        Some(loops) => loops as u32 + 1,
+    }
+}
+
+/// Read the number of plays of the animation control chunk
+fn apng_plays(bytes: &[u8]) -> u32 {
+    metadata::read_png_chunks(bytes)
+        .into_iter()
+        .find(|(kind, data)| kind == b"acTL" && data.len() == 8)
+        .map_or(0, |(_, data)| {
+            u32::from_be_bytes([data[4], data[5], data[6], data[7]])
+        })
+}
+
+/// Filter every frame of an animation in parallel and save the result
+pub fn process(
+    opts: &Opts,
+    filter: &Filter,
+    regions: &[Region],
+    mut animation: Animation,
+    input: &Path,
+    output: &Path,
+) -> Result<()> {
+    ensure!(
+        opts.histogram.is_none(),
+        "--histogram cannot be combined with animations"
+    );
+
+    let (width, height) = animation.frames[0].image.dimensions();
+
+    let mask = match opts.mask {
+        Some(ref path) => Some(mask::open_mask(path, width, height)?),
+        None => None,
+    };
+
+    animation
+        .frames
+        .par_iter_mut()
+        .enumerate()
+        .try_for_each(|(index, frame)| {
+            filter_regions(
+                opts,
+                &mut frame.image,
+                filter,
+                regions,
+                mask.as_ref(),
+                input,
+            )
+            .with_context(|| format!("Failed to process frame {}", index))
+        })?;
+
+    save(&animation, output, &opts.encode, opts.force)
+}
+
+/// Save an animation as GIF, APNG or a numbered image sequence
+pub fn save(
+    animation: &Animation,
+    output: &Path,
+    options: &io::EncodeOptions,
+    force: bool,
+) -> Result<()> {
+    if is_sequence(output) {
+        for (index, frame) in animation.frames.iter().enumerate() {
+            let path = sequence_path(output, animation.first + index);
+
+            ensure!(
+                !path.exists() || force,
+                "Output {:?} exists. To overwrite files, use --force.",
+                path.display()
+            );
+
+            io::save_image(&frame.image, &Metadata::default(), &path, options)?;
+        }
+
+        return Ok(());
+    }
+
+    let bytes = match io::output_format(output, options)? {
+        ImageFormat::Gif => encode_gif(animation)?,
+        ImageFormat::Png => encode_apng(animation, options)?,
+        format => bail!(
+            "Animations can only be saved as GIF, PNG or a numbered sequence, not {:?}",
+            format
+        ),
+    };
+
+    io::write_output(output, &bytes)
+}
+
+fn encode_gif(animation: &Animation) -> Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+
+    {
+        let mut encoder = GifEncoder::new(&mut bytes);
+
+        // The loop count repeats the animation after it has been played once
+        match animation.plays {
+            0 => encoder.set_repeat(Repeat::Infinite)?,
+            1 => {}
+            plays => encoder.set_repeat(Repeat::Finite((plays - 1).min(u16::MAX as u32) as u16))?,
+        }
+
+        let frames = animation
+            .frames
+            .iter()
+            .map(|frame| image::Frame::from_parts(frame.image.to_rgba8(), 0, 0, frame.delay));
+
+        encoder
+            .encode_frames(frames)
+            .context("Failed to encode animation")?;
+    }
+
+    Ok(bytes)
+}
+
+/// Encode an animated PNG, of which the first frame doubles as the still image
+///
+/// See: https://wiki.mozilla.org/APNG_Specification
+fn encode_apng(animation: &Animation, options: &io::EncodeOptions) -> Result<Vec<u8>> {
+    let mut output = PNG_SIGNATURE.to_vec();
+    let mut sequence = 0u32;
+
+    for (index, frame) in animation.frames.iter().enumerate() {
+        let mut png = Vec::new();
+        io::encode(&frame.image, &mut png, ImageFormat::Png, options)
+            .context("Failed to encode animation")?;
+
+        let chunks = metadata::read_png_chunks(&png);
+
+        if index == 0 {
+            let (kind, header) = chunks[0];
+            metadata::write_png_chunk(&mut output, kind, header);
+
+            let control = [
+                (animation.frames.len() as u32).to_be_bytes(),
+                animation.plays.to_be_bytes(),
+            ]
+            .concat();
+            metadata::write_png_chunk(&mut output, *b"acTL", &control);
+        }
+
+        // Each frame covers the canvas at offset 0,0 and replaces the previous one
+        let (width, height) = frame.image.dimensions();
+        let delay = delay_ms(frame.delay).min(u16::MAX as u32) as u16;
+
+        let mut control = Vec::with_capacity(26);
+        control.extend_from_slice(&sequence.to_be_bytes());
+        control.extend_from_slice(&width.to_be_bytes());
+        control.extend_from_slice(&height.to_be_bytes());
+        control.extend_from_slice(&[0; 8]);
+        control.extend_from_slice(&delay.to_be_bytes());
+        control.extend_from_slice(&1000u16.to_be_bytes());
+        control.extend_from_slice(&[0, 0]);
+
+        metadata::write_png_chunk(&mut output, *b"fcTL", &control);
+        sequence += 1;
+
+        for (_, data) in chunks.iter().filter(|(kind, _)| kind == b"IDAT") {
+            if index == 0 {
+                metadata::write_png_chunk(&mut output, *b"IDAT", data);
+            } else {
+                let data = [&sequence.to_be_bytes()[..], data].concat();
+                metadata::write_png_chunk(&mut output, *b"fdAT", &data);
+                sequence += 1;
+            }
+        }
+    }
+
+    metadata::write_png_chunk(&mut output, *b"IEND", &[]);
+
+    Ok(output)
+}
+
+fn delay_ms(delay: Delay) -> u32 {
+    let (numerator, denominator) = delay.numer_denom_ms();
+    (numerator as f64 / denominator as f64).round() as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgba, RgbaImage};
+
+    fn animation(plays: u32) -> Animation {
+        let frames = [(255, 40), (0, 120)]
+            .iter()
+            .map(|&(red, delay)| Frame {
+                image: DynamicImage::ImageRgba8(RgbaImage::from_pixel(
+                    4,
+                    3,
+                    Rgba([red, 0, 0, 255]),
+                )),
+                delay: Delay::from_numer_denom_ms(delay, 1),
+            })
+            .collect();
+
+        Animation {
+            frames,
+            plays,
+            first: 0,
+        }
+    }
+
+    #[test]
+    fn test_sequence_path() {
+        assert!(is_sequence(Path::new("frames/%04d.png")));
+        assert!(is_sequence(Path::new("frame_%d.png")));
+        assert!(!is_sequence(Path::new("100%.png")));
+
+        assert_eq!(
+            sequence_path(Path::new("frames/%04d.png"), 12),
+            Path::new("frames/0012.png")
+        );
+        assert_eq!(
+            sequence_path(Path::new("frame_%d.png"), 3),
+            Path::new("frame_3.png")
+        );
+    }
+
+    #[test]
+    fn test_apng_roundtrip() {
+        let expect = animation(3);
+        let options = io::EncodeOptions {
+            format: None,
+            jpeg_quality: 75,
+            png_compression: image::codecs::png::CompressionType::Fast,
+            png_filter: image::codecs::png::FilterType::Sub,
+            png_16bit: false,
+        };
+
+        let bytes = encode_apng(&expect, &options).unwrap();
+        let actual = decode(&bytes).unwrap().unwrap();
+
+        assert_eq!(actual.plays, 3);
+        assert_eq!(actual.frames.len(), 2);
+
+        for (actual, expect) in actual.frames.iter().zip(&expect.frames) {
+            assert_eq!(actual.image.as_bytes(), expect.image.as_bytes());
+            assert_eq!(delay_ms(actual.delay), delay_ms(expect.delay));
+        }
+    }
+
+    #[test]
+    fn test_gif_roundtrip() {
+        for &plays in [0, 1, 5].iter() {
+            let bytes = encode_gif(&animation(plays)).unwrap();
+            let actual = decode(&bytes).unwrap().unwrap();
+
+            assert_eq!(actual.plays, plays);
+            assert_eq!(actual.frames.len(), 2);
+            assert_eq!(delay_ms(actual.frames[1].delay), 120);
+        }
+    }
+}
@@ -0,0 +1,257 @@
+use anyhow::{anyhow, bail, Context, Result};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+/// Expand each input into a list of image paths
+///
+/// An input can either be a file, a directory of which the images are used or a glob
+/// pattern, i.e. `"frames/*.png"`. The pattern has to be quoted to prevent the shell from
+/// expanding it. Stdin, `-`, and numbered image sequences, i.e. `frames/%04d.png`, are passed
+/// on as is.
+pub fn expand_inputs(inputs: &[PathBuf]) -> Result<Vec<PathBuf>> {
+    let mut paths = Vec::new();
+
+    for input in inputs {
+        if crate::io::is_stdio(input) || crate::animation::is_sequence(input) {
+            paths.push(input.clone());
+        } else if input.is_dir() {
+            let mut entries = std::fs::read_dir(input)
+                .with_context(|| format!("Failed to read directory {:?}", input.display()))?
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .filter(|path| path.is_file() && image::ImageFormat::from_path(path).is_ok())
+                .collect::<Vec<_>>();
+
+            // Keep numbered frames in order
+            entries.sort();
+            paths.extend(entries);
+        } else if input.exists() {
+            paths.push(input.clone());
+        } else {
+            let pattern = input
+                .to_str()
+                .ok_or_else(|| anyhow!("Input {:?} is not valid UTF-8", input.display()))?;
+
+            let matches = glob::glob(pattern)
+                .with_context(|| format!("Input {:?} does not exist", input.display()))?
+                .filter_map(|entry| entry.ok())
+                .filter(|path| path.is_file())
+                .collect::<Vec<_>>();
+
+            if matches.is_empty() {
+                bail!("Input {:?} does not exist", input.display());
+            }
+
+            paths.extend(matches);
+        }
+    }
+
+    Ok(paths)
+}
+
+/// Create an output path from a template
+///
+/// The template can contain the placeholders `{stem}`, `{ext}`, `{name}` and `{index}`,
+/// which are replaced by the file stem, extension and name of the input and its position
+/// in the batch.
+pub fn output_path(dir: &Path, template: &str, input: &Path, index: usize) -> PathBuf {
+    let stem = input.file_stem().unwrap_or_default().to_string_lossy();
+    let ext = input.extension().unwrap_or_default().to_string_lossy();
+    let name = input.file_name().unwrap_or_default().to_string_lossy();
+
+    let file_name = template
+        .replace("{stem}", &stem)
+        .replace("{ext}", &ext)
+        .replace("{name}", &name)
+        .replace("{index}", &index.to_string());
+
+    dir.join(file_name)
+}
+
+/// Pair each input with its output path from a template
+///
+/// Fails before anything is written if two inputs map to the same output, i.e. `a/x.png` and
+/// `b/x.png` with `{stem}.{ext}`, as the later image would overwrite the earlier one.
+pub fn output_paths(
+    dir: &Path,
+    template: &str,
+    inputs: &[PathBuf],
+) -> Result<Vec<(PathBuf, PathBuf)>> {
+    let mut seen = HashMap::new();
+    let mut paths = Vec::with_capacity(inputs.len());
+
+    for (index, input) in inputs.iter().enumerate() {
+        let output = output_path(dir, template, input, index);
+
+        if let Some(other) = seen.insert(output.clone(), input) {
+            bail!(
+                "Inputs {:?} and {:?} are both written to {:?}, use {{index}} in --template to tell them apart",
+                other.display(),
+                input.display(),
+                output.display()
+            );
+        }
+
+        paths.push((input.clone(), output));
+    }
+
+    Ok(paths)
+}
+
+#[derive(Debug, Default)]
+pub struct Summary {
+    pub succeeded: usize,
+    pub failed: Vec<(PathBuf, anyhow::Error)>,
+}
+
+impl Summary {
+    pub fn print(&self) {
+        for (input, err) in &self.failed {
+            eprintln!("Failed {:?}: {:#}", input.display(), err);
+        }
+
+        eprintln!(
+            "Processed {} images: {} succeeded, {} failed",
+            self.succeeded + self.failed.len(),
+            self.succeeded,
+            self.failed.len()
+        );
+    }
+}
+
+/// Process each input and output pair with at most `jobs` images in flight at once
+///
+/// A failing image does not stop the batch, its error is collected in the summary.
+pub fn run<F>(paths: &[(PathBuf, PathBuf)], jobs: Option<usize>, process: F) -> Result<Summary>
+where
+    F: Fn(&Path, &Path) -> Result<()> + Sync,
+{
+    let mut builder = rayon::ThreadPoolBuilder::new();
+
+    if let Some(jobs) = jobs {
+        builder = builder.num_threads(jobs);
+    }
+
+    // Filters run their rows on the same pool, so it also bounds the threads per image
+    let pool = builder.build().context("Failed to create thread pool")?;
+
+    // A fixed number of tasks take the next image once they are done with the previous one. A
+    // thread that waits for the rows of its image can pick up another task, but never more
+    // images than there are tasks.
+    let next = AtomicUsize::new(0);
+    let results = Mutex::new(Vec::with_capacity(paths.len()));
+
+    pool.install(|| {
+        rayon::scope(|scope| {
+            for _ in 0..pool.current_num_threads() {
+                scope.spawn(|_| loop {
+                    let index = next.fetch_add(1, Ordering::SeqCst);
+
+                    let (input, output) = match paths.get(index) {
+                        Some(pair) => pair,
+                        None => break,
+                    };
+
+                    let result = process(input, output).map_err(|err| (input.clone(), err));
+                    results.lock().unwrap().push((index, result));
+                });
+            }
+        })
+    });
+
+    let mut results = results.into_inner().unwrap();
+    results.sort_by_key(|(index, _)| *index);
+
+    let mut summary = Summary::default();
+
+    for (_, result) in results {
+        match result {
+            Ok(()) => summary.succeeded += 1,
+            Err(failure) => summary.failed.push(failure),
+        }
+    }
+
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rayon::prelude::*;
+
+    #[test]
+    fn test_output_path() {
+        let actual = output_path(
+            Path::new("out"),
+            "{stem}_blur_{index}.{ext}",
+            Path::new("frames/frame_001.png"),
+            3,
+        );
+
+        assert_eq!(actual, Path::new("out/frame_001_blur_3.png"));
+    }
+
+    #[test]
+    fn test_output_paths_rejects_duplicates() {
+        let inputs = vec![PathBuf::from("a/x.png"), PathBuf::from("b/x.png")];
+
+        let err = output_paths(Path::new("out"), "{stem}.{ext}", &inputs).unwrap_err();
+        assert!(err.to_string().contains("out/x.png"), "{}", err);
+
+        let paths = output_paths(Path::new("out"), "{stem}_{index}.{ext}", &inputs).unwrap();
+        assert_eq!(paths[0].1, Path::new("out/x_0.png"));
+        assert_eq!(paths[1].1, Path::new("out/x_1.png"));
+    }
+
+    #[test]
+    fn test_run_continues_past_failures() {
+        let paths = vec![
+            (PathBuf::from("a.png"), PathBuf::from("out/a.png")),
+            (PathBuf::from("b.png"), PathBuf::from("out/b.png")),
+            (PathBuf::from("c.png"), PathBuf::from("out/c.png")),
+        ];
+
+        let summary = run(&paths, Some(2), |input, _| {
+            if input == Path::new("b.png") {
+                bail!("Corrupt image");
+            }
+
+            Ok(())
+        })
+        .unwrap();
+
+        assert_eq!(summary.succeeded, 2);
+        assert_eq!(summary.failed.len(), 1);
+        assert_eq!(summary.failed[0].0, Path::new("b.png"));
+    }
+
+    #[test]
+    fn test_run_bounds_images_in_flight() {
+        let paths = (0..16)
+            .map(|i| (PathBuf::from(format!("{}.png", i)), PathBuf::from("out")))
+            .collect::<Vec<_>>();
+
+        let in_flight = AtomicUsize::new(0);
+        let most = AtomicUsize::new(0);
+
+        let summary = run(&paths, Some(2), |_, _| {
+            let current = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+            most.fetch_max(current, Ordering::SeqCst);
+
+            // Rows of a filter, of which one is slow, so the thread that is done with the others
+            // waits in a join, where it can steal other work
+            (0..8).into_par_iter().for_each(|row| {
+                let millis = if row == 7 { 20 } else { 1 };
+                std::thread::sleep(std::time::Duration::from_millis(millis));
+            });
+
+            in_flight.fetch_sub(1, Ordering::SeqCst);
+            Ok(())
+        })
+        .unwrap();
+
+        assert_eq!(summary.succeeded, 16);
+        assert!(most.load(Ordering::SeqCst) <= 2, "{:?}", most);
+    }
+}
@@ -0,0 +1,77 @@
+use crate::{compare, mask, transform, Opts};
+use anyhow::{ensure, Context, Result};
+use clap::Clap;
+use filters::{try_blend, Image};
+use image::{DynamicImage, GenericImageView};
+use std::path::PathBuf;
+
+#[derive(Clap, Debug, Clone)]
+pub struct Blend {
+    #[clap(
+        short,
+        long,
+        parse(from_os_str),
+        about = "Image of the input size to blend into the input"
+    )]
+    overlay: PathBuf,
+    #[clap(
+        short,
+        long,
+        parse(from_os_str),
+        about = "Grayscale mask of the input size, white selects the overlay"
+    )]
+    mask: PathBuf,
+    #[clap(
+        short,
+        long,
+        default_value = "6",
+        about = "Number of pyramid levels, more blend coarse features over a wider seam"
+    )]
+    levels: usize,
+    #[clap(
+        short,
+        long,
+        default_value = "1.0",
+        about = "Sigma of the blur before each halving"
+    )]
+    sigma: f32,
+}
+
+/// Blend the overlay into an image with multi-band blending
+pub fn blend_image(opts: &Opts, image: &DynamicImage, blend: &Blend) -> Result<DynamicImage> {
+    let (width, height) = image.dimensions();
+    let overlay = compare::open(opts, &blend.overlay)?;
+
+    ensure!(
+        overlay.dimensions() == (width, height),
+        "Overlay is {}×{}, but the image is {}×{}",
+        overlay.width(),
+        overlay.height(),
+        width,
+        height
+    );
+
+    let mask = mask::open_mask(&blend.mask, width, height)?;
+
+    // Blend in the color type with the most channels, so no channel is discarded
+    let channels = image
+        .color()
+        .channel_count()
+        .max(overlay.color().channel_count());
+    let overlay = compare::raw_pixels(&overlay, channels);
+    let mut buf_read = compare::raw_pixels(image, channels);
+    let mut buf_write = buf_read.clone();
+
+    let img = Image {
+        width,
+        height,
+        channels: channels as usize,
+        buf_read: &mut buf_read,
+        buf_write: &mut buf_write,
+    };
+
+    let buf = try_blend(&img, &overlay, &mask, blend.levels, blend.sigma)
+        .context("Failed to blend images")?;
+
+    transform::image_from_pixels(width, height, channels as usize, buf)
+}
@@ -0,0 +1,96 @@
+use crate::input::Input;
+use crate::{draw, io, Opts};
+use anyhow::{anyhow, Context, Result};
+use clap::Clap;
+use filters::{try_detect_blobs, ScaleSpace};
+use image::GenericImageView;
+use serde_json::json;
+
+#[derive(Clap, Debug, Clone)]
+pub struct Blobs {
+    #[clap(
+        short,
+        long,
+        default_value = "dog",
+        parse(try_from_str = parse_scale_space),
+        about = "Scale space: dog or log"
+    )]
+    method: ScaleSpace,
+    #[clap(long, default_value = "1.0", about = "Sigma of the smallest blobs")]
+    min_sigma: f32,
+    #[clap(long, default_value = "16.0", about = "Sigma of the largest blobs")]
+    max_sigma: f32,
+    #[clap(
+        long,
+        default_value = "9",
+        about = "Number of sigmas from --min-sigma to --max-sigma"
+    )]
+    scales: usize,
+    #[clap(
+        short,
+        long,
+        default_value = "10.0",
+        about = "Smallest response of a blob on the 8-bit scale"
+    )]
+    threshold: f32,
+    #[clap(long, about = "Draw the blobs onto the input and save it to --output")]
+    draw: bool,
+}
+
+fn parse_scale_space(space: &str) -> Result<ScaleSpace> {
+    match space {
+        "dog" => Ok(ScaleSpace::DoG),
+        "log" => Ok(ScaleSpace::LoG),
+        _ => Err(anyhow!("Unknown scale space {:?}", space)),
+    }
+}
+
+impl Blobs {
+    fn params(&self) -> filters::Blobs {
+        filters::Blobs {
+            space: self.method,
+            min_sigma: self.min_sigma,
+            max_sigma: self.max_sigma,
+            scales: self.scales,
+            threshold: self.threshold,
+        }
+    }
+}
+
+/// Detect blobs in the input and print them as JSON, strongest first
+pub fn run(opts: &Opts, blobs: &Blobs) -> Result<()> {
+    draw::check_output(opts, blobs.draw, "Blobs")?;
+
+    let mut input = Input::open(opts, "Blobs")?;
+    let (width, height) = input.image.dimensions();
+
+    let keypoints =
+        try_detect_blobs(&input.pixels(), blobs.params()).context("Failed to detect blobs")?;
+
+    let json = json!({
+        "width": width,
+        "height": height,
+        "blobs": keypoints.iter().map(|keypoint| json!({
+            "x": keypoint.x,
+            "y": keypoint.y,
+            "sigma": keypoint.sigma,
+            "radius": keypoint.radius(),
+            "response": keypoint.response,
+        })).collect::<Vec<_>>(),
+    });
+
+    io::print_json(&json)?;
+
+    if blobs.draw {
+        let mut annotated = input.image.to_rgb8();
+
+        for keypoint in &keypoints {
+            let center = (keypoint.x as f32, keypoint.y as f32);
+            draw::circle(&mut annotated, center, keypoint.radius(), draw::COLOR);
+        }
+
+        draw::save(opts, annotated)?;
+    }
+
+    Ok(())
+}
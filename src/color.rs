@@ -0,0 +1,187 @@
+use anyhow::{anyhow, Result};
+use clap::Clap;
+use filters::{
+    try_in_color_space, try_with_channels, Channels, ColorSpace, FilterError, Image, Luma,
+};
+
+#[derive(Clap, Debug, Clone, Default)]
+pub struct ColorOptions {
+    #[clap(
+        long,
+        parse(try_from_str = parse_filter_color_space),
+        about = "Filter only the lightness in hsv, hsl, ycbcr or lab, i.e. Y of YCbCr"
+    )]
+    pub color_space: Option<ColorSpace>,
+    #[clap(
+        long,
+        about = "Channels to filter, i.e. r,g or alpha, named after --color-space"
+    )]
+    pub channels: Option<String>,
+}
+
+impl ColorOptions {
+    /// Whether channels are filtered on their own, as the luma of RGB would mix them
+    pub fn per_channel(&self) -> bool {
+        self.channels.is_some() || !matches!(self.color_space, None | Some(ColorSpace::Rgb))
+    }
+}
+
+pub fn parse_color_space(space: &str) -> Result<ColorSpace> {
+    match space {
+        "rgb" => Ok(ColorSpace::Rgb),
+        "gray" => Ok(ColorSpace::Gray(Luma::default())),
+        "hsv" => Ok(ColorSpace::Hsv),
+        "hsl" => Ok(ColorSpace::Hsl),
+        "ycbcr" => Ok(ColorSpace::YCbCr),
+        "lab" => Ok(ColorSpace::Lab),
+        _ => Err(anyhow!("Unknown color space {:?}", space)),
+    }
+}
+
+/// Parse the color space of `--color-space`, which has to keep the colors of the image
+fn parse_filter_color_space(space: &str) -> Result<ColorSpace> {
+    match parse_color_space(space)? {
+        ColorSpace::Gray(_) => Err(anyhow!(
+            "Filtering in gray would drop the colors, use convert --to gray first"
+        )),
+        space => Ok(space),
+    }
+}
+
+pub fn parse_luma(luma: &str) -> Result<Luma> {
+    match luma {
+        "bt601" => Ok(Luma::Bt601),
+        "bt709" => Ok(Luma::Bt709),
+        "average" => Ok(Luma::Average),
+        _ => Err(anyhow!("Unknown luma {:?}", luma)),
+    }
+}
+
+/// Resolve a comma-separated list of channel names of a color space, alpha is always `alpha`
+pub fn parse_channels(names: &str, space: ColorSpace) -> Result<Channels, FilterError> {
+    let space_names = space.channel_names();
+
+    let indices = names
+        .split(',')
+        .map(|name| match name.trim() {
+            "alpha" => Ok(3),
+            // `a` is a* in Lab
+            "a" if space == ColorSpace::Rgb => Ok(3),
+            name => space_names.iter().position(|&n| n == name).ok_or_else(|| {
+                FilterError::InvalidParameter(format!(
+                    "Unknown channel {:?}, expected one of {} or alpha",
+                    name,
+                    space_names.join(", ")
+                ))
+            }),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(Channels::from_indices(&indices))
+}
+
+/// Run a filter in the color space of `--color-space` on the channels of `--channels`
+///
+/// In a color space other than RGB, only the lightness is filtered by default.
+pub fn apply<F>(options: &ColorOptions, image: &mut Image<u8>, filter: F) -> Result<(), FilterError>
+where
+    F: FnOnce(&mut Image<u8>) -> Result<(), FilterError>,
+{
+    let space = options.color_space.unwrap_or(ColorSpace::Rgb);
+
+    let channels = match (&options.channels, space) {
+        (Some(names), _) => parse_channels(names, space)?,
+        (None, ColorSpace::Rgb) => Channels::ALL,
+        (None, space) => Channels::from_indices(&[space.lightness()]),
+    };
+
+    if space == ColorSpace::Rgb {
+        return try_with_channels(image, channels, filter);
+    }
+
+    try_in_color_space(image, space, |image| {
+        try_with_channels(image, channels, filter)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_apply_lightness() {
+        // Orange and blue, which a blur of all channels would mix
+        let mut buf_read = vec![255, 128, 0, 255, 0, 64, 255, 255];
+        let mut buf_write = buf_read.clone();
+
+        let options = ColorOptions {
+            color_space: Some(ColorSpace::YCbCr),
+            channels: None,
+        };
+
+        apply(
+            &options,
+            &mut Image {
+                buf_read: &mut buf_read,
+                buf_write: &mut buf_write,
+                width: 2,
+                height: 1,
+                channels: 4,
+            },
+            |image| filters::try_box_blur_2d(image, 1),
+        )
+        .unwrap();
+
+        // Both pixels keep their own hue, but their luma moves closer together
+        assert!(buf_write[0] > buf_write[2] && buf_write[6] > buf_write[4]);
+
+        let luma = |p: &[u8]| Luma::Bt601.luma(p[0] as f32, p[1] as f32, p[2] as f32);
+        let difference = |p: &[u8]| (luma(&p[..4]) - luma(&p[4..])).abs();
+
+        assert!(difference(&buf_write) < difference(&[255, 128, 0, 255, 0, 64, 255, 255]) / 2.0);
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(parse_color_space("lab").unwrap(), ColorSpace::Lab);
+        assert!(parse_color_space("cmyk").is_err());
+        assert_eq!(parse_filter_color_space("hsv").unwrap(), ColorSpace::Hsv);
+        assert!(parse_filter_color_space("gray").is_err());
+        assert_eq!(parse_luma("bt709").unwrap(), Luma::Bt709);
+        assert!(parse_luma("bt2020").is_err());
+    }
+
+    #[test]
+    fn test_per_channel() {
+        let options = |color_space, channels: Option<&str>| ColorOptions {
+            color_space,
+            channels: channels.map(String::from),
+        };
+
+        assert!(!options(None, None).per_channel());
+        assert!(!options(Some(ColorSpace::Rgb), None).per_channel());
+        assert!(options(Some(ColorSpace::Lab), None).per_channel());
+        assert!(options(None, Some("r,g")).per_channel());
+    }
+
+    #[test]
+    fn test_parse_channels() {
+        let rgb = parse_channels("r,alpha", ColorSpace::Rgb).unwrap();
+        assert!(rgb.contains(0) && rgb.contains(3) && !rgb.contains(1));
+
+        assert_eq!(
+            parse_channels("a", ColorSpace::Rgb).unwrap(),
+            Channels::from_indices(&[3])
+        );
+        assert_eq!(
+            parse_channels("a,b", ColorSpace::Lab).unwrap(),
+            Channels::from_indices(&[1, 2])
+        );
+        assert_eq!(
+            parse_channels("cr", ColorSpace::YCbCr).unwrap(),
+            Channels::from_indices(&[2])
+        );
+
+        assert!(parse_channels("y", ColorSpace::Rgb).is_err());
+    }
+}
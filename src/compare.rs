@@ -0,0 +1,198 @@
+use crate::metadata::{self, Metadata, Orientation};
+use crate::{io, Opts};
+use anyhow::{ensure, Context, Result};
+use clap::Clap;
+use filters::{try_compare, try_difference, Metrics};
+use image::{DynamicImage, GenericImageView, ImageBuffer, RgbImage};
+use serde_json::json;
+use std::path::{Path, PathBuf};
+
+#[derive(Clap, Debug, Clone)]
+pub struct Compare {
+    #[clap(
+        short,
+        long,
+        parse(from_os_str),
+        about = "Reference image to compare the input with"
+    )]
+    reference: PathBuf,
+    #[clap(long, about = "Print the metrics as JSON")]
+    json: bool,
+    #[clap(
+        long,
+        parse(from_os_str),
+        about = "Write a heatmap of the largest difference of each pixel"
+    )]
+    heatmap: Option<PathBuf>,
+}
+
+/// Compare the input with the reference and print MSE, PSNR, SSIM and the maximum error
+pub fn run(opts: &Opts, compare: &Compare) -> Result<()> {
+    ensure!(opts.input.len() == 1, "Compare takes a single --input");
+
+    let actual = open(opts, &opts.input[0])?;
+    let expect = open(opts, &compare.reference)?;
+
+    let (width, height) = expect.dimensions();
+
+    ensure!(
+        actual.dimensions() == (width, height),
+        "Input is {}×{}, but the reference is {}×{}",
+        actual.width(),
+        actual.height(),
+        width,
+        height
+    );
+
+    // Compare in the color type with the most channels, so no channel is discarded
+    let channels = expect
+        .color()
+        .channel_count()
+        .max(actual.color().channel_count());
+
+    let expect = raw_pixels(&expect, channels);
+    let actual = raw_pixels(&actual, channels);
+    let channels = channels as usize;
+
+    let metrics = try_compare(&expect, &actual, width, height, channels)
+        .context("Failed to compare images")?;
+
+    if let Some(ref path) = compare.heatmap {
+        ensure!(
+            io::is_stdio(path) || !path.exists() || opts.force,
+            "Heatmap {:?} exists. To overwrite files, use --force.",
+            path.display()
+        );
+
+        let difference = try_difference(&expect, &actual, width, height, channels)?;
+        let heatmap = heatmap(width, height, &difference);
+
+        io::save_image(
+            &DynamicImage::ImageRgb8(heatmap),
+            &Metadata::default(),
+            path,
+            &opts.encode,
+        )?;
+    }
+
+    let names = channel_names(channels);
+
+    if compare.json {
+        // Infinite PSNR of identical images is written as null
+        let json = json!({
+            "width": width,
+            "height": height,
+            "channels": metrics.channels.iter().zip(names).map(|(metrics, name)| json!({
+                "channel": name,
+                "mse": metrics.mse,
+                "psnr": metrics.psnr,
+                "ssim": metrics.ssim,
+                "max_error": metrics.max_error,
+            })).collect::<Vec<_>>(),
+            "mse": metrics.mse,
+            "psnr": metrics.psnr,
+            "ssim": metrics.ssim,
+            "max_error": metrics.max_error,
+        });
+
+        io::print_json(&json)?;
+    } else {
+        print_table(&metrics, names);
+    }
+
+    Ok(())
+}
+
+/// Decode an image upright, as it is filtered
+pub fn open(opts: &Opts, path: &Path) -> Result<DynamicImage> {
+    let bytes = io::read_input(path)?;
+    let (mut image, metadata) = io::decode_image(&bytes, path)?;
+
+    if let (Orientation::Apply, Some(orientation)) = (opts.orientation, metadata.orientation()) {
+        image = metadata::apply_orientation(image, orientation);
+    }
+
+    Ok(image)
+}
+
+/// Pixels of an image as 8-bit values, converted to a color type with the given channels
+pub fn raw_pixels(image: &DynamicImage, channels: u8) -> Vec<u8> {
+    match channels {
+        1 => image.to_luma8().into_raw(),
+        2 => image.to_luma_alpha8().into_raw(),
+        3 => image.to_rgb8().into_raw(),
+        _ => image.to_rgba8().into_raw(),
+    }
+}
+
+pub fn channel_names(channels: usize) -> &'static [&'static str] {
+    match channels {
+        1 => &["L"],
+        2 => &["L", "A"],
+        3 => &["R", "G", "B"],
+        _ => &["R", "G", "B", "A"],
+    }
+}
+
+fn print_table(metrics: &Metrics, names: &[&str]) {
+    println!(
+        "{:<8} {:>10} {:>10} {:>8} {:>10}",
+        "Channel", "MSE", "PSNR (dB)", "SSIM", "Max error"
+    );
+
+    let rows = metrics
+        .channels
+        .iter()
+        .zip(names)
+        .map(|(m, name)| (*name, m.mse, m.psnr, m.ssim, m.max_error))
+        .chain(std::iter::once((
+            "All",
+            metrics.mse,
+            metrics.psnr,
+            metrics.ssim,
+            metrics.max_error,
+        )));
+
+    for (name, mse, psnr, ssim, max_error) in rows {
+        println!(
+            "{:<8} {:>10.3} {:>10.2} {:>8.4} {:>10}",
+            name, mse, psnr, ssim, max_error
+        );
+    }
+}
+
+/// Color the differences from black through red and yellow to white, scaled to the largest
+/// difference so small errors remain visible
+fn heatmap(width: u32, height: u32, difference: &[u8]) -> RgbImage {
+    let max = difference.iter().copied().max().unwrap_or(0).max(1) as f32;
+
+    let pixels = difference
+        .iter()
+        .flat_map(|&d| {
+            let t = d as f32 / max * 3.0;
+
+            vec![channel(t), channel(t - 1.0), channel(t - 2.0)]
+        })
+        .collect();
+
+    ImageBuffer::from_raw(width, height, pixels).expect("Heatmap buffer matches its size")
+}
+
+fn channel(value: f32) -> u8 {
+    (value.min(1.0).max(0.0) * 255.0).round() as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_heatmap() {
+        let heatmap = heatmap(4, 1, &[0, 2, 4, 6]);
+
+        assert_eq!(
+            heatmap.into_raw(),
+            vec![0, 0, 0, 255, 0, 0, 255, 255, 0, 255, 255, 255]
+        );
+    }
+}
@@ -0,0 +1,121 @@
+use crate::input::Input;
+use crate::{draw, io, Opts};
+use anyhow::{anyhow, Context, Result};
+use clap::Clap;
+use filters::{try_detect_corners, CornerResponse};
+use image::GenericImageView;
+use serde_json::json;
+
+/// Half the length of the crosses drawn at corners
+const CROSS_SIZE: f32 = 4.0;
+
+#[derive(Clap, Debug, Clone)]
+pub struct Corners {
+    #[clap(
+        short,
+        long,
+        default_value = "harris",
+        parse(try_from_str = parse_corner_response),
+        about = "Corner response: harris or shi-tomasi"
+    )]
+    method: CornerResponse,
+    #[clap(
+        short,
+        default_value = "0.04",
+        about = "Sensitivity of Harris' response, lower finds more corners"
+    )]
+    k: f32,
+    #[clap(
+        short,
+        long,
+        default_value = "1.0",
+        about = "Sigma of the window over which gradients are summed"
+    )]
+    sigma: f32,
+    #[clap(
+        short,
+        long,
+        default_value = "0.01",
+        about = "Smallest response relative to the strongest corner, from 0 to 1"
+    )]
+    quality: f32,
+    #[clap(
+        short = 'd',
+        long,
+        default_value = "3",
+        about = "Radius within which a corner has the largest response"
+    )]
+    min_distance: u32,
+    #[clap(
+        short = 'n',
+        long,
+        about = "Largest number of corners, strongest first"
+    )]
+    max_corners: Option<usize>,
+    #[clap(
+        long,
+        about = "Draw the corners onto the input and save it to --output"
+    )]
+    draw: bool,
+}
+
+fn parse_corner_response(response: &str) -> Result<CornerResponse> {
+    match response {
+        "harris" => Ok(CornerResponse::Harris { k: 0.04 }),
+        "shi-tomasi" => Ok(CornerResponse::ShiTomasi),
+        _ => Err(anyhow!("Unknown corner response {:?}", response)),
+    }
+}
+
+impl Corners {
+    fn params(&self) -> filters::Corners {
+        let response = match self.method {
+            CornerResponse::Harris { .. } => CornerResponse::Harris { k: self.k },
+            response => response,
+        };
+
+        filters::Corners {
+            response,
+            sigma: self.sigma,
+            quality: self.quality,
+            min_distance: self.min_distance,
+            max_corners: self.max_corners,
+        }
+    }
+}
+
+/// Detect corners in the input and print them as JSON, strongest first
+pub fn run(opts: &Opts, corners: &Corners) -> Result<()> {
+    draw::check_output(opts, corners.draw, "Corners")?;
+
+    let mut input = Input::open(opts, "Corners")?;
+    let (width, height) = input.image.dimensions();
+
+    let found = try_detect_corners(&input.pixels(), corners.params())
+        .context("Failed to detect corners")?;
+
+    let json = json!({
+        "width": width,
+        "height": height,
+        "corners": found.iter().map(|corner| json!({
+            "x": corner.x,
+            "y": corner.y,
+            "response": corner.response,
+        })).collect::<Vec<_>>(),
+    });
+
+    io::print_json(&json)?;
+
+    if corners.draw {
+        let mut annotated = input.image.to_rgb8();
+
+        for corner in &found {
+            let center = (corner.x as f32, corner.y as f32);
+            draw::cross(&mut annotated, center, CROSS_SIZE, draw::COLOR);
+        }
+
+        draw::save(opts, annotated)?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,119 @@
+use crate::metadata::Metadata;
+use crate::{io, Opts};
+use anyhow::{ensure, Result};
+use image::{DynamicImage, Rgb, RgbImage};
+
+/// Color of the shapes drawn onto annotated images
+pub const COLOR: Rgb<u8> = Rgb([255, 0, 0]);
+
+/// Draw the outline of a circle, one pixel wide
+pub fn circle(image: &mut RgbImage, (cx, cy): (f32, f32), radius: f32, color: Rgb<u8>) {
+    // Enough steps to leave no gaps between neighbouring pixels
+    let steps = (radius * std::f32::consts::PI * 2.0).ceil().max(8.0) as u32;
+
+    for step in 0..steps {
+        let angle = step as f32 / steps as f32 * std::f32::consts::PI * 2.0;
+        let (sin, cos) = angle.sin_cos();
+
+        put(image, cx + radius * cos, cy + radius * sin, color);
+    }
+}
+
+/// Draw a line between two points, one pixel wide
+pub fn line(image: &mut RgbImage, (x1, y1): (f32, f32), (x2, y2): (f32, f32), color: Rgb<u8>) {
+    let steps = (x2 - x1).abs().max((y2 - y1).abs()).ceil().max(1.0) as u32;
+
+    for step in 0..=steps {
+        let t = step as f32 / steps as f32;
+
+        put(image, x1 + (x2 - x1) * t, y1 + (y2 - y1) * t, color);
+    }
+}
+
+/// Draw a cross of two lines from `size` pixels left of and above the center to as far right and
+/// below
+pub fn cross(image: &mut RgbImage, (cx, cy): (f32, f32), size: f32, color: Rgb<u8>) {
+    let size = size.round() as i32;
+
+    for offset in -size..=size {
+        put(image, cx + offset as f32, cy, color);
+        put(image, cx, cy + offset as f32, color);
+    }
+}
+
+/// Set the pixel at a position, if it lies within the image
+fn put(image: &mut RgbImage, x: f32, y: f32, color: Rgb<u8>) {
+    let (x, y) = (x.round(), y.round());
+
+    if x >= 0.0 && y >= 0.0 && x < image.width() as f32 && y < image.height() as f32 {
+        image.put_pixel(x as u32, y as u32, color);
+    }
+}
+
+/// Check that `--draw` has a file to save to, as the results are printed to stdout
+pub fn check_output(opts: &Opts, draw: bool, results: &str) -> Result<()> {
+    ensure!(
+        !(draw && io::is_stdio(&opts.output)),
+        "{} are printed to stdout, so --draw needs an --output file",
+        results
+    );
+
+    Ok(())
+}
+
+/// Save an annotated image to `--output`
+pub fn save(opts: &Opts, image: RgbImage) -> Result<()> {
+    ensure!(
+        io::is_stdio(&opts.output) || !opts.output.exists() || opts.force,
+        "Output {:?} exists. To overwrite files, use --force.",
+        opts.output.display()
+    );
+
+    io::save_image(
+        &DynamicImage::ImageRgb8(image),
+        &Metadata::default(),
+        &opts.output,
+        &opts.encode,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_circle() {
+        let mut image = RgbImage::new(9, 9);
+
+        circle(&mut image, (4.0, 4.0), 3.0, COLOR);
+        assert_eq!(*image.get_pixel(7, 4), COLOR);
+        assert_eq!(*image.get_pixel(4, 1), COLOR);
+        assert_eq!(*image.get_pixel(4, 4), Rgb([0, 0, 0]));
+
+        // Pixels beyond the edges are clipped
+        circle(&mut image, (0.0, 0.0), 2.0, COLOR);
+        assert_eq!(*image.get_pixel(2, 0), COLOR);
+    }
+
+    #[test]
+    fn test_line() {
+        let mut image = RgbImage::new(9, 9);
+
+        line(&mut image, (1.0, 1.0), (7.0, 4.0), COLOR);
+        assert_eq!(*image.get_pixel(1, 1), COLOR);
+        assert_eq!(*image.get_pixel(7, 4), COLOR);
+        assert_eq!(image.pixels().filter(|&&p| p == COLOR).count(), 7);
+    }
+
+    #[test]
+    fn test_cross() {
+        let mut image = RgbImage::new(9, 9);
+
+        cross(&mut image, (4.0, 4.0), 2.0, COLOR);
+        assert_eq!(*image.get_pixel(2, 4), COLOR);
+        assert_eq!(*image.get_pixel(4, 6), COLOR);
+        assert_eq!(*image.get_pixel(4, 4), COLOR);
+        assert_eq!(*image.get_pixel(5, 5), Rgb([0, 0, 0]));
+        assert_eq!(*image.get_pixel(1, 4), Rgb([0, 0, 0]));
+    }
+}
@@ -0,0 +1,39 @@
+use crate::compare::{channel_names, raw_pixels};
+use crate::io;
+use anyhow::{ensure, Result};
+use filters::{try_histogram, try_luma_histogram};
+use image::DynamicImage;
+use serde_json::json;
+use std::path::Path;
+
+/// Write the histogram of each channel and of luma as JSON, to a file or to stdout if the path
+/// is `-`
+pub fn save_histogram(image: &DynamicImage, path: &Path, force: bool) -> Result<()> {
+    ensure!(
+        io::is_stdio(path) || !path.exists() || force,
+        "Histogram {:?} exists. To overwrite files, use --force.",
+        path.display()
+    );
+
+    // Higher bit depths are binned as 8-bit values
+    let channels = image.color().channel_count();
+    let pixels = raw_pixels(image, channels);
+    let channels = channels as usize;
+
+    let histograms = try_histogram(&pixels, channels)?;
+    let luma = try_luma_histogram(&pixels, channels)?;
+
+    let json = json!({
+        "channels": channel_names(channels)
+            .iter()
+            .zip(&histograms)
+            .map(|(name, histogram)| json!({
+                "channel": name,
+                "counts": histogram.to_vec(),
+            }))
+            .collect::<Vec<_>>(),
+        "luma": luma.to_vec(),
+    });
+
+    io::write_output(path, format!("{}\n", json).as_bytes())
+}
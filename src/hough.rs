@@ -0,0 +1,217 @@
+use crate::input::Input;
+use crate::{compare, draw, io, Opts};
+use anyhow::{ensure, Context, Result};
+use clap::Clap;
+use filters::{try_hough_circles, try_hough_lines, HoughCircles, HoughLines};
+use image::GenericImageView;
+use serde_json::json;
+use std::path::PathBuf;
+
+/// Half the length of the crosses drawn at the centers of circles
+const CROSS_SIZE: f32 = 4.0;
+
+#[derive(Clap, Debug, Clone)]
+pub struct Lines {
+    #[clap(
+        long,
+        default_value = "1.0",
+        about = "Resolution of the distance from the origin in pixels"
+    )]
+    rho: f32,
+    #[clap(
+        long,
+        default_value = "1.0",
+        about = "Resolution of the angle in degrees"
+    )]
+    theta: f32,
+    #[clap(
+        short,
+        long,
+        default_value = "100",
+        about = "Smallest number of edge pixels on a line"
+    )]
+    threshold: u32,
+    #[clap(
+        short = 'd',
+        long,
+        default_value = "10",
+        about = "Distance in bins of --rho and --theta within which a line has the most votes"
+    )]
+    min_distance: usize,
+    #[clap(short = 'n', long, about = "Largest number of lines, strongest first")]
+    max_lines: Option<usize>,
+    #[clap(long, about = "Draw the lines onto the input and save it to --output")]
+    draw: bool,
+    #[clap(
+        long,
+        parse(from_os_str),
+        requires = "draw",
+        about = "Image of the input size to draw onto instead of the input"
+    )]
+    onto: Option<PathBuf>,
+}
+
+#[derive(Clap, Debug, Clone)]
+pub struct Circles {
+    #[clap(long, default_value = "5.0", about = "Smallest radius in pixels")]
+    min_radius: f32,
+    #[clap(long, default_value = "100.0", about = "Largest radius in pixels")]
+    max_radius: f32,
+    #[clap(
+        short,
+        long,
+        about = "Blur strength (sigma) before the gradients are found"
+    )]
+    sigma: Option<f32>,
+    #[clap(
+        short,
+        long,
+        default_value = "100.0",
+        about = "Smallest gradient magnitude of an edge pixel, on the scale of sobel_2d"
+    )]
+    edge: f32,
+    #[clap(
+        short,
+        long,
+        default_value = "0.5",
+        about = "Smallest share of the circle within the image that lies on edges, from 0 to 1"
+    )]
+    threshold: f32,
+    #[clap(
+        short = 'd',
+        long,
+        default_value = "10.0",
+        about = "Smallest distance between the centers of two circles"
+    )]
+    min_distance: f32,
+    #[clap(
+        short = 'n',
+        long,
+        about = "Largest number of circles, strongest first"
+    )]
+    max_circles: Option<usize>,
+    #[clap(
+        long,
+        about = "Draw the circles onto the input and save it to --output"
+    )]
+    draw: bool,
+}
+
+impl Lines {
+    fn params(&self) -> HoughLines {
+        HoughLines {
+            rho: self.rho,
+            theta: self.theta,
+            threshold: self.threshold,
+            min_distance: self.min_distance,
+            max_lines: self.max_lines,
+        }
+    }
+}
+
+impl Circles {
+    fn params(&self) -> HoughCircles {
+        HoughCircles {
+            min_radius: self.min_radius,
+            max_radius: self.max_radius,
+            sigma: self.sigma,
+            edge: self.edge,
+            threshold: self.threshold,
+            min_distance: self.min_distance,
+            max_circles: self.max_circles,
+        }
+    }
+}
+
+/// Detect lines in a binary edge image and print them as JSON, strongest first
+pub fn run_lines(opts: &Opts, lines: &Lines) -> Result<()> {
+    draw::check_output(opts, lines.draw, "Lines")?;
+
+    let mut input = Input::open(opts, "Lines")?;
+    let (width, height) = input.image.dimensions();
+
+    let found =
+        try_hough_lines(&input.pixels(), lines.params()).context("Failed to detect lines")?;
+    let segments = found
+        .iter()
+        .map(|line| line.segment(width, height))
+        .collect::<Vec<_>>();
+
+    let json = json!({
+        "width": width,
+        "height": height,
+        "lines": found.iter().zip(&segments).map(|(line, segment)| json!({
+            "rho": line.rho,
+            "theta": line.theta,
+            "angle": line.angle(),
+            "votes": line.votes,
+            "segment": segment.map(|((x1, y1), (x2, y2))| [x1, y1, x2, y2]),
+        })).collect::<Vec<_>>(),
+    });
+
+    io::print_json(&json)?;
+
+    if lines.draw {
+        let background = match lines.onto {
+            Some(ref path) => compare::open(opts, path)?,
+            None => input.image,
+        };
+
+        ensure!(
+            background.dimensions() == (width, height),
+            "Image to draw onto is {}×{}, but the input is {}×{}",
+            background.width(),
+            background.height(),
+            width,
+            height
+        );
+
+        let mut annotated = background.to_rgb8();
+
+        for &(from, to) in segments.iter().flatten() {
+            draw::line(&mut annotated, from, to, draw::COLOR);
+        }
+
+        draw::save(opts, annotated)?;
+    }
+
+    Ok(())
+}
+
+/// Detect circles in the input and print them as JSON, strongest first
+pub fn run_circles(opts: &Opts, circles: &Circles) -> Result<()> {
+    draw::check_output(opts, circles.draw, "Circles")?;
+
+    let mut input = Input::open(opts, "Circles")?;
+    let (width, height) = input.image.dimensions();
+
+    let found =
+        try_hough_circles(&input.pixels(), circles.params()).context("Failed to detect circles")?;
+
+    let json = json!({
+        "width": width,
+        "height": height,
+        "circles": found.iter().map(|circle| json!({
+            "x": circle.x,
+            "y": circle.y,
+            "radius": circle.radius,
+            "score": circle.score,
+        })).collect::<Vec<_>>(),
+    });
+
+    io::print_json(&json)?;
+
+    if circles.draw {
+        let mut annotated = input.image.to_rgb8();
+
+        for circle in &found {
+            let center = (circle.x, circle.y);
+            draw::circle(&mut annotated, center, circle.radius, draw::COLOR);
+            draw::cross(&mut annotated, center, CROSS_SIZE, draw::COLOR);
+        }
+
+        draw::save(opts, annotated)?;
+    }
+
+    Ok(())
+}
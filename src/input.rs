@@ -0,0 +1,42 @@
+use crate::{compare, Opts};
+use anyhow::{ensure, Result};
+use filters::Image;
+use image::{DynamicImage, GenericImageView};
+
+/// The single `--input` of a subcommand that analyses one image, with its 8-bit pixels
+pub struct Input {
+    pub image: DynamicImage,
+    channels: usize,
+    buf_read: Vec<u8>,
+    buf_write: Vec<u8>,
+}
+
+impl Input {
+    /// Open the input of a subcommand, named in the error if there is more than one
+    pub fn open(opts: &Opts, command: &str) -> Result<Input> {
+        ensure!(opts.input.len() == 1, "{} takes a single --input", command);
+
+        let image = compare::open(opts, &opts.input[0])?;
+        let channels = image.color().channel_count();
+        let buf_read = compare::raw_pixels(&image, channels);
+        let buf_write = buf_read.clone();
+
+        Ok(Input {
+            image,
+            channels: channels as usize,
+            buf_read,
+            buf_write,
+        })
+    }
+
+    /// The pixels as the filters take them
+    pub fn pixels(&mut self) -> Image<'_, u8> {
+        Image {
+            width: self.image.width(),
+            height: self.image.height(),
+            channels: self.channels,
+            buf_read: &mut self.buf_read,
+            buf_write: &mut self.buf_write,
+        }
+    }
+}
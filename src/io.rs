@@ -0,0 +1,246 @@
+use crate::metadata::Metadata;
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use clap::Clap;
+use image::{
+    codecs::{
+        jpeg::JpegEncoder,
+        png::{CompressionType, FilterType, PngEncoder},
+    },
+    ColorType, DynamicImage, GenericImageView, ImageEncoder, ImageFormat,
+};
+use std::borrow::Cow;
+use std::io::{Read, Write};
+use std::path::Path;
+
+#[derive(Clap, Debug, Clone, Copy)]
+pub struct EncodeOptions {
+    #[clap(
+        long,
+        parse(try_from_str = parse_format),
+        about = "Output format, i.e. png or jpg, required when writing to stdout"
+    )]
+    pub format: Option<ImageFormat>,
+    #[clap(
+        long,
+        default_value = "75",
+        parse(try_from_str = parse_jpeg_quality),
+        about = "JPEG quality from 1 to 100"
+    )]
+    pub jpeg_quality: u8,
+    #[clap(
+        long,
+        default_value = "fast",
+        parse(try_from_str = parse_png_compression),
+        about = "PNG compression: fast, default, best, huffman or rle"
+    )]
+    pub png_compression: CompressionType,
+    #[clap(
+        long,
+        default_value = "sub",
+        parse(try_from_str = parse_png_filter),
+        about = "PNG filter: none, sub, up, avg or paeth"
+    )]
+    pub png_filter: FilterType,
+    #[clap(long, about = "Write PNG with 16 bits per channel")]
+    pub png_16bit: bool,
+}
+
+/// Returns true if the path refers to stdin or stdout
+pub fn is_stdio(path: &Path) -> bool {
+    path == Path::new("-")
+}
+
+/// Parse an output format from its name or file extension, i.e. `png` or `jpg`
+pub fn parse_format(format: &str) -> Result<ImageFormat> {
+    ImageFormat::from_extension(format).ok_or_else(|| anyhow!("Unknown image format {:?}", format))
+}
+
+fn parse_jpeg_quality(quality: &str) -> Result<u8> {
+    let quality = quality.parse::<u8>()?;
+
+    ensure!(
+        (1..=100).contains(&quality),
+        "JPEG quality should be between 1 and 100"
+    );
+
+    Ok(quality)
+}
+
+fn parse_png_compression(compression: &str) -> Result<CompressionType> {
+    match compression {
+        "fast" => Ok(CompressionType::Fast),
+        "default" => Ok(CompressionType::Default),
+        "best" => Ok(CompressionType::Best),
+        "huffman" => Ok(CompressionType::Huffman),
+        "rle" => Ok(CompressionType::Rle),
+        _ => Err(anyhow!("Unknown PNG compression {:?}", compression)),
+    }
+}
+
+fn parse_png_filter(filter: &str) -> Result<FilterType> {
+    match filter {
+        "none" => Ok(FilterType::NoFilter),
+        "sub" => Ok(FilterType::Sub),
+        "up" => Ok(FilterType::Up),
+        "avg" => Ok(FilterType::Avg),
+        "paeth" => Ok(FilterType::Paeth),
+        _ => Err(anyhow!("Unknown PNG filter {:?}", filter)),
+    }
+}
+
+/// Read the encoded bytes of a file, or of stdin if the path is `-`
+pub fn read_input(path: &Path) -> Result<Vec<u8>> {
+    if is_stdio(path) {
+        let mut bytes = Vec::new();
+
+        std::io::stdin()
+            .read_to_end(&mut bytes)
+            .context("Failed to read from stdin")?;
+
+        return Ok(bytes);
+    }
+
+    std::fs::read(path).with_context(|| format!("Failed to open file {:?}", path.display()))
+}
+
+/// Decode an image and its metadata read from `path`
+///
+/// The format of stdin is detected from its magic bytes, as there is no extension to go by.
+pub fn decode_image(bytes: &[u8], path: &Path) -> Result<(DynamicImage, Metadata)> {
+    let image = match ImageFormat::from_path(path) {
+        Ok(format) => image::load_from_memory_with_format(bytes, format),
+        Err(_) => image::load_from_memory(bytes),
+    }
+    .with_context(|| format!("Failed to decode file {:?}", path.display()))?;
+
+    Ok((image, Metadata::read(bytes)))
+}
+
+/// The format to write to a path, without an explicit format the file extension is used
+pub fn output_format(path: &Path, options: &EncodeOptions) -> Result<ImageFormat> {
+    match options.format {
+        Some(format) => Ok(format),
+        None if is_stdio(path) => bail!("Writing to stdout requires --format"),
+        None => ImageFormat::from_path(path)
+            .with_context(|| format!("Unknown image format for {:?}", path.display())),
+    }
+}
+
+/// Save an image and its metadata to a file, or to stdout if the path is `-`
+pub fn save_image(
+    image: &DynamicImage,
+    metadata: &Metadata,
+    path: &Path,
+    options: &EncodeOptions,
+) -> Result<()> {
+    let format = output_format(path, options)?;
+
+    let mut bytes = Vec::new();
+    encode(image, &mut bytes, format, options).context("Failed to encode image")?;
+
+    write_output(path, &metadata.embed(bytes, format))
+}
+
+/// Print the results of a subcommand to stdout as indented JSON
+pub fn print_json(json: &serde_json::Value) -> Result<()> {
+    println!("{}", serde_json::to_string_pretty(json)?);
+
+    Ok(())
+}
+
+/// Write encoded bytes to a file, or to stdout if the path is `-`
+pub fn write_output(path: &Path, bytes: &[u8]) -> Result<()> {
+    if is_stdio(path) {
+        let stdout = std::io::stdout();
+        let mut writer = stdout.lock();
+
+        return writer
+            .write_all(bytes)
+            .and_then(|()| writer.flush())
+            .context("Failed to write to stdout");
+    }
+
+    std::fs::write(path, bytes).with_context(|| format!("Failed to save file {:?}", path.display()))
+}
+
+pub fn encode<W: Write>(
+    image: &DynamicImage,
+    writer: &mut W,
+    format: ImageFormat,
+    options: &EncodeOptions,
+) -> Result<()> {
+    match format {
+        ImageFormat::Jpeg => {
+            let mut encoder = JpegEncoder::new_with_quality(writer, options.jpeg_quality);
+
+            match image.color() {
+                ColorType::L8 | ColorType::La8 | ColorType::L16 | ColorType::La16 => {
+                    encoder.encode_image(&image.to_luma8())?
+                }
+                _ => encoder.encode_image(&image.to_rgb8())?,
+            }
+        }
+        ImageFormat::Png => {
+            let encoder =
+                PngEncoder::new_with_quality(writer, options.png_compression, options.png_filter);
+
+            let image = match png_color(image.color(), options) {
+                color if color == image.color() => Cow::Borrowed(image),
+                ColorType::L16 => Cow::Owned(DynamicImage::ImageLuma16(image.to_luma16())),
+                ColorType::La16 => Cow::Owned(DynamicImage::ImageLumaA16(image.to_luma_alpha16())),
+                ColorType::Rgba16 => Cow::Owned(DynamicImage::ImageRgba16(image.to_rgba16())),
+                _ => Cow::Owned(DynamicImage::ImageRgb16(image.to_rgb16())),
+            };
+
+            encoder.write_image(
+                image.as_bytes(),
+                image.width(),
+                image.height(),
+                image.color(),
+            )?
+        }
+        format => image.write_to(writer, format)?,
+    }
+
+    Ok(())
+}
+
+/// The color type of a PNG written from an image of the given color type
+pub fn png_color(color: ColorType, options: &EncodeOptions) -> ColorType {
+    match (options.png_16bit, color.has_color(), color.has_alpha()) {
+        (false, _, _) => color,
+        (true, false, false) => ColorType::L16,
+        (true, false, true) => ColorType::La16,
+        (true, true, false) => ColorType::Rgb16,
+        (true, true, true) => ColorType::Rgba16,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_format() {
+        assert_eq!(parse_format("png").unwrap(), ImageFormat::Png);
+        assert_eq!(parse_format("JPG").unwrap(), ImageFormat::Jpeg);
+        assert!(parse_format("txt").is_err());
+    }
+
+    #[test]
+    fn test_png_color() {
+        let options = EncodeOptions::try_parse_from(["encode", "--png-16bit"]).unwrap();
+
+        assert_eq!(png_color(ColorType::L8, &options), ColorType::L16);
+        assert_eq!(png_color(ColorType::La8, &options), ColorType::La16);
+        assert_eq!(png_color(ColorType::Rgb8, &options), ColorType::Rgb16);
+        assert_eq!(png_color(ColorType::Rgba8, &options), ColorType::Rgba16);
+    }
+
+    #[test]
+    fn test_parse_jpeg_quality() {
+        assert_eq!(parse_jpeg_quality("90").unwrap(), 90);
+        assert!(parse_jpeg_quality("0").is_err());
+        assert!(parse_jpeg_quality("101").is_err());
+    }
+}
@@ -4,23 +4,81 @@ use clap::{
     AppSettings::{ColoredHelp, DeriveDisplayOrder, SubcommandRequiredElseHelp},
     Clap,
 };
+use compare::Compare;
 use filters::{
-    box_blur_1d, box_blur_1d_gpu, box_blur_2d, gaussian_blur_1d, gaussian_blur_2d, sobel2d, Image,
+    try_box_blur_1d, try_box_blur_1d_gpu, try_box_blur_2d, try_clahe, try_convert, try_dog,
+    try_equalize, try_gaussian_blur_1d, try_gaussian_blur_1d_gpu, try_gaussian_blur_2d,
+    try_sobel2d_channels, try_sobel2d_luma, try_threshold, Channels, ColorSpace, FilterError,
+    Image, Luma,
 };
 use image::{
-    flat::SampleLayout, imageops, GenericImage, GenericImageView, ImageBuffer, Rgba, SubImage,
+    flat::SampleLayout, imageops, DynamicImage, GenericImage, GenericImageView, GrayImage,
+    ImageBuffer, Rgba, SubImage,
 };
-use std::path::PathBuf;
+use metadata::{Metadata, Orientation};
+use region::Region;
+use std::path::{Path, PathBuf};
+
+mod animation;
+mod batch;
+mod blend;
+mod blobs;
+mod color;
+mod compare;
+mod corners;
+mod draw;
+mod histogram;
+mod hough;
+mod input;
+mod io;
+mod mask;
+mod metadata;
+mod pyramid;
+mod region;
+mod resize;
+mod tile;
+mod transform;
 
 #[derive(Clap)]
 #[clap(setting = SubcommandRequiredElseHelp, version = crate_version!(), author = crate_authors!())]
 struct Opts {
     #[clap(subcommand)]
-    filter: Filter,
-    #[clap(short, long, parse(from_os_str))]
-    input: PathBuf,
-    #[clap(short, long, parse(from_os_str), default_value = "output.jpg")]
+    command: Command,
+    #[clap(
+        short,
+        long,
+        parse(from_os_str),
+        required = true,
+        number_of_values = 1,
+        about = "Image input, directory, quoted glob pattern or - for stdin, can be repeated"
+    )]
+    input: Vec<PathBuf>,
+    #[clap(
+        short,
+        long,
+        parse(from_os_str),
+        default_value = "output.jpg",
+        about = "Image output or - for stdout"
+    )]
     output: PathBuf,
+    #[clap(flatten)]
+    encode: io::EncodeOptions,
+    #[clap(flatten)]
+    color: color::ColorOptions,
+    #[clap(
+        long,
+        parse(from_os_str),
+        about = "Output directory for batch processing"
+    )]
+    output_dir: Option<PathBuf>,
+    #[clap(
+        long,
+        default_value = "{stem}.{ext}",
+        about = "Output file name within --output-dir, supports {stem}, {ext}, {name} and {index}"
+    )]
+    template: String,
+    #[clap(short, long, about = "Number of images processed in parallel")]
+    jobs: Option<usize>,
     // #[clap(short, long, arg_enum, default_value = "crop")]
     // edges: Edges,
     #[clap(short, default_value = "0", about = "Crop x-coordinate")]
@@ -31,6 +89,60 @@ struct Opts {
     width: Option<u32>,
     #[clap(short, long, about = "Crop height")]
     height: Option<u32>,
+    #[clap(
+        long,
+        number_of_values = 1,
+        parse(try_from_str = region::parse_region),
+        about = "Region as x,y,width,height[,filter[,radius or sigma]], can be repeated"
+    )]
+    region: Vec<Region>,
+    #[clap(
+        long,
+        parse(from_os_str),
+        about = "JSON file with a list of regions, each with an optional filter"
+    )]
+    regions: Option<PathBuf>,
+    #[clap(
+        long,
+        parse(from_os_str),
+        about = "Grayscale mask of the image size, white selects the filtered pixels"
+    )]
+    mask: Option<PathBuf>,
+    #[clap(
+        long,
+        default_value = "0",
+        about = "Fade the filter out towards the crop edges over the given number of pixels"
+    )]
+    feather: u32,
+    #[clap(
+        long,
+        default_value = "100",
+        about = "Delay between the frames of a numbered image sequence in milliseconds"
+    )]
+    frame_delay: u32,
+    #[clap(
+        long,
+        about = "Process a PNG in strips of the given number of rows to limit memory usage"
+    )]
+    tile_rows: Option<u32>,
+    #[clap(
+        long,
+        parse(from_os_str),
+        about = "Write the histograms of each channel and of luma of the output as JSON"
+    )]
+    histogram: Option<PathBuf>,
+    #[clap(
+        long,
+        default_value = "apply",
+        parse(try_from_str = metadata::parse_orientation),
+        about = "EXIF orientation: apply rotates the image upright, preserve keeps it as stored"
+    )]
+    orientation: metadata::Orientation,
+    #[clap(
+        long,
+        about = "Do not copy ICC profiles, EXIF and other metadata to the output"
+    )]
+    strip_metadata: bool,
     #[clap(short, long, about = "Force output file overwrite")]
     force: bool,
     #[clap(short, long, about = "Increase logging verbosity")]
@@ -43,7 +155,32 @@ enum Edges {
     Crop,
 }
 
-#[derive(Clap)]
+#[derive(Clap, Debug, Clone)]
+#[clap(setting = ColoredHelp, setting = DeriveDisplayOrder)]
+enum Command {
+    #[clap(flatten)]
+    Filter(Filter),
+    /// Compare the input with a reference image
+    #[clap(name = "compare")]
+    Compare(Compare),
+    /// Write the levels of a Gaussian or Laplacian pyramid of the input
+    #[clap(name = "pyramid")]
+    Pyramid(pyramid::Pyramid),
+    /// Detect blobs at multiple scales and print them as JSON
+    #[clap(name = "blobs")]
+    Blobs(blobs::Blobs),
+    /// Detect corners with Harris' or Shi and Tomasi's response and print them as JSON
+    #[clap(name = "corners")]
+    Corners(corners::Corners),
+    /// Detect straight lines in a binary edge image and print them as JSON
+    #[clap(name = "lines")]
+    Lines(hough::Lines),
+    /// Detect circles from the gradients of the input and print them as JSON
+    #[clap(name = "circles")]
+    Circles(hough::Circles),
+}
+
+#[derive(Clap, Debug, Clone)]
 #[clap(setting = ColoredHelp, setting = DeriveDisplayOrder)]
 enum Filter {
     #[clap(name = "box_blur_1d")]
@@ -54,28 +191,224 @@ enum Filter {
     BoxBlur2D(BoxBlur),
     #[clap(name = "gaussian_blur_1d")]
     GaussianBlur1D(GaussianBlur),
+    #[clap(name = "gaussian_blur_1d_gpu")]
+    GaussianBlur1DGPU(GaussianBlur),
     #[clap(name = "gaussian_blur_2d")]
     GaussianBlur2D(GaussianBlur),
     #[clap(name = "sobel_2d")]
     Sobel2D(Sobel),
+    #[clap(name = "equalize")]
+    Equalize(Equalize),
+    #[clap(name = "clahe")]
+    Clahe(Clahe),
+    #[clap(name = "convert")]
+    Convert(Convert),
+    #[clap(name = "threshold")]
+    Threshold(Threshold),
+    #[clap(name = "dog")]
+    Dog(Dog),
+    #[clap(name = "resize")]
+    Resize(resize::Resize),
+    #[clap(name = "rotate")]
+    Rotate(transform::Rotate),
+    #[clap(name = "flip")]
+    Flip(transform::Flip),
+    #[clap(name = "transpose")]
+    Transpose(transform::Transpose),
+    #[clap(name = "warp")]
+    Warp(transform::Warp),
+    #[clap(name = "blend")]
+    Blend(blend::Blend),
 }
 
-#[derive(Clap, Debug)]
+#[derive(Clap, Debug, Clone)]
 struct BoxBlur {
     #[clap(short, long, default_value = "1")]
     radius: usize,
 }
 
-#[derive(Clap, Debug)]
+/// Sigma of the Gaussian blurs when none is given, as a string for the default of the option
+const DEFAULT_SIGMA: &str = "0.84089642";
+
+#[derive(Clap, Debug, Clone)]
 struct GaussianBlur {
-    #[clap(short, long, default_value = "0.84089642")]
+    #[clap(short, long, default_value = DEFAULT_SIGMA)]
     sigma: f32,
 }
 
-#[derive(Clap, Debug)]
+#[derive(Clap, Debug, Clone)]
 struct Sobel {
     #[clap(short, long)]
     sigma: Option<f32>,
+    #[clap(
+        long,
+        parse(try_from_str = color::parse_luma),
+        about = "Luma weights: bt601, bt709 or average, bt601 by default"
+    )]
+    luma: Option<Luma>,
+}
+
+#[derive(Clap, Debug, Clone)]
+struct Dog {
+    #[clap(
+        long,
+        default_value = "1.0",
+        about = "Sigma of the blur that is subtracted from"
+    )]
+    sigma1: f32,
+    #[clap(
+        long,
+        default_value = "1.6",
+        about = "Sigma of the blur that is subtracted"
+    )]
+    sigma2: f32,
+}
+
+#[derive(Clap, Debug, Clone)]
+struct Equalize {}
+
+#[derive(Clap, Debug, Clone)]
+struct Clahe {
+    #[clap(
+        short,
+        long,
+        default_value = "64",
+        about = "Width and height of the tiles"
+    )]
+    tile_size: u32,
+    #[clap(
+        short,
+        long,
+        default_value = "2.0",
+        about = "Limit of each histogram bin relative to the average bin"
+    )]
+    clip_limit: f32,
+}
+
+#[derive(Clap, Debug, Clone)]
+struct Convert {
+    #[clap(
+        long,
+        default_value = "rgb",
+        parse(try_from_str = color::parse_color_space),
+        about = "Color space of the input: rgb, hsv, hsl, ycbcr or lab"
+    )]
+    from: ColorSpace,
+    #[clap(
+        long,
+        parse(try_from_str = color::parse_color_space),
+        about = "Color space of the output: rgb, gray, hsv, hsl, ycbcr or lab"
+    )]
+    to: ColorSpace,
+    #[clap(
+        long,
+        default_value = "bt601",
+        parse(try_from_str = color::parse_luma),
+        about = "Luma weights of gray: bt601, bt709 or average"
+    )]
+    luma: Luma,
+}
+
+#[derive(Clap, Debug, Clone)]
+struct Threshold {
+    #[clap(
+        short,
+        long,
+        default_value = "otsu",
+        parse(try_from_str = parse_threshold_method),
+        about = "Threshold method: fixed, otsu, mean, gaussian or sauvola"
+    )]
+    method: ThresholdMethod,
+    #[clap(
+        short,
+        long,
+        default_value = "128",
+        about = "Threshold of the fixed method"
+    )]
+    threshold: u8,
+    #[clap(
+        short,
+        long,
+        default_value = "15",
+        about = "Radius of the window of the mean and sauvola methods"
+    )]
+    radius: usize,
+    #[clap(
+        short,
+        long,
+        default_value = "5.0",
+        about = "Sigma of the window of the gaussian method"
+    )]
+    sigma: f32,
+    #[clap(
+        long,
+        default_value = "0.0",
+        about = "Subtracted from the local mean of the mean and gaussian methods"
+    )]
+    offset: f32,
+    #[clap(
+        short,
+        default_value = "0.2",
+        about = "Weight of the local contrast of the sauvola method"
+    )]
+    k: f32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ThresholdMethod {
+    Fixed,
+    Otsu,
+    Mean,
+    Gaussian,
+    Sauvola,
+}
+
+fn parse_threshold_method(method: &str) -> Result<ThresholdMethod> {
+    match method {
+        "fixed" => Ok(ThresholdMethod::Fixed),
+        "otsu" => Ok(ThresholdMethod::Otsu),
+        "mean" => Ok(ThresholdMethod::Mean),
+        "gaussian" => Ok(ThresholdMethod::Gaussian),
+        "sauvola" => Ok(ThresholdMethod::Sauvola),
+        _ => Err(anyhow::anyhow!("Unknown threshold method {:?}", method)),
+    }
+}
+
+impl Filter {
+    /// Whether the filter applies to the whole image only, as it resizes, moves or blends its
+    /// pixels
+    fn whole_image(&self) -> bool {
+        matches!(
+            *self,
+            Filter::Resize(_)
+                | Filter::Rotate(_)
+                | Filter::Flip(_)
+                | Filter::Transpose(_)
+                | Filter::Warp(_)
+                | Filter::Blend(_)
+        )
+    }
+}
+
+impl Threshold {
+    fn method(&self) -> filters::Threshold {
+        match self.method {
+            ThresholdMethod::Fixed => filters::Threshold::Fixed(self.threshold),
+            ThresholdMethod::Otsu => filters::Threshold::Otsu,
+            ThresholdMethod::Mean => filters::Threshold::Mean {
+                radius: self.radius,
+                offset: self.offset,
+            },
+            ThresholdMethod::Gaussian => filters::Threshold::Gaussian {
+                sigma: self.sigma,
+                offset: self.offset,
+            },
+            ThresholdMethod::Sauvola => filters::Threshold::Sauvola {
+                radius: self.radius,
+                k: self.k,
+            },
+        }
+    }
 }
 
 fn crop_image<I>(
@@ -90,51 +423,315 @@ where
 {
     let (width, height) = img.dimensions();
 
+    ensure!(crop_x <= width, "Crop -x exceeds image bounds");
+    ensure!(crop_y <= height, "Crop -y exceeds image bounds");
+
     // If no crop width or height was specified,
     // default to the full width and height of the image
     let crop_w = crop_w.unwrap_or(width - crop_x);
     let crop_h = crop_h.unwrap_or(height - crop_y);
 
-    ensure!(crop_x <= width, "Crop -x exceeds image bounds");
-    ensure!(crop_x <= height, "Crop -y exceeds image bounds");
     ensure!(
-        crop_w + crop_x <= width,
+        crop_w <= width - crop_x,
         "Crop --width exceeds image bounds"
     );
     ensure!(
-        crop_h + crop_y <= height,
+        crop_h <= height - crop_y,
         "Crop --height exceeds image bounds"
     );
 
     Ok(imageops::crop_imm(img, crop_x, crop_y, crop_w, crop_h))
 }
 
-fn main() -> Result<()> {
+/// Exit code for errors that are not caused by a filter, clap uses the same for usage errors
+const EXIT_FAILURE: i32 = 1;
+/// Exit code for invalid filter parameters, such as a non-positive sigma
+const EXIT_INVALID_PARAMETER: i32 = 3;
+/// Exit code for image buffers a filter cannot process
+const EXIT_INVALID_IMAGE: i32 = 4;
+/// Exit code for failures to set up or read back from the GPU
+const EXIT_GPU: i32 = 5;
+
+fn exit_code(err: &anyhow::Error) -> i32 {
+    match err.downcast_ref::<FilterError>() {
+        Some(FilterError::InvalidSigma(_))
+        | Some(FilterError::InvalidParameter(_))
+        | Some(FilterError::InvalidKernel { .. }) => EXIT_INVALID_PARAMETER,
+        Some(FilterError::BufferSize { .. }) | Some(FilterError::UnsupportedChannels(_)) => {
+            EXIT_INVALID_IMAGE
+        }
+        Some(FilterError::AdapterUnavailable)
+        | Some(FilterError::DeviceRequest(_))
+        | Some(FilterError::Shader(_))
+        | Some(FilterError::BufferMap) => EXIT_GPU,
+        None => EXIT_FAILURE,
+    }
+}
+
+fn main() {
+    if let Err(err) = run() {
+        eprintln!("Error: {:?}", err);
+        std::process::exit(exit_code(&err));
+    }
+}
+
+fn run() -> Result<()> {
     let opts: Opts = Opts::parse();
 
+    let filter = match opts.command {
+        Command::Filter(ref filter) => filter,
+        Command::Compare(ref compare) => return compare::run(&opts, compare),
+        Command::Pyramid(ref pyramid) => return pyramid::run(&opts, pyramid),
+        Command::Blobs(ref blobs) => return blobs::run(&opts, blobs),
+        Command::Corners(ref corners) => return corners::run(&opts, corners),
+        Command::Lines(ref lines) => return hough::run_lines(&opts, lines),
+        Command::Circles(ref circles) => return hough::run_circles(&opts, circles),
+    };
+
+    if filter.whole_image() {
+        transform::validate(&opts)?;
+    }
+
+    if let Filter::Sobel2D(Sobel { luma: Some(_), .. }) = *filter {
+        ensure!(
+            !opts.color.per_channel(),
+            "--luma cannot be combined with --channels or --color-space, as Sobel then filters each channel on its own"
+        );
+    }
+
+    let inputs = batch::expand_inputs(&opts.input)?;
+    let regions = regions(&opts)?;
+
+    let output_dir = match opts.output_dir {
+        Some(ref output_dir) => output_dir,
+        None => {
+            ensure!(
+                inputs.len() == 1,
+                "Multiple inputs found, use --output-dir to process them as a batch"
+            );
+
+            return process(&opts, filter, &regions, &inputs[0], &opts.output);
+        }
+    };
+
+    ensure!(
+        !inputs.iter().any(|input| io::is_stdio(input)),
+        "Reading from stdin cannot be combined with --output-dir"
+    );
+    ensure!(
+        opts.histogram.is_none(),
+        "--histogram cannot be combined with --output-dir"
+    );
+
+    let paths = batch::output_paths(output_dir, &opts.template, &inputs)?;
+
+    std::fs::create_dir_all(output_dir)
+        .with_context(|| format!("Failed to create directory {:?}", output_dir.display()))?;
+
+    let summary = batch::run(&paths, opts.jobs, |input, output| {
+        process(&opts, filter, &regions, input, output)
+    })?;
+
+    summary.print();
+
     ensure!(
-        opts.input.exists(),
-        format!("Input: {:?} does not exist", opts.input.display())
+        summary.failed.is_empty(),
+        "{} of {} images failed",
+        summary.failed.len(),
+        paths.len()
     );
 
+    Ok(())
+}
+
+/// Collect the regions of --region and --regions, or the crop of -x, -y, --width and --height
+fn regions(opts: &Opts) -> Result<Vec<Region>> {
+    let mut regions = opts.region.clone();
+
+    if let Some(ref path) = opts.regions {
+        regions.extend(region::open_regions(path)?);
+    }
+
+    if regions.is_empty() {
+        regions.push(Region {
+            x: opts.x,
+            y: opts.y,
+            width: opts.width,
+            height: opts.height,
+            filter: None,
+        });
+    }
+
+    Ok(regions)
+}
+
+fn process(
+    opts: &Opts,
+    filter: &Filter,
+    regions: &[Region],
+    input: &Path,
+    output: &Path,
+) -> Result<()> {
     ensure!(
-        !opts.output.exists() || opts.force,
+        io::is_stdio(output) || !output.exists() || opts.force,
         format!(
             "Output {:?} exists. To overwrite files, use --force.",
-            opts.output.display()
+            output.display()
         )
     );
 
-    let mut file = image::open(opts.input.clone())
-        .with_context(|| format!("Failed to open file {:?}", opts.input.display()))?;
+    ensure!(
+        opts.histogram.is_none() || opts.tile_rows.is_none(),
+        "--histogram cannot be combined with --tile-rows"
+    );
+
+    if let Some(rows) = opts.tile_rows {
+        return tile::process(opts, filter, input, output, rows);
+    }
+
+    // Animations are filtered frame by frame
+    if animation::is_sequence(input) {
+        let delay = image::Delay::from_numer_denom_ms(opts.frame_delay, 1);
+        let animation = animation::open_sequence(input, delay)?;
 
-    let crop = crop_image(&file, opts.x, opts.y, opts.width, opts.height)
-        .with_context(|| format!("Failed to crop image"))?;
+        return animation::process(opts, filter, regions, animation, input, output);
+    }
+
+    let bytes = io::read_input(input)?;
+
+    if let Some(animation) = animation::decode(&bytes)? {
+        return animation::process(opts, filter, regions, animation, input, output);
+    }
+
+    let (mut file, mut metadata) = io::decode_image(&bytes, input)?;
+
+    // Crop coordinates are relative to the image as it is displayed
+    if let (Orientation::Apply, Some(orientation)) = (opts.orientation, metadata.orientation()) {
+        file = metadata::apply_orientation(file, orientation);
+        metadata.reset_orientation();
+    }
+
+    // Strip only once the orientation is applied, which stripping would otherwise discard
+    if opts.strip_metadata {
+        metadata = Metadata::default();
+    }
+
+    let mask = match opts.mask {
+        Some(ref path) => Some(mask::open_mask(path, file.width(), file.height())?),
+        None => None,
+    };
+
+    filter_regions(opts, &mut file, filter, regions, mask.as_ref(), input)?;
+
+    if let Some(ref path) = opts.histogram {
+        histogram::save_histogram(&file, path, opts.force)?;
+    }
+
+    io::save_image(&file, &metadata, output, &opts.encode)
+}
+
+/// Filter each region of the image, or resize, transform or blend the whole image
+fn filter_regions(
+    opts: &Opts,
+    file: &mut DynamicImage,
+    filter: &Filter,
+    regions: &[Region],
+    mask: Option<&GrayImage>,
+    input: &Path,
+) -> Result<()> {
+    *file = match *filter {
+        Filter::Resize(ref resize) => resize::resize_image(file, resize)?,
+        Filter::Rotate(ref rotate) => transform::rotate_image(file, rotate)?,
+        Filter::Flip(ref flip) => transform::flip_image(file, flip)?,
+        Filter::Transpose(ref transpose) => transform::transpose_image(file, transpose)?,
+        Filter::Warp(ref warp) => transform::warp_image(file, warp)?,
+        Filter::Blend(ref blend) => blend::blend_image(opts, file, blend)?,
+        _ => {
+            for region in regions {
+                let filter = region.filter.as_ref().unwrap_or(filter);
+                apply_region(opts, file, region, filter, mask, input)?;
+            }
+
+            return Ok(());
+        }
+    };
+
+    Ok(())
+}
+
+fn apply_filter(
+    filter: &Filter,
+    options: &color::ColorOptions,
+    image: &mut Image<u8>,
+) -> Result<(), FilterError> {
+    color::apply(options, image, |image| match *filter {
+        Filter::BoxBlur1D(BoxBlur { radius }) => try_box_blur_1d(image, radius),
+        Filter::BoxBlur1DGPU(BoxBlur { radius }) => {
+            futures::executor::block_on(try_box_blur_1d_gpu(image, radius))
+        }
+        Filter::BoxBlur2D(BoxBlur { radius }) => try_box_blur_2d(image, radius),
+        Filter::GaussianBlur1D(GaussianBlur { sigma }) => try_gaussian_blur_1d(image, sigma),
+        Filter::GaussianBlur1DGPU(GaussianBlur { sigma }) => {
+            futures::executor::block_on(try_gaussian_blur_1d_gpu(image, sigma))
+        }
+        Filter::GaussianBlur2D(GaussianBlur { sigma }) => try_gaussian_blur_2d(image, sigma),
+        Filter::Sobel2D(Sobel { sigma, .. }) if options.per_channel() => {
+            try_sobel2d_channels(image, sigma, Channels::ALL)
+        }
+        Filter::Sobel2D(Sobel { sigma, luma }) => {
+            try_sobel2d_luma(image, sigma, luma.unwrap_or_default())
+        }
+        Filter::Equalize(_) => try_equalize(image),
+        Filter::Clahe(Clahe {
+            tile_size,
+            clip_limit,
+        }) => try_clahe(image, tile_size, clip_limit),
+        Filter::Convert(Convert { from, to, luma }) => {
+            let to = match to {
+                ColorSpace::Gray(_) => ColorSpace::Gray(luma),
+                to => to,
+            };
+
+            try_convert(image.buf_write, image.channels, from, to)
+        }
+        Filter::Threshold(ref threshold) => try_threshold(image, threshold.method()),
+        Filter::Dog(Dog { sigma1, sigma2 }) => try_dog(image, sigma1, sigma2),
+        Filter::Resize(_)
+        | Filter::Rotate(_)
+        | Filter::Flip(_)
+        | Filter::Transpose(_)
+        | Filter::Warp(_)
+        | Filter::Blend(_) => Err(FilterError::InvalidParameter(
+            "Resizing, geometric transforms and blending apply to the whole image only".to_string(),
+        )),
+    })
+}
+
+/// Filter a single region of the image in place
+fn apply_region(
+    opts: &Opts,
+    file: &mut DynamicImage,
+    region: &Region,
+    filter: &Filter,
+    mask: Option<&GrayImage>,
+    input: &Path,
+) -> Result<()> {
+    let crop = crop_image(file, region.x, region.y, region.width, region.height)
+        .with_context(|| format!("Failed to crop region {},{}", region.x, region.y))?;
+
+    let (crop_w, crop_h) = crop.dimensions();
 
     // Create the read and write buffers
     let mut buf_read: ImageBuffer<Rgba<_>, _> = crop.to_image();
     let mut buf_write: ImageBuffer<Rgba<_>, _> = crop.to_image();
 
+    // Keep the original pixels to blend with, as the filters overwrite both buffers
+    let original = if mask.is_some() || opts.feather > 0 {
+        Some(buf_write.clone())
+    } else {
+        None
+    };
+
     let SampleLayout {
         width,
         height,
@@ -155,38 +752,41 @@ fn main() -> Result<()> {
 
     if opts.verbose {
         eprintln!(
-            "Image:\n  \
+            "Image: {}\n  \
+             region: {},{}\n  \
              width: {}\n  \
              height: {}\n  \
              channels: {}\n  \
         ",
-            width, height, channels
+            input.display(),
+            region.x,
+            region.y,
+            width,
+            height,
+            channels
         );
     }
 
-    match opts.filter {
-        Filter::BoxBlur1D(BoxBlur { radius }) => box_blur_1d(&mut image, radius),
-        Filter::BoxBlur1DGPU(BoxBlur { radius }) => {
-            futures::executor::block_on(box_blur_1d_gpu(&mut image, radius))
-        }
-        Filter::BoxBlur2D(BoxBlur { radius }) => box_blur_2d(&mut image, radius),
-        Filter::GaussianBlur1D(GaussianBlur { sigma }) => gaussian_blur_1d(&mut image, sigma),
-        Filter::GaussianBlur2D(GaussianBlur { sigma }) => gaussian_blur_2d(&mut image, sigma),
-        Filter::Sobel2D(Sobel { sigma }) => sobel2d(&mut image, sigma),
-    }
+    apply_filter(filter, &opts.color, &mut image).context("Failed to apply filter")?;
 
     if opts.verbose {
         eprintln!("Time elapsed: {:?} ms", start.elapsed().as_millis());
     }
 
-    // Overlay result on top of original image
-    file.copy_from(&buf_write, opts.x, opts.y)
-        .with_context(|| format!("Could not write buffer to image"))?;
+    if let Some(original) = original {
+        let weights = mask::weights(
+            (region.x, region.y, crop_w, crop_h),
+            file.dimensions(),
+            mask,
+            opts.feather,
+        );
 
-    file.save(opts.output.clone())
-        .with_context(|| format!("Failed to save file {:?}", opts.output.display()))?;
+        mask::blend(&original, &mut buf_write, channels as usize, &weights);
+    }
 
-    Ok(())
+    // Overlay result on top of original image
+    file.copy_from(&buf_write, region.x, region.y)
+        .context("Could not write buffer to image")
 }
 
 #[cfg(test)]
@@ -207,5 +807,131 @@ mod tests {
 
         assert_eq!(actual, [255, 255, 255]);
     }
-}
 
+    #[test]
+    fn test_invalid_crop() {
+        // Create a 3×1 image
+        let pixels: ImageBuffer<Rgb<u8>, _> =
+            ImageBuffer::from_raw(3, 1, vec![0, 0, 0, 255, 255, 255, 0, 0, 0]).unwrap();
+
+        assert!(crop_image(&pixels, 0, 2, None, None).is_err());
+        assert!(crop_image(&pixels, 4, 0, None, None).is_err());
+        assert!(crop_image(&pixels, 2, 0, Some(2), None).is_err());
+    }
+
+    #[test]
+    fn test_exit_code() {
+        let err = anyhow::Error::new(FilterError::InvalidSigma(0.0)).context("Failed");
+        assert_eq!(exit_code(&err), EXIT_INVALID_PARAMETER);
+
+        let err = anyhow::Error::new(FilterError::AdapterUnavailable);
+        assert_eq!(exit_code(&err), EXIT_GPU);
+
+        let err = anyhow::anyhow!("Input does not exist");
+        assert_eq!(exit_code(&err), EXIT_FAILURE);
+    }
+
+    #[test]
+    fn test_sobel_in_color_space() {
+        // Halves of the same luma but a different blue chroma, which is no edge in Y
+        let mut buf_read = (0..6 * 3)
+            .flat_map(|i| {
+                if i % 6 < 3 {
+                    [128, 128, 128, 255]
+                } else {
+                    [128, 117, 185, 255]
+                }
+                .to_vec()
+            })
+            .collect::<Vec<u8>>();
+        let mut buf_write = buf_read.clone();
+
+        let options = color::ColorOptions {
+            color_space: Some(ColorSpace::YCbCr),
+            channels: None,
+        };
+
+        let sobel = Filter::Sobel2D(Sobel {
+            sigma: None,
+            luma: None,
+        });
+
+        let mut image = Image {
+            buf_read: &mut buf_read,
+            buf_write: &mut buf_write,
+            width: 6,
+            height: 3,
+            channels: 4,
+        };
+
+        apply_filter(&sobel, &options, &mut image).unwrap();
+
+        for pixel in buf_write.chunks_exact(4) {
+            let [y, _, _] =
+                ColorSpace::YCbCr.from_rgb([pixel[0] as f32, pixel[1] as f32, pixel[2] as f32]);
+            assert!(y < 10.0, "{:?}", pixel);
+        }
+    }
+
+    #[test]
+    fn test_strip_metadata_applies_orientation() {
+        let dir = std::env::temp_dir().join(format!("image-filter-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (input, output) = (dir.join("rotated.jpg"), dir.join("upright.jpg"));
+
+        // A 16×8 image of which the left half is black, stored rotated by EXIF orientation 6
+        let pixels = GrayImage::from_fn(16, 8, |x, _| image::Luma([if x < 8 { 0 } else { 255 }]));
+        let mut jpeg = Vec::new();
+        image::codecs::jpeg::JpegEncoder::new(&mut jpeg)
+            .encode(&pixels, 16, 8, image::ColorType::L8)
+            .unwrap();
+
+        #[rustfmt::skip]
+        let exif = vec![
+            b'I', b'I', 0x2a, 0x00, 0x08, 0x00, 0x00, 0x00, // Header and offset of IFD0
+            0x01, 0x00,                                     // Number of entries
+            0x12, 0x01, 0x03, 0x00, 0x01, 0x00, 0x00, 0x00, // Orientation, SHORT, count
+            0x06, 0x00, 0x00, 0x00,                         // Value
+            0x00, 0x00, 0x00, 0x00,                         // Offset of next IFD
+        ];
+        let mut metadata = Metadata::default();
+        metadata.exif = Some(exif);
+        std::fs::write(&input, metadata.embed(jpeg, image::ImageFormat::Jpeg)).unwrap();
+
+        let args: Vec<std::ffi::OsString> = vec![
+            "image-filter".into(),
+            "--strip-metadata".into(),
+            "-f".into(),
+            "-i".into(),
+            input.clone().into(),
+            "-o".into(),
+            output.clone().into(),
+            "box_blur_1d".into(),
+        ];
+        let opts = Opts::try_parse_from(args).unwrap();
+        let filter = match opts.command {
+            Command::Filter(ref filter) => filter,
+            _ => unreachable!(),
+        };
+
+        process(&opts, filter, &regions(&opts).unwrap(), &input, &output).unwrap();
+
+        let bytes = std::fs::read(&output).unwrap();
+        let actual = image::load_from_memory(&bytes).unwrap().to_luma8();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        // Orientation 6 is a clockwise rotation, which moves the left half to the top
+        assert_eq!(actual.dimensions(), (8, 16));
+        assert!(
+            actual.get_pixel(4, 2)[0] < 64,
+            "{:?}",
+            actual.get_pixel(4, 2)
+        );
+        assert!(
+            actual.get_pixel(4, 13)[0] > 192,
+            "{:?}",
+            actual.get_pixel(4, 13)
+        );
+        assert_eq!(Metadata::read(&bytes), Metadata::default());
+    }
+}
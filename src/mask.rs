@@ -0,0 +1,111 @@
+use anyhow::{ensure, Context, Result};
+use image::GrayImage;
+use rayon::prelude::*;
+use std::path::Path;
+
+/// Open a grayscale mask, of which white selects the filtered and black the original pixels
+pub fn open_mask(path: &Path, width: u32, height: u32) -> Result<GrayImage> {
+    let mask = image::open(path)
+        .with_context(|| format!("Failed to open mask {:?}", path.display()))?
+        .to_luma8();
+
+    ensure!(
+        mask.dimensions() == (width, height),
+        "Mask is {}×{}, but the image is {}×{}",
+        mask.width(),
+        mask.height(),
+        width,
+        height
+    );
+
+    Ok(mask)
+}
+
+/// A rectangle within an image, as `(x, y, width, height)`
+pub type Rect = (u32, u32, u32, u32);
+
+/// Compute the weight of each filtered pixel within a crop, from 0.0 to 1.0
+///
+/// The weight is taken from the mask, if any, and fades out over `feather` pixels towards the
+/// edges of the crop. Edges that lie on the border of the image are not feathered, as there is
+/// no seam to hide.
+pub fn weights(
+    crop: Rect,
+    (width, height): (u32, u32),
+    mask: Option<&GrayImage>,
+    feather: u32,
+) -> Vec<f32> {
+    let (crop_x, crop_y, crop_w, crop_h) = crop;
+    let ramp = feather as f32 + 1.0;
+
+    (0..crop_h)
+        .flat_map(|y| (0..crop_w).map(move |x| (x, y)))
+        .map(|(x, y)| {
+            // Distance, in pixels, to each edge that is within the image
+            let edges = [
+                (crop_x > 0, x + 1),
+                (crop_y > 0, y + 1),
+                (crop_x + crop_w < width, crop_w - x),
+                (crop_y + crop_h < height, crop_h - y),
+            ];
+
+            let feather = edges
+                .iter()
+                .filter(|(inner, _)| *inner)
+                .map(|(_, distance)| (*distance as f32 / ramp).min(1.0))
+                .fold(1.0, f32::min);
+
+            let mask = mask.map_or(1.0, |mask| {
+                mask.get_pixel(crop_x + x, crop_y + y).0[0] as f32 / 255.0
+            });
+
+            feather * mask
+        })
+        .collect()
+}
+
+/// Blend the filtered pixels with the original pixels according to their weight
+pub fn blend(original: &[u8], filtered: &mut [u8], channels: usize, weights: &[f32]) {
+    filtered
+        .par_chunks_mut(channels)
+        .zip(original.par_chunks(channels))
+        .zip(weights.par_iter())
+        .for_each(|((filtered, original), &weight)| {
+            for (f, &o) in filtered.iter_mut().zip(original) {
+                let value = o as f32 + (*f as f32 - o as f32) * weight;
+                *f = value.round().min(255.0).max(0.0) as u8;
+            }
+        });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_feather_weights() {
+        // A 5×1 crop in the middle of a 7×1 image, only the left and right edges are feathered
+        let actual = weights((1, 0, 5, 1), (7, 1), None, 1);
+
+        assert_eq!(actual, [0.5, 1.0, 1.0, 1.0, 0.5]);
+    }
+
+    #[test]
+    fn test_mask_weights() {
+        let mask = GrayImage::from_raw(3, 1, vec![0, 255, 51]).unwrap();
+
+        let actual = weights((0, 0, 3, 1), (3, 1), Some(&mask), 0);
+
+        assert_eq!(actual, [0.0, 1.0, 0.2]);
+    }
+
+    #[test]
+    fn test_blend() {
+        let original = [0, 0, 100, 100];
+        let mut filtered = [200, 200, 200, 200];
+
+        blend(&original, &mut filtered, 2, &[0.5, 0.0]);
+
+        assert_eq!(filtered, [100, 100, 100, 100]);
+    }
+}
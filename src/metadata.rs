@@ -0,0 +1,523 @@
+use anyhow::{anyhow, ensure, Context, Result};
+use image::{DynamicImage, ImageFormat};
+use std::convert::TryInto;
+use std::io::{BufReader, Read};
+use std::path::Path;
+
+pub const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+const EXIF_HEADER: &[u8] = b"Exif\0\0";
+const ICC_HEADER: &[u8] = b"ICC_PROFILE\0";
+
+/// JPEG segments can hold at most 65535 bytes, including the two length bytes
+const JPEG_SEGMENT_MAX: usize = 65533;
+
+/// JPEG markers, see: https://www.w3.org/Graphics/JPEG/itu-t81.pdf
+const APP0: u8 = 0xe0;
+const APP1: u8 = 0xe1;
+const APP2: u8 = 0xe2;
+const APP14: u8 = 0xee;
+const APP15: u8 = 0xef;
+const COM: u8 = 0xfe;
+const SOS: u8 = 0xda;
+const EOI: u8 = 0xd9;
+
+/// EXIF tag holding the orientation, see: https://www.exif.org/Exif2-2.PDF
+const ORIENTATION_TAG: u16 = 0x0112;
+
+/// Ancillary PNG chunks that do not depend on how the pixels are stored
+const PNG_CHUNKS: [&[u8; 4]; 8] = [
+    b"tEXt", b"zTXt", b"iTXt", b"pHYs", b"tIME", b"gAMA", b"cHRM", b"sRGB",
+];
+
+/// How to handle the EXIF orientation of the input
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Orientation {
+    /// Rotate and flip the pixels upright and reset the tag
+    Apply,
+    /// Keep the pixels as stored and the tag as is
+    Preserve,
+}
+
+pub fn parse_orientation(orientation: &str) -> Result<Orientation> {
+    match orientation {
+        "apply" => Ok(Orientation::Apply),
+        "preserve" => Ok(Orientation::Preserve),
+        _ => Err(anyhow!("Unknown orientation {:?}", orientation)),
+    }
+}
+
+/// Metadata of an encoded JPEG or PNG image
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Metadata {
+    pub icc_profile: Option<Vec<u8>>,
+    /// EXIF data as a TIFF structure, without the `Exif\0\0` header
+    pub exif: Option<Vec<u8>>,
+    /// Other APPn and COM segments, such as XMP and IPTC
+    jpeg_segments: Vec<(u8, Vec<u8>)>,
+    /// Other ancillary chunks, such as text and physical dimensions
+    png_chunks: Vec<([u8; 4], Vec<u8>)>,
+}
+
+impl Metadata {
+    /// Read the metadata of an encoded image, unsupported formats have none
+    pub fn read(bytes: &[u8]) -> Metadata {
+        if bytes.starts_with(&[0xff, 0xd8]) {
+            read_jpeg(bytes)
+        } else if bytes.starts_with(PNG_SIGNATURE) {
+            read_png(bytes)
+        } else {
+            Metadata::default()
+        }
+    }
+
+    /// Returns the EXIF orientation, from 1 to 8, where 1 is upright
+    pub fn orientation(&self) -> Option<u16> {
+        let exif = self.exif.as_ref()?;
+        let offset = orientation_offset(exif)?;
+
+        let value = Tiff::new(exif)?.u16(offset)?;
+
+        if (1..=8).contains(&value) {
+            Some(value)
+        } else {
+            None
+        }
+    }
+
+    /// Mark the image as upright, i.e. once the orientation has been applied to the pixels
+    pub fn reset_orientation(&mut self) {
+        if let Some(exif) = self.exif.as_mut() {
+            if let Some(offset) = orientation_offset(exif) {
+                let value = if exif.starts_with(b"MM") {
+                    [0, 1]
+                } else {
+                    [1, 0]
+                };
+                exif[offset..offset + 2].copy_from_slice(&value);
+            }
+        }
+    }
+
+    /// Embed the metadata into an encoded image
+    ///
+    /// Only JPEG and PNG are supported, other formats are returned as is. Segments and chunks
+    /// are only copied between images of the same format, as their contents are format specific.
+    pub fn embed(&self, bytes: Vec<u8>, format: ImageFormat) -> Vec<u8> {
+        match format {
+            ImageFormat::Jpeg if bytes.starts_with(&[0xff, 0xd8]) => self.embed_jpeg(bytes),
+            ImageFormat::Png if bytes.starts_with(PNG_SIGNATURE) => self.embed_png(bytes),
+            _ => bytes,
+        }
+    }
+
+    fn embed_jpeg(&self, bytes: Vec<u8>) -> Vec<u8> {
+        let mut segments = Vec::new();
+
+        if let Some(exif) = &self.exif {
+            segments.push((APP1, [EXIF_HEADER, exif].concat()));
+        }
+
+        if let Some(icc_profile) = &self.icc_profile {
+            // Profiles that exceed a single segment are split into numbered chunks
+            let chunks = icc_profile.chunks(JPEG_SEGMENT_MAX - ICC_HEADER.len() - 2);
+            let count = chunks.len();
+
+            // The sequence number is a single byte
+            for (i, chunk) in chunks.enumerate().take_while(|_| count <= 255) {
+                segments.push((
+                    APP2,
+                    [ICC_HEADER, &[i as u8 + 1, count as u8], chunk].concat(),
+                ));
+            }
+        }
+
+        segments.extend(self.jpeg_segments.iter().cloned());
+
+        // Insert after the JFIF segment, which has to come first
+        let mut offset = 2;
+        if bytes.get(2..4) == Some(&[0xff, APP0][..]) {
+            offset += 2 + be_u16(&bytes[4..6]) as usize;
+        }
+
+        let mut output = bytes[..offset].to_vec();
+
+        for (marker, contents) in segments {
+            if contents.len() > JPEG_SEGMENT_MAX {
+                continue;
+            }
+
+            output.extend_from_slice(&[0xff, marker]);
+            output.extend_from_slice(&(contents.len() as u16 + 2).to_be_bytes());
+            output.extend_from_slice(&contents);
+        }
+
+        output.extend_from_slice(&bytes[offset..]);
+        output
+    }
+
+    /// The metadata as PNG chunks, in the order they are written after the header
+    pub fn png_chunks(&self) -> Vec<([u8; 4], Vec<u8>)> {
+        let mut chunks = Vec::new();
+
+        if let Some(icc_profile) = &self.icc_profile {
+            // Profile name, compression method and zlib compressed profile
+            let compressed = miniz_oxide::deflate::compress_to_vec_zlib(icc_profile, 6);
+            chunks.push((*b"iCCP", [b"ICC profile\0\0", &compressed[..]].concat()));
+        }
+
+        if let Some(exif) = &self.exif {
+            chunks.push((*b"eXIf", exif.clone()));
+        }
+
+        chunks.extend(self.png_chunks.iter().cloned());
+        chunks
+    }
+
+    fn embed_png(&self, bytes: Vec<u8>) -> Vec<u8> {
+        let chunks = self.png_chunks();
+
+        // Insert after the header chunk, which has to come first
+        let offset = PNG_SIGNATURE.len() + 8 + 13 + 4;
+
+        let mut output = bytes[..offset].to_vec();
+
+        for (kind, data) in chunks {
+            write_png_chunk(&mut output, kind, &data);
+        }
+
+        output.extend_from_slice(&bytes[offset..]);
+        output
+    }
+}
+
+fn read_jpeg(bytes: &[u8]) -> Metadata {
+    let mut metadata = Metadata::default();
+    let mut icc_chunks = Vec::new();
+    let mut offset = 2;
+
+    while let Some(&[0xff, marker]) = bytes.get(offset..offset + 2) {
+        // Markers can be padded with fill bytes
+        if marker == 0xff {
+            offset += 1;
+            continue;
+        }
+
+        // Only the segments before the image data are of interest
+        if marker == SOS || marker == EOI {
+            break;
+        }
+
+        // Standalone markers without a length
+        if marker == 0x01 || (0xd0..=0xd7).contains(&marker) {
+            offset += 2;
+            continue;
+        }
+
+        let length = match bytes.get(offset + 2..offset + 4) {
+            Some(length) => be_u16(length) as usize,
+            None => break,
+        };
+
+        let contents = match bytes.get(offset + 4..offset + 2 + length) {
+            Some(contents) if length >= 2 => contents,
+            _ => break,
+        };
+
+        match marker {
+            APP1 if contents.starts_with(EXIF_HEADER) => {
+                metadata.exif = Some(contents[EXIF_HEADER.len()..].to_vec());
+            }
+            APP2 if contents.starts_with(ICC_HEADER) && contents.len() >= 14 => {
+                // Sequence number, number of chunks and the profile data
+                icc_chunks.push((contents[12], contents[14..].to_vec()));
+            }
+            // JFIF is written by the encoder, and the Adobe transform flag describes the colors
+            // of the original encoding, which the new one need not share
+            APP0 | APP14 => {}
+            APP1..=APP15 | COM => {
+                metadata.jpeg_segments.push((marker, contents.to_vec()));
+            }
+            _ => {}
+        }
+
+        offset += 2 + length;
+    }
+
+    if !icc_chunks.is_empty() {
+        icc_chunks.sort_by_key(|(sequence, _)| *sequence);
+        metadata.icc_profile = Some(icc_chunks.into_iter().flat_map(|(_, data)| data).collect());
+    }
+
+    metadata
+}
+
+/// Read the metadata of a PNG file without loading its image data into memory
+pub fn read_png_file(path: &Path) -> Result<Metadata> {
+    let file = std::fs::File::open(path)
+        .with_context(|| format!("Failed to open file {:?}", path.display()))?;
+    let mut reader = BufReader::new(file);
+
+    // Collect every chunk but the image data, which `read_png` skips anyway
+    let mut bytes = PNG_SIGNATURE.to_vec();
+    let mut signature = [0; 8];
+    reader.read_exact(&mut signature)?;
+    ensure!(
+        &signature[..] == PNG_SIGNATURE,
+        "{:?} is not a PNG file",
+        path.display()
+    );
+
+    let mut header = [0; 8];
+    while reader.read_exact(&mut header).is_ok() {
+        let length = be_u32(&header[..4]) as u64;
+
+        // Data and CRC
+        if &header[4..] == b"IDAT" {
+            std::io::copy(&mut reader.by_ref().take(length + 4), &mut std::io::sink())?;
+            continue;
+        }
+
+        bytes.extend_from_slice(&header);
+        reader.by_ref().take(length + 4).read_to_end(&mut bytes)?;
+
+        if &header[4..] == b"IEND" {
+            break;
+        }
+    }
+
+    Ok(read_png(&bytes))
+}
+
+fn read_png(bytes: &[u8]) -> Metadata {
+    let mut metadata = Metadata::default();
+
+    for (kind, data) in read_png_chunks(bytes) {
+        match &kind {
+            b"iCCP" => {
+                // Skip the profile name and compression method
+                if let Some(name_len) = data.iter().position(|&b| b == 0) {
+                    metadata.icc_profile = data.get(name_len + 2..).and_then(|profile| {
+                        miniz_oxide::inflate::decompress_to_vec_zlib(profile).ok()
+                    });
+                }
+            }
+            b"eXIf" => metadata.exif = Some(data.to_vec()),
+            kind if PNG_CHUNKS.contains(&kind) => {
+                metadata.png_chunks.push((*kind, data.to_vec()));
+            }
+            _ => {}
+        }
+    }
+
+    metadata
+}
+
+/// Split an encoded PNG into its chunks up to IEND, a truncated chunk ends the list
+pub fn read_png_chunks(bytes: &[u8]) -> Vec<([u8; 4], &[u8])> {
+    let mut chunks = Vec::new();
+    let mut offset = PNG_SIGNATURE.len();
+
+    while let Some(header) = bytes.get(offset..offset + 8) {
+        let length = be_u32(&header[..4]) as usize;
+        let kind: [u8; 4] = header[4..].try_into().unwrap();
+
+        let data = match bytes.get(offset + 8..offset + 8 + length) {
+            Some(data) => data,
+            None => break,
+        };
+
+        if &kind == b"IEND" {
+            break;
+        }
+
+        chunks.push((kind, data));
+
+        // Length, type, data and CRC
+        offset += 12 + length;
+    }
+
+    chunks
+}
+
+/// Append a PNG chunk with its length and CRC
+pub fn write_png_chunk(output: &mut Vec<u8>, kind: [u8; 4], data: &[u8]) {
+    output.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    output.extend_from_slice(&kind);
+    output.extend_from_slice(data);
+
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(&kind);
+    hasher.update(data);
+    output.extend_from_slice(&hasher.finalize().to_be_bytes());
+}
+
+/// Rotate and flip an image according to its EXIF orientation
+pub fn apply_orientation(image: DynamicImage, orientation: u16) -> DynamicImage {
+    match orientation {
+        2 => image.fliph(),
+        3 => image.rotate180(),
+        4 => image.flipv(),
+        5 => image.rotate90().fliph(),
+        6 => image.rotate90(),
+        7 => image.rotate270().fliph(),
+        8 => image.rotate270(),
+        _ => image,
+    }
+}
+
+/// Find the offset of the orientation value within the first IFD of the EXIF data
+fn orientation_offset(exif: &[u8]) -> Option<usize> {
+    let tiff = Tiff::new(exif)?;
+    let ifd = tiff.u32(4)? as usize;
+    let entries = tiff.u16(ifd)? as usize;
+
+    // Each entry consists of a tag, type, count and value of 12 bytes in total
+    (0..entries)
+        .map(|i| ifd + 2 + i * 12)
+        .find(|&entry| tiff.u16(entry) == Some(ORIENTATION_TAG))
+        .map(|entry| entry + 8)
+        .filter(|&value| value + 2 <= exif.len())
+}
+
+/// Reads integers from a TIFF structure in its byte order
+struct Tiff<'a> {
+    bytes: &'a [u8],
+    big_endian: bool,
+}
+
+impl<'a> Tiff<'a> {
+    fn new(bytes: &'a [u8]) -> Option<Tiff<'a>> {
+        let big_endian = match bytes.get(..4)? {
+            b"MM\0*" => true,
+            b"II*\0" => false,
+            _ => return None,
+        };
+
+        Some(Tiff { bytes, big_endian })
+    }
+
+    fn u16(&self, offset: usize) -> Option<u16> {
+        let bytes = self.bytes.get(offset..offset + 2)?.try_into().ok()?;
+
+        Some(if self.big_endian {
+            u16::from_be_bytes(bytes)
+        } else {
+            u16::from_le_bytes(bytes)
+        })
+    }
+
+    fn u32(&self, offset: usize) -> Option<u32> {
+        let bytes = self.bytes.get(offset..offset + 4)?.try_into().ok()?;
+
+        Some(if self.big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        })
+    }
+}
+
+fn be_u16(bytes: &[u8]) -> u16 {
+    u16::from_be_bytes([bytes[0], bytes[1]])
+}
+
+fn be_u32(bytes: &[u8]) -> u32 {
+    u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Little-endian EXIF data with a single orientation entry
+    fn exif(orientation: u8) -> Vec<u8> {
+        #[rustfmt::skip]
+        let exif = vec![
+            b'I', b'I', 0x2a, 0x00, 0x08, 0x00, 0x00, 0x00, // Header and offset of IFD0
+            0x01, 0x00,                                     // Number of entries
+            0x12, 0x01, 0x03, 0x00, 0x01, 0x00, 0x00, 0x00, // Orientation, SHORT, count
+            orientation, 0x00, 0x00, 0x00,                  // Value
+            0x00, 0x00, 0x00, 0x00,                         // Offset of next IFD
+        ];
+
+        exif
+    }
+
+    #[test]
+    fn test_orientation() {
+        let mut metadata = Metadata {
+            exif: Some(exif(6)),
+            ..Metadata::default()
+        };
+
+        assert_eq!(metadata.orientation(), Some(6));
+
+        metadata.reset_orientation();
+
+        assert_eq!(metadata.orientation(), Some(1));
+    }
+
+    #[test]
+    fn test_apply_orientation() {
+        // Create a 2×1 image of a black and a white pixel
+        let image =
+            DynamicImage::ImageLuma8(image::GrayImage::from_raw(2, 1, vec![0, 255]).unwrap());
+
+        let actual = apply_orientation(image, 6).to_luma8();
+
+        assert_eq!(actual.dimensions(), (1, 2));
+        assert_eq!(actual.into_raw(), [0, 255]);
+    }
+
+    #[test]
+    fn test_jpeg_roundtrip() {
+        let metadata = Metadata {
+            icc_profile: Some(vec![1, 2, 3]),
+            exif: Some(exif(3)),
+            jpeg_segments: vec![(COM, b"comment".to_vec())],
+            png_chunks: vec![],
+        };
+
+        let mut jpeg = Vec::new();
+        image::codecs::jpeg::JpegEncoder::new(&mut jpeg)
+            .encode(&[0, 0, 0], 1, 1, image::ColorType::Rgb8)
+            .unwrap();
+
+        let embedded = metadata.embed(jpeg, ImageFormat::Jpeg);
+
+        assert_eq!(Metadata::read(&embedded), metadata);
+        assert!(image::load_from_memory(&embedded).is_ok());
+    }
+
+    #[test]
+    fn test_jpeg_adobe_segment() {
+        // SOI, an Adobe APP14 segment with transform 0, a comment and EOI
+        let mut jpeg = vec![0xff, 0xd8, 0xff, APP14, 0x00, 0x0e];
+        jpeg.extend_from_slice(b"Adobe\x00\x64\x00\x00\x00\x00\x00");
+        jpeg.extend_from_slice(&[0xff, COM, 0x00, 0x04, b'h', b'i', 0xff, EOI]);
+
+        let metadata = Metadata::read(&jpeg);
+
+        assert_eq!(metadata.jpeg_segments, vec![(COM, b"hi".to_vec())]);
+    }
+
+    #[test]
+    fn test_png_roundtrip() {
+        let metadata = Metadata {
+            icc_profile: Some(vec![1, 2, 3]),
+            exif: Some(exif(8)),
+            jpeg_segments: vec![],
+            png_chunks: vec![(*b"tEXt", b"Title\0Jupiter".to_vec())],
+        };
+
+        let mut png = Vec::new();
+        image::codecs::png::PngEncoder::new(&mut png)
+            .encode(&[0, 0, 0], 1, 1, image::ColorType::Rgb8)
+            .unwrap();
+
+        let embedded = metadata.embed(png, ImageFormat::Png);
+
+        assert_eq!(Metadata::read(&embedded), metadata);
+        assert!(image::load_from_memory(&embedded).is_ok());
+    }
+}
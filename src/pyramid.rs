@@ -0,0 +1,111 @@
+use crate::input::Input;
+use crate::metadata::Metadata;
+use crate::{io, transform, Opts};
+use anyhow::{ensure, Context, Result};
+use clap::Clap;
+use filters::{try_gaussian_pyramid, try_laplacian_pyramid, Level};
+use image::DynamicImage;
+use std::path::{Path, PathBuf};
+
+/// Offset of the signed details of Laplacian levels in the written images
+const DETAIL_OFFSET: f32 = 128.0;
+
+#[derive(Clap, Debug, Clone)]
+pub struct Pyramid {
+    #[clap(
+        short,
+        long,
+        default_value = "4",
+        about = "Number of levels, including the image itself"
+    )]
+    levels: usize,
+    #[clap(
+        short,
+        long,
+        default_value = "1.0",
+        about = "Sigma of the blur before each halving"
+    )]
+    sigma: f32,
+    #[clap(
+        long,
+        about = "Write the Laplacian pyramid, of which the details are offset by 128"
+    )]
+    laplacian: bool,
+}
+
+/// Write each level of the pyramid of the input to the output with the level appended to its
+/// name, i.e. `out_0.png` for `--output out.png`
+pub fn run(opts: &Opts, pyramid: &Pyramid) -> Result<()> {
+    ensure!(
+        !io::is_stdio(&opts.output),
+        "Pyramid levels cannot be written to stdout"
+    );
+
+    let mut input = Input::open(opts, "Pyramid")?;
+    let img = input.pixels();
+
+    let levels = if pyramid.laplacian {
+        try_laplacian_pyramid(&img, pyramid.levels, pyramid.sigma)
+    } else {
+        try_gaussian_pyramid(&img, pyramid.levels, pyramid.sigma)
+    }
+    .context("Failed to build pyramid")?;
+
+    for (index, level) in levels.iter().enumerate() {
+        let path = level_path(&opts.output, index);
+
+        ensure!(
+            !path.exists() || opts.force,
+            "Output {:?} exists. To overwrite files, use --force.",
+            path.display()
+        );
+
+        // The last Laplacian level is the coarsest Gaussian level itself
+        let offset = if pyramid.laplacian && index + 1 < levels.len() {
+            DETAIL_OFFSET
+        } else {
+            0.0
+        };
+
+        let image = level_image(level, offset)?;
+        io::save_image(&image, &Metadata::default(), &path, &opts.encode)?;
+    }
+
+    Ok(())
+}
+
+/// Path of a level, with its index appended to the file stem
+fn level_path(output: &Path, index: usize) -> PathBuf {
+    let stem = output.file_stem().unwrap_or_default().to_string_lossy();
+
+    let name = match output.extension() {
+        Some(ext) => format!("{}_{}.{}", stem, index, ext.to_string_lossy()),
+        None => format!("{}_{}", stem, index),
+    };
+
+    output.with_file_name(name)
+}
+
+fn level_image(level: &Level, offset: f32) -> Result<DynamicImage> {
+    let buf = level
+        .buf
+        .iter()
+        .map(|&v| (v + offset).round().min(255.0).max(0.0) as u8)
+        .collect();
+
+    transform::image_from_pixels(level.width, level.height, level.channels, buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_level_path() {
+        assert_eq!(
+            level_path(Path::new("out/levels.png"), 2),
+            Path::new("out/levels_2.png")
+        );
+        assert_eq!(level_path(Path::new("levels"), 0), Path::new("levels_0"));
+    }
+}
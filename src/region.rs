@@ -0,0 +1,170 @@
+use crate::{BoxBlur, Filter, GaussianBlur, Sobel, DEFAULT_SIGMA};
+use anyhow::{bail, ensure, Context, Result};
+use serde::Deserialize;
+use std::path::Path;
+
+/// A rectangle to filter, of which a missing width or height extends to the image edge
+#[derive(Debug, Clone)]
+pub struct Region {
+    pub x: u32,
+    pub y: u32,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    /// Regions without a filter use the filter of the subcommand
+    pub filter: Option<Filter>,
+}
+
+/// A region as it is stored in a JSON file, i.e.
+/// `{ "x": 10, "y": 10, "width": 80, "height": 40, "filter": "gaussian_blur_1d", "sigma": 8.0 }`
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RegionSpec {
+    x: u32,
+    y: u32,
+    width: Option<u32>,
+    height: Option<u32>,
+    filter: Option<String>,
+    radius: Option<usize>,
+    sigma: Option<f32>,
+}
+
+/// Parse a region from `x,y,width,height`, optionally followed by a filter and its radius or
+/// sigma, i.e. `10,10,80,40,gaussian_blur_1d,8.0`
+pub fn parse_region(region: &str) -> Result<Region> {
+    let parts = region.split(',').map(str::trim).collect::<Vec<_>>();
+
+    ensure!(
+        parts.len() >= 4 && parts.len() <= 6,
+        "Region {:?} should be x,y,width,height[,filter[,parameter]]",
+        region
+    );
+
+    let number = |i: usize| -> Result<u32> {
+        parts[i]
+            .parse()
+            .with_context(|| format!("Invalid number {:?} in region {:?}", parts[i], region))
+    };
+
+    let filter = match parts.get(4) {
+        Some(name) => {
+            let parameter = parts.get(5);
+            let radius = parameter.map(|p| p.parse()).transpose();
+            let sigma = parameter.map(|p| p.parse()).transpose();
+
+            Some(match *name {
+                "box_blur_1d" | "box_blur_1d_gpu" | "box_blur_2d" => {
+                    let radius =
+                        radius.with_context(|| format!("Invalid radius in {:?}", region))?;
+                    filter(name, radius, None)?
+                }
+                _ => {
+                    let sigma = sigma.with_context(|| format!("Invalid sigma in {:?}", region))?;
+                    filter(name, None, sigma)?
+                }
+            })
+        }
+        None => None,
+    };
+
+    Ok(Region {
+        x: number(0)?,
+        y: number(1)?,
+        width: Some(number(2)?),
+        height: Some(number(3)?),
+        filter,
+    })
+}
+
+/// Read a list of regions from a JSON file
+pub fn open_regions(path: &Path) -> Result<Vec<Region>> {
+    let file = std::fs::File::open(path)
+        .with_context(|| format!("Failed to open regions {:?}", path.display()))?;
+
+    let specs: Vec<RegionSpec> = serde_json::from_reader(std::io::BufReader::new(file))
+        .with_context(|| format!("Failed to parse regions {:?}", path.display()))?;
+
+    specs
+        .into_iter()
+        .map(|spec| {
+            let filter = match spec.filter {
+                Some(ref name) => Some(filter(name, spec.radius, spec.sigma)?),
+                None => None,
+            };
+
+            Ok(Region {
+                x: spec.x,
+                y: spec.y,
+                width: spec.width,
+                height: spec.height,
+                filter,
+            })
+        })
+        .collect()
+}
+
+/// Create a filter from its subcommand name, with the defaults of the subcommand
+fn filter(name: &str, radius: Option<usize>, sigma: Option<f32>) -> Result<Filter> {
+    let radius = radius.unwrap_or(1);
+    let gaussian = || GaussianBlur {
+        sigma: sigma.unwrap_or_else(|| DEFAULT_SIGMA.parse().unwrap()),
+    };
+
+    Ok(match name {
+        "box_blur_1d" => Filter::BoxBlur1D(BoxBlur { radius }),
+        "box_blur_1d_gpu" => Filter::BoxBlur1DGPU(BoxBlur { radius }),
+        "box_blur_2d" => Filter::BoxBlur2D(BoxBlur { radius }),
+        "gaussian_blur_1d" => Filter::GaussianBlur1D(gaussian()),
+        "gaussian_blur_1d_gpu" => Filter::GaussianBlur1DGPU(gaussian()),
+        "gaussian_blur_2d" => Filter::GaussianBlur2D(gaussian()),
+        "sobel_2d" => Filter::Sobel2D(Sobel { sigma, luma: None }),
+        _ => bail!("Unknown filter {:?}", name),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_region() {
+        let region = parse_region("10,20,30,40").unwrap();
+
+        assert_eq!(
+            (region.x, region.y, region.width, region.height),
+            (10, 20, Some(30), Some(40))
+        );
+        assert!(region.filter.is_none());
+    }
+
+    #[test]
+    fn test_parse_region_with_filter() {
+        let region = parse_region("0,0,8,8,gaussian_blur_1d,3.5").unwrap();
+
+        match region.filter {
+            Some(Filter::GaussianBlur1D(GaussianBlur { sigma })) => assert_eq!(sigma, 3.5),
+            _ => panic!("Expected a Gaussian blur"),
+        }
+
+        let region = parse_region("0,0,8,8,gaussian_blur_2d").unwrap();
+
+        match region.filter {
+            Some(Filter::GaussianBlur2D(GaussianBlur { sigma })) => assert_eq!(sigma, 0.840_896_4),
+            _ => panic!("Expected a Gaussian blur"),
+        }
+
+        let region = parse_region("0,0,8,8,box_blur_2d,4").unwrap();
+
+        match region.filter {
+            Some(Filter::BoxBlur2D(BoxBlur { radius })) => assert_eq!(radius, 4),
+            _ => panic!("Expected a box blur"),
+        }
+    }
+
+    #[test]
+    fn test_parse_invalid_region() {
+        assert!(parse_region("10,20").is_err());
+        assert!(parse_region("10,20,30,-40").is_err());
+        assert!(parse_region("0,0,8,8,median,3").is_err());
+        assert!(parse_region("0,0,8,8,box_blur_2d,0.5").is_err());
+    }
+}
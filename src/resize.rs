@@ -0,0 +1,154 @@
+use crate::transform::transform_pixels;
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use clap::Clap;
+use filters::{try_resize, Resample};
+use image::{DynamicImage, GenericImageView};
+
+#[derive(Clap, Debug, Clone)]
+pub struct Resize {
+    #[clap(
+        long,
+        parse(try_from_str = parse_size),
+        about = "Size as WIDTHxHEIGHT, either side can be left out to keep the aspect ratio"
+    )]
+    size: Option<Size>,
+    #[clap(long, about = "Factor to scale both sides with")]
+    scale: Option<f32>,
+    #[clap(
+        long,
+        parse(try_from_str = parse_size),
+        about = "Largest size as WIDTHxHEIGHT to fit within, keeping the aspect ratio"
+    )]
+    fit: Option<Size>,
+    #[clap(
+        long,
+        default_value = "lanczos3",
+        parse(try_from_str = parse_resample),
+        about = "Kernel: box, bilinear, bicubic, mitchell or lanczos3"
+    )]
+    filter: Resample,
+    #[clap(long, about = "Resample colors in linear light")]
+    linear: bool,
+}
+
+/// Width and height, either of which can be left out
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Size(pub Option<u32>, pub Option<u32>);
+
+pub fn parse_size(size: &str) -> Result<Size> {
+    let (width, height) = match size.find('x') {
+        Some(index) => (&size[..index], &size[index + 1..]),
+        None => bail!("Size {:?} should be WIDTHxHEIGHT", size),
+    };
+
+    let side = |side: &str| -> Result<Option<u32>> {
+        if side.is_empty() {
+            return Ok(None);
+        }
+
+        match side.parse() {
+            Ok(0) | Err(_) => bail!("Size {:?} should have sides > 0", size),
+            Ok(side) => Ok(Some(side)),
+        }
+    };
+
+    match (side(width)?, side(height)?) {
+        (None, None) => bail!("Size {:?} should have a width or a height", size),
+        (width, height) => Ok(Size(width, height)),
+    }
+}
+
+pub fn parse_resample(filter: &str) -> Result<Resample> {
+    match filter {
+        "box" => Ok(Resample::Box),
+        "bilinear" => Ok(Resample::Bilinear),
+        "bicubic" | "catmull-rom" => Ok(Resample::CatmullRom),
+        "mitchell" => Ok(Resample::Mitchell),
+        "lanczos3" => Ok(Resample::Lanczos3),
+        _ => Err(anyhow!("Unknown resampling filter {:?}", filter)),
+    }
+}
+
+impl Resize {
+    /// Size of the output for an input of the given size
+    fn dimensions(&self, (width, height): (u32, u32)) -> Result<(u32, u32)> {
+        let scaled = |side: u32, factor: f64| ((side as f64 * factor).round() as u32).max(1);
+
+        match (self.size, self.scale, self.fit) {
+            (Some(Size(Some(w), Some(h))), None, None) => Ok((w, h)),
+            (Some(Size(Some(w), None)), None, None) => {
+                Ok((w, scaled(height, w as f64 / width as f64)))
+            }
+            (Some(Size(None, Some(h))), None, None) => {
+                Ok((scaled(width, h as f64 / height as f64), h))
+            }
+            (None, Some(scale), None) => {
+                ensure!(
+                    scale > 0.0 && scale.is_finite(),
+                    "--scale should be > 0, got {}",
+                    scale
+                );
+
+                Ok((scaled(width, scale as f64), scaled(height, scale as f64)))
+            }
+            (None, None, Some(Size(w, h))) => {
+                let factor_w = w.map_or(f64::INFINITY, |w| w as f64 / width as f64);
+                let factor_h = h.map_or(f64::INFINITY, |h| h as f64 / height as f64);
+                let factor = factor_w.min(factor_h);
+
+                Ok((scaled(width, factor), scaled(height, factor)))
+            }
+            _ => bail!("Resize takes exactly one of --size, --scale or --fit"),
+        }
+    }
+}
+
+/// Resize an image, keeping its number of channels
+pub fn resize_image(image: &DynamicImage, resize: &Resize) -> Result<DynamicImage> {
+    let (width, height) = resize.dimensions(image.dimensions())?;
+
+    transform_pixels(image, (width, height), |img| {
+        try_resize(img, width, height, resize.filter, resize.linear)
+    })
+    .context("Failed to resize image")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resize(size: Option<&str>, scale: Option<f32>, fit: Option<&str>) -> Resize {
+        Resize {
+            size: size.map(|size| parse_size(size).unwrap()),
+            scale,
+            fit: fit.map(|fit| parse_size(fit).unwrap()),
+            filter: Resample::Lanczos3,
+            linear: false,
+        }
+    }
+
+    #[test]
+    fn test_dimensions() {
+        let dimensions = |resize: Resize| resize.dimensions((400, 300)).unwrap();
+
+        assert_eq!(dimensions(resize(Some("100x100"), None, None)), (100, 100));
+        assert_eq!(dimensions(resize(Some("200x"), None, None)), (200, 150));
+        assert_eq!(dimensions(resize(Some("x60"), None, None)), (80, 60));
+        assert_eq!(dimensions(resize(None, Some(0.5), None)), (200, 150));
+        assert_eq!(dimensions(resize(None, None, Some("100x100"))), (100, 75));
+        assert_eq!(dimensions(resize(None, None, Some("x600"))), (800, 600));
+
+        assert!(resize(None, None, None).dimensions((400, 300)).is_err());
+        assert!(resize(Some("1x1"), Some(2.0), None)
+            .dimensions((400, 300))
+            .is_err());
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("640x480").unwrap(), Size(Some(640), Some(480)));
+        assert!(parse_size("640").is_err());
+        assert!(parse_size("x").is_err());
+        assert!(parse_size("0x10").is_err());
+    }
+}
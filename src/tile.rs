@@ -0,0 +1,558 @@
+use crate::{
+    apply_filter, color::ColorOptions, io, metadata, BoxBlur, Dog, Filter, GaussianBlur, Opts,
+    Sobel,
+};
+use anyhow::{bail, ensure, Context, Result};
+use filters::{gaussian_radius, FilterError, Image};
+use image::{
+    codecs::png::{CompressionType, FilterType},
+    ColorType, DynamicImage, GenericImage, ImageBuffer, ImageFormat,
+};
+use metadata::{Metadata, Orientation};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::Path;
+
+/// Size of the IDAT chunks the compressed image data is split into
+const IDAT_SIZE: usize = 64 * 1024;
+
+/// Number of rows above and below a strip that the filter reads from, or `None` if the filter
+/// depends on the whole image
+pub fn overlap(filter: &Filter) -> Result<Option<u32>, FilterError> {
+    let rows = match *filter {
+        Filter::BoxBlur1D(BoxBlur { radius })
+        | Filter::BoxBlur1DGPU(BoxBlur { radius })
+        | Filter::BoxBlur2D(BoxBlur { radius }) => radius,
+        Filter::GaussianBlur1D(GaussianBlur { sigma })
+        | Filter::GaussianBlur1DGPU(GaussianBlur { sigma })
+        | Filter::GaussianBlur2D(GaussianBlur { sigma }) => gaussian_radius(sigma)?,
+        // The Sobel kernels reach one row beyond the optional blur
+        Filter::Sobel2D(Sobel { sigma, .. }) => sigma.map_or(Ok(0), gaussian_radius)? + 1,
+        Filter::Convert(_) => 0,
+        Filter::Dog(Dog { sigma1, sigma2 }) => gaussian_radius(sigma1.max(sigma2))?,
+        Filter::Threshold(ref threshold) => match threshold.method() {
+            filters::Threshold::Fixed(_) => 0,
+            filters::Threshold::Mean { radius, .. }
+            | filters::Threshold::Sauvola { radius, .. } => radius,
+            filters::Threshold::Gaussian { sigma, .. } => gaussian_radius(sigma)?,
+            filters::Threshold::Otsu => return Ok(None),
+        },
+        Filter::Equalize(_) | Filter::Clahe(_) => return Ok(None),
+        // Resizing and geometric transforms move rows, blending reaches across the image
+        Filter::Resize(_)
+        | Filter::Rotate(_)
+        | Filter::Flip(_)
+        | Filter::Transpose(_)
+        | Filter::Warp(_)
+        | Filter::Blend(_) => return Ok(None),
+    };
+
+    Ok(Some(rows as u32))
+}
+
+/// Filter a PNG file in strips of `rows` rows, so only a strip and its overlap are in memory
+///
+/// The output is identical to filtering the whole image at once, as each strip is extended with
+/// the rows the filter reads from and those rows are discarded afterwards.
+pub fn process(opts: &Opts, filter: &Filter, input: &Path, output: &Path, rows: u32) -> Result<()> {
+    ensure!(rows > 0, "--tile-rows should be > 0");
+    ensure!(
+        opts.region.is_empty()
+            && opts.regions.is_none()
+            && opts.mask.is_none()
+            && (opts.x, opts.y, opts.width, opts.height) == (0, 0, None, None),
+        "--tile-rows cannot be combined with crops, regions or masks"
+    );
+    ensure!(
+        !io::is_stdio(input) && !io::is_stdio(output),
+        "--tile-rows requires files for input and output"
+    );
+    ensure!(
+        ImageFormat::from_path(input).ok() == Some(ImageFormat::Png),
+        "--tile-rows requires a PNG input"
+    );
+    ensure!(
+        opts.encode
+            .format
+            .or_else(|| ImageFormat::from_path(output).ok())
+            == Some(ImageFormat::Png),
+        "--tile-rows requires a PNG output"
+    );
+
+    let mut metadata = metadata::read_png_file(input)?;
+
+    if opts.orientation == Orientation::Apply {
+        ensure!(
+            metadata.orientation().unwrap_or(1) == 1,
+            "Rotated images cannot be processed in tiles, use --orientation preserve"
+        );
+        metadata.reset_orientation();
+    }
+
+    // Strip only after the check above, which needs the orientation
+    if opts.strip_metadata {
+        metadata = Metadata::default();
+    }
+
+    let file =
+        File::open(input).with_context(|| format!("Failed to open file {:?}", input.display()))?;
+
+    // Expand palettes and low bit depths to 8 bits, like `image` does
+    let mut decoder = png::Decoder::new(BufReader::new(file));
+    decoder.set_transformations(png::Transformations::EXPAND);
+
+    let (info, mut reader) = decoder
+        .read_info()
+        .with_context(|| format!("Failed to decode file {:?}", input.display()))?;
+
+    ensure!(
+        !reader.info().interlaced,
+        "Interlaced PNG cannot be processed in tiles"
+    );
+
+    let color = match (info.color_type, info.bit_depth) {
+        (png::ColorType::Grayscale, png::BitDepth::Eight) => ColorType::L8,
+        (png::ColorType::GrayscaleAlpha, png::BitDepth::Eight) => ColorType::La8,
+        (png::ColorType::RGB, png::BitDepth::Eight) => ColorType::Rgb8,
+        (png::ColorType::RGBA, png::BitDepth::Eight) => ColorType::Rgba8,
+        _ => bail!("Only 8-bit PNG can be processed in tiles"),
+    };
+
+    let output_file = File::create(output)
+        .with_context(|| format!("Failed to save file {:?}", output.display()))?;
+    let mut out = BufWriter::new(output_file);
+
+    let mut writer = PngWriter::new(
+        &mut out,
+        (info.width, info.height),
+        io::png_color(color, &opts.encode),
+        &opts.encode,
+        &metadata,
+    )?;
+
+    filter_strips(
+        (info.width, info.height),
+        color,
+        filter,
+        &opts.color,
+        rows,
+        || match reader.next_row()? {
+            Some(row) => Ok(row.to_vec()),
+            None => bail!("Image data ended early"),
+        },
+        |row| writer.write_row(&dynamic_image(info.width, 1, color, row.to_vec())?),
+    )
+    .with_context(|| format!("Failed to process {:?} in tiles", input.display()))?;
+
+    writer.finish()?;
+
+    out.flush()
+        .with_context(|| format!("Failed to save file {:?}", output.display()))
+}
+
+/// Filter an image strip by strip, pulling rows from `read_row` and pushing them to `write_row`
+pub fn filter_strips<R, W>(
+    (width, height): (u32, u32),
+    color: ColorType,
+    filter: &Filter,
+    options: &ColorOptions,
+    rows: u32,
+    mut read_row: R,
+    mut write_row: W,
+) -> Result<()>
+where
+    R: FnMut() -> Result<Vec<u8>>,
+    W: FnMut(&[u8]) -> Result<()>,
+{
+    let overlap = overlap(filter)?
+        .context("Filters that need the whole image cannot be processed in strips")?;
+    let row_len = width as usize * color.bytes_per_pixel() as usize;
+
+    // Rows from `window_start` up to `next_row`
+    let mut window = Vec::new();
+    let mut window_start = 0;
+    let mut next_row = 0;
+
+    for strip_start in (0..height).step_by(rows as usize) {
+        let strip_end = strip_start.saturating_add(rows).min(height);
+        let start = strip_start.saturating_sub(overlap);
+        let end = strip_end.saturating_add(overlap).min(height);
+
+        // Drop the rows that no remaining strip reads from
+        window.drain(..(start - window_start) as usize * row_len);
+        window_start = start;
+
+        while next_row < end {
+            let row = read_row()?;
+            ensure!(
+                row.len() == row_len,
+                "Row {} has an invalid length",
+                next_row
+            );
+
+            window.extend_from_slice(&row);
+            next_row += 1;
+        }
+
+        let mut strip = dynamic_image(width, end - start, color, window.clone())?;
+        filter_image(&mut strip, filter, options)?;
+
+        let bytes = strip.as_bytes();
+
+        for y in strip_start..strip_end {
+            let offset = (y - start) as usize * row_len;
+            write_row(&bytes[offset..offset + row_len])?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Filter an image as RGBA, like a full-size crop is filtered
+fn filter_image(image: &mut DynamicImage, filter: &Filter, options: &ColorOptions) -> Result<()> {
+    let mut buf_read = image.to_rgba8();
+    let mut buf_write = buf_read.clone();
+    let (width, height) = buf_read.dimensions();
+
+    let mut rgba = Image {
+        buf_read: buf_read.as_mut(),
+        buf_write: buf_write.as_mut(),
+        width,
+        height,
+        channels: 4,
+    };
+
+    apply_filter(filter, options, &mut rgba).context("Failed to apply filter")?;
+
+    image
+        .copy_from(&buf_write, 0, 0)
+        .context("Could not write buffer to image")
+}
+
+pub fn dynamic_image(
+    width: u32,
+    height: u32,
+    color: ColorType,
+    bytes: Vec<u8>,
+) -> Result<DynamicImage> {
+    let image = match color {
+        ColorType::L8 => ImageBuffer::from_raw(width, height, bytes).map(DynamicImage::ImageLuma8),
+        ColorType::La8 => {
+            ImageBuffer::from_raw(width, height, bytes).map(DynamicImage::ImageLumaA8)
+        }
+        ColorType::Rgb8 => ImageBuffer::from_raw(width, height, bytes).map(DynamicImage::ImageRgb8),
+        ColorType::Rgba8 => {
+            ImageBuffer::from_raw(width, height, bytes).map(DynamicImage::ImageRgba8)
+        }
+        _ => None,
+    };
+
+    image.with_context(|| format!("Unsupported color type {:?}", color))
+}
+
+/// Writes a PNG row by row, filtering and compressing each row as it arrives
+struct PngWriter<W: Write> {
+    encoder: deflate::write::ZlibEncoder<IdatWriter<W>>,
+    color: ColorType,
+    filter: FilterType,
+    previous: Vec<u8>,
+}
+
+impl<W: Write> PngWriter<W> {
+    fn new(
+        w: W,
+        (width, height): (u32, u32),
+        color: ColorType,
+        options: &io::EncodeOptions,
+        metadata: &Metadata,
+    ) -> Result<Self> {
+        let (color_type, bit_depth) = match color {
+            ColorType::L8 => (png::ColorType::Grayscale, png::BitDepth::Eight),
+            ColorType::La8 => (png::ColorType::GrayscaleAlpha, png::BitDepth::Eight),
+            ColorType::Rgb8 => (png::ColorType::RGB, png::BitDepth::Eight),
+            ColorType::Rgba8 => (png::ColorType::RGBA, png::BitDepth::Eight),
+            ColorType::L16 => (png::ColorType::Grayscale, png::BitDepth::Sixteen),
+            ColorType::La16 => (png::ColorType::GrayscaleAlpha, png::BitDepth::Sixteen),
+            ColorType::Rgb16 => (png::ColorType::RGB, png::BitDepth::Sixteen),
+            ColorType::Rgba16 => (png::ColorType::RGBA, png::BitDepth::Sixteen),
+            _ => bail!("Unsupported color type {:?}", color),
+        };
+
+        let mut encoder = png::Encoder::new(w, width, height);
+        encoder.set_color(color_type);
+        encoder.set_depth(bit_depth);
+
+        let mut writer = encoder.write_header()?;
+
+        for (kind, data) in metadata.png_chunks() {
+            writer.write_chunk(kind, &data)?;
+        }
+
+        let compression = match options.png_compression {
+            CompressionType::Fast => deflate::CompressionOptions::fast(),
+            CompressionType::Best => deflate::CompressionOptions::high(),
+            CompressionType::Huffman => deflate::CompressionOptions::huffman_only(),
+            CompressionType::Rle => deflate::CompressionOptions::rle(),
+            _ => deflate::CompressionOptions::default(),
+        };
+
+        let idat = IdatWriter {
+            writer,
+            buffer: Vec::with_capacity(IDAT_SIZE),
+        };
+
+        Ok(PngWriter {
+            encoder: deflate::write::ZlibEncoder::new(idat, compression),
+            color,
+            filter: options.png_filter,
+            previous: Vec::new(),
+        })
+    }
+
+    /// Convert a row to the color type of the PNG and write it
+    fn write_row(&mut self, row: &DynamicImage) -> Result<()> {
+        // PNG stores 16-bit samples as big endian
+        let current = match self.color {
+            ColorType::L16 => be_bytes(&row.to_luma16()),
+            ColorType::La16 => be_bytes(&row.to_luma_alpha16()),
+            ColorType::Rgb16 => be_bytes(&row.to_rgb16()),
+            ColorType::Rgba16 => be_bytes(&row.to_rgba16()),
+            _ => row.as_bytes().to_vec(),
+        };
+
+        if self.previous.is_empty() {
+            self.previous = vec![0; current.len()];
+        }
+
+        // Prediction is based on the unfiltered bytes of the previous row
+        let mut filtered = current.clone();
+        let bpp = self.color.bytes_per_pixel() as usize;
+        let method = filter_row(self.filter, bpp, &self.previous, &mut filtered);
+
+        self.encoder.write_all(&[method])?;
+        self.encoder.write_all(&filtered)?;
+        self.previous = current;
+
+        Ok(())
+    }
+
+    /// Compress the remaining rows, the `png` writer adds the IEND chunk when it is dropped
+    fn finish(self) -> Result<()> {
+        let mut idat = self.encoder.finish()?;
+        idat.flush()?;
+
+        Ok(())
+    }
+}
+
+/// Filter a row in place for a better compression, returns the filter method
+///
+/// See: https://www.w3.org/TR/PNG/#9Filters
+fn filter_row(filter: FilterType, bpp: usize, previous: &[u8], current: &mut [u8]) -> u8 {
+    let method = match filter {
+        FilterType::NoFilter => 0,
+        FilterType::Up => 2,
+        FilterType::Avg => 3,
+        FilterType::Paeth => 4,
+        _ => 1,
+    };
+
+    // Go from right to left, so the bytes to the left are not filtered yet
+    for i in (0..current.len()).rev() {
+        let a = if i >= bpp { current[i - bpp] } else { 0 };
+        let b = previous[i];
+        let c = if i >= bpp { previous[i - bpp] } else { 0 };
+
+        let prediction = match method {
+            0 => 0,
+            1 => a,
+            2 => b,
+            3 => ((a as u16 + b as u16) / 2) as u8,
+            _ => paeth(a, b, c),
+        };
+
+        current[i] = current[i].wrapping_sub(prediction);
+    }
+
+    method
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let (pa, pb, pc) = (
+        (p - a as i16).abs(),
+        (p - b as i16).abs(),
+        (p - c as i16).abs(),
+    );
+
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+fn be_bytes(samples: &[u16]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(samples.len() * 2);
+
+    for sample in samples {
+        bytes.extend_from_slice(&sample.to_be_bytes());
+    }
+
+    bytes
+}
+
+/// Buffers compressed image data and writes it as IDAT chunks
+struct IdatWriter<W: Write> {
+    writer: png::Writer<W>,
+    buffer: Vec<u8>,
+}
+
+impl<W: Write> Write for IdatWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+
+        if self.buffer.len() >= IDAT_SIZE {
+            self.flush()?;
+        }
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        if !self.buffer.is_empty() {
+            self.writer.write_chunk(png::chunk::IDAT, &self.buffer)?;
+            self.buffer.clear();
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Threshold, ThresholdMethod};
+    use filters::{ColorSpace, Luma};
+    use image::RgbImage;
+
+    fn pattern(width: u32, height: u32) -> DynamicImage {
+        DynamicImage::ImageRgb8(RgbImage::from_fn(width, height, |x, y| {
+            image::Rgb([(x * 37 + y * 11) as u8, (x * y * 7) as u8, (y * 53) as u8])
+        }))
+    }
+
+    #[test]
+    fn test_strips_match_whole_image() {
+        let filters = [
+            Filter::BoxBlur1D(BoxBlur { radius: 2 }),
+            Filter::BoxBlur2D(BoxBlur { radius: 1 }),
+            Filter::GaussianBlur1D(GaussianBlur { sigma: 1.0 }),
+            Filter::GaussianBlur2D(GaussianBlur { sigma: 0.8 }),
+            Filter::Sobel2D(Sobel {
+                sigma: None,
+                luma: Some(Luma::Bt601),
+            }),
+            Filter::Sobel2D(Sobel {
+                sigma: Some(1.0),
+                luma: Some(Luma::Bt709),
+            }),
+            Filter::Threshold(Threshold {
+                method: ThresholdMethod::Sauvola,
+                threshold: 128,
+                radius: 3,
+                sigma: 1.5,
+                offset: 0.0,
+                k: 0.2,
+            }),
+            Filter::Threshold(Threshold {
+                method: ThresholdMethod::Gaussian,
+                threshold: 128,
+                radius: 3,
+                sigma: 1.5,
+                offset: 2.0,
+                k: 0.2,
+            }),
+            Filter::Dog(Dog {
+                sigma1: 0.8,
+                sigma2: 1.6,
+            }),
+        ];
+
+        let image = pattern(9, 23);
+        let options = ColorOptions {
+            color_space: Some(ColorSpace::Lab),
+            channels: Some("l,alpha".to_string()),
+        };
+
+        for filter in filters.iter() {
+            let mut expect = image.clone();
+            filter_image(&mut expect, filter, &options).unwrap();
+
+            for &rows in [1, 4, 7, 23, 100].iter() {
+                let mut source = image.as_bytes().chunks(9 * 3);
+                let mut actual = Vec::new();
+
+                filter_strips(
+                    (9, 23),
+                    ColorType::Rgb8,
+                    filter,
+                    &options,
+                    rows,
+                    || Ok(source.next().unwrap().to_vec()),
+                    |row| {
+                        actual.extend_from_slice(row);
+                        Ok(())
+                    },
+                )
+                .unwrap();
+
+                assert_eq!(actual, expect.as_bytes(), "{:?} in {} rows", filter, rows);
+            }
+        }
+    }
+
+    #[test]
+    fn test_png_writer() {
+        let image = pattern(5, 4);
+        let options = [
+            FilterType::NoFilter,
+            FilterType::Sub,
+            FilterType::Up,
+            FilterType::Avg,
+            FilterType::Paeth,
+        ];
+
+        for (&filter, &png_16bit) in options.iter().zip([false, true].iter().cycle()) {
+            let mut bytes = Vec::new();
+            let encode = io::EncodeOptions {
+                format: None,
+                jpeg_quality: 75,
+                png_compression: CompressionType::Fast,
+                png_filter: filter,
+                png_16bit,
+            };
+
+            let mut writer = PngWriter::new(
+                &mut bytes,
+                (5, 4),
+                io::png_color(ColorType::Rgb8, &encode),
+                &encode,
+                &Metadata::default(),
+            )
+            .unwrap();
+
+            for row in image.as_bytes().chunks(5 * 3) {
+                let row = dynamic_image(5, 1, ColorType::Rgb8, row.to_vec()).unwrap();
+                writer.write_row(&row).unwrap();
+            }
+
+            writer.finish().unwrap();
+
+            let actual = image::load_from_memory(&bytes).unwrap();
+            assert_eq!(actual.to_rgb8().as_raw(), image.as_bytes());
+        }
+    }
+}
@@ -0,0 +1,350 @@
+use crate::resize::{parse_resample, parse_size, Size};
+use crate::{compare, tile, Opts};
+use anyhow::{bail, ensure, Context, Result};
+use clap::Clap;
+use filters::{
+    rotated_size, try_orient, try_warp, Affine, FilterError, Image, Luma, Orientation, Resample,
+};
+use image::{ColorType, DynamicImage, GenericImageView, ImageBuffer, Pixel};
+
+#[derive(Clap, Debug, Clone)]
+pub struct Rotate {
+    #[clap(
+        short,
+        long,
+        allow_hyphen_values = true,
+        about = "Angle in degrees clockwise, multiples of 90 are lossless"
+    )]
+    angle: f32,
+    #[clap(long, about = "Enlarge the output to hold the whole rotated image")]
+    expand: bool,
+    #[clap(
+        long,
+        default_value = "bilinear",
+        parse(try_from_str = parse_resample),
+        about = "Interpolation: box, bilinear, bicubic, mitchell or lanczos3"
+    )]
+    filter: Resample,
+    #[clap(
+        long,
+        default_value = "0,0,0,0",
+        parse(try_from_str = parse_background),
+        about = "Color beyond the input edges as gray, r,g,b or r,g,b,a"
+    )]
+    background: [u8; 4],
+}
+
+#[derive(Clap, Debug, Clone)]
+pub struct Flip {
+    #[clap(long, about = "Mirror top and bottom instead of left and right")]
+    vertical: bool,
+}
+
+#[derive(Clap, Debug, Clone)]
+pub struct Transpose {
+    #[clap(long, about = "Mirror along the diagonal from the top right")]
+    anti: bool,
+}
+
+#[derive(Clap, Debug, Clone)]
+pub struct Warp {
+    #[clap(
+        short,
+        long,
+        allow_hyphen_values = true,
+        parse(try_from_str = parse_affine),
+        about = "Map a,b,c,d,e,f from input to output as x' = a x + b y + c, y' = d x + e y + f"
+    )]
+    matrix: Affine,
+    #[clap(
+        long,
+        parse(try_from_str = parse_size),
+        about = "Output size as WIDTHxHEIGHT, a missing side is that of the input"
+    )]
+    size: Option<Size>,
+    #[clap(
+        long,
+        default_value = "bilinear",
+        parse(try_from_str = parse_resample),
+        about = "Interpolation: box, bilinear, bicubic, mitchell or lanczos3"
+    )]
+    filter: Resample,
+    #[clap(
+        long,
+        default_value = "0,0,0,0",
+        parse(try_from_str = parse_background),
+        about = "Color beyond the input edges as gray, r,g,b or r,g,b,a"
+    )]
+    background: [u8; 4],
+}
+
+fn parse_values<T: std::str::FromStr>(values: &str) -> Result<Vec<T>> {
+    values
+        .split(',')
+        .map(|value| {
+            value
+                .trim()
+                .parse()
+                .ok()
+                .with_context(|| format!("Invalid number {:?} in {:?}", value, values))
+        })
+        .collect()
+}
+
+pub fn parse_background(color: &str) -> Result<[u8; 4]> {
+    match parse_values(color)?[..] {
+        [gray] => Ok([gray, gray, gray, 255]),
+        [r, g, b] => Ok([r, g, b, 255]),
+        [r, g, b, a] => Ok([r, g, b, a]),
+        _ => bail!("Color {:?} should be gray, r,g,b or r,g,b,a", color),
+    }
+}
+
+pub fn parse_affine(matrix: &str) -> Result<Affine> {
+    match parse_values(matrix)?[..] {
+        [a, b, c, d, e, f] => Ok(Affine([a, b, c, d, e, f])),
+        _ => bail!("Matrix {:?} should be a,b,c,d,e,f", matrix),
+    }
+}
+
+/// Check that the options do not apply to part of an image, as the filter applies to the whole
+/// image only
+pub fn validate(opts: &Opts) -> Result<()> {
+    ensure!(
+        opts.region.is_empty()
+            && opts.regions.is_none()
+            && opts.mask.is_none()
+            && opts.color.channels.is_none()
+            && opts.color.color_space.is_none()
+            && (opts.x, opts.y, opts.width, opts.height) == (0, 0, None, None),
+        "Resizing, geometric transforms and blending cannot be combined with crops, regions, \
+         masks, channels or color spaces"
+    );
+
+    Ok(())
+}
+
+/// Run a transform on the 8-bit pixels of an image, which yields pixels of `width` × `height`
+/// with the same channels
+pub fn transform_pixels<F>(
+    image: &DynamicImage,
+    (width, height): (u32, u32),
+    transform: F,
+) -> Result<DynamicImage>
+where
+    F: FnOnce(&Image<u8>) -> Result<Vec<u8>, FilterError>,
+{
+    let channels = image.color().channel_count();
+    let mut buf_read = compare::raw_pixels(image, channels);
+    let mut buf_write = buf_read.clone();
+
+    let buf = transform(&Image {
+        width: image.width(),
+        height: image.height(),
+        channels: channels as usize,
+        buf_read: &mut buf_read,
+        buf_write: &mut buf_write,
+    })?;
+
+    image_from_pixels(width, height, channels as usize, buf)
+}
+
+/// Image of 8-bit pixels with 1 to 4 channels
+pub fn image_from_pixels(
+    width: u32,
+    height: u32,
+    channels: usize,
+    buf: Vec<u8>,
+) -> Result<DynamicImage> {
+    let color = match channels {
+        1 => ColorType::L8,
+        2 => ColorType::La8,
+        3 => ColorType::Rgb8,
+        _ => ColorType::Rgba8,
+    };
+
+    tile::dynamic_image(width, height, color, buf)
+}
+
+pub fn rotate_image(image: &DynamicImage, rotate: &Rotate) -> Result<DynamicImage> {
+    ensure!(
+        rotate.angle.is_finite(),
+        "--angle should be a finite number, got {}",
+        rotate.angle
+    );
+
+    let quarters = rotate.angle / 90.0;
+
+    if quarters.fract() == 0.0 {
+        return match (quarters as i64).rem_euclid(4) {
+            0 => Ok(image.clone()),
+            1 => orient_image(image, Orientation::Rotate90),
+            2 => orient_image(image, Orientation::Rotate180),
+            _ => orient_image(image, Orientation::Rotate270),
+        };
+    }
+
+    let from = image.dimensions();
+    let to = if rotate.expand {
+        rotated_size(from.0, from.1, rotate.angle)
+    } else {
+        from
+    };
+
+    let affine = Affine::rotation(rotate.angle, from, to);
+    let background = background(rotate.background, image.color());
+
+    transform_pixels(image, to, |img| {
+        try_warp(img, affine, to.0, to.1, rotate.filter, background)
+    })
+    .context("Failed to rotate image")
+}
+
+pub fn flip_image(image: &DynamicImage, flip: &Flip) -> Result<DynamicImage> {
+    if flip.vertical {
+        orient_image(image, Orientation::FlipVertical)
+    } else {
+        orient_image(image, Orientation::FlipHorizontal)
+    }
+}
+
+pub fn transpose_image(image: &DynamicImage, transpose: &Transpose) -> Result<DynamicImage> {
+    if transpose.anti {
+        orient_image(image, Orientation::Transverse)
+    } else {
+        orient_image(image, Orientation::Transpose)
+    }
+}
+
+pub fn warp_image(image: &DynamicImage, warp: &Warp) -> Result<DynamicImage> {
+    let (width, height) = image.dimensions();
+    let size = match warp.size {
+        Some(Size(w, h)) => (w.unwrap_or(width), h.unwrap_or(height)),
+        None => (width, height),
+    };
+
+    let background = background(warp.background, image.color());
+
+    transform_pixels(image, size, |img| {
+        try_warp(img, warp.matrix, size.0, size.1, warp.filter, background)
+    })
+    .context("Failed to warp image")
+}
+
+/// Rotate or flip an image in its own sample type, so 16-bit images stay lossless
+fn orient_image(image: &DynamicImage, orientation: Orientation) -> Result<DynamicImage> {
+    let (width, height) = image.dimensions();
+    let size = orientation.dimensions(width, height);
+
+    let image = match *image {
+        DynamicImage::ImageLuma16(ref buf) => {
+            DynamicImage::ImageLuma16(orient_buffer(buf, orientation)?)
+        }
+        DynamicImage::ImageLumaA16(ref buf) => {
+            DynamicImage::ImageLumaA16(orient_buffer(buf, orientation)?)
+        }
+        DynamicImage::ImageRgb16(ref buf) => {
+            DynamicImage::ImageRgb16(orient_buffer(buf, orientation)?)
+        }
+        DynamicImage::ImageRgba16(ref buf) => {
+            DynamicImage::ImageRgba16(orient_buffer(buf, orientation)?)
+        }
+        _ => transform_pixels(image, size, |img| try_orient(img, orientation))
+            .context("Failed to reorient image")?,
+    };
+
+    Ok(image)
+}
+
+fn orient_buffer<P>(
+    buf: &ImageBuffer<P, Vec<P::Subpixel>>,
+    orientation: Orientation,
+) -> Result<ImageBuffer<P, Vec<P::Subpixel>>>
+where
+    P: Pixel + 'static,
+    P::Subpixel: Sync + Send + Into<f32> + 'static,
+{
+    let mut buf_read = buf.as_raw().clone();
+    let mut buf_write = buf_read.clone();
+
+    let oriented = try_orient(
+        &Image {
+            width: buf.width(),
+            height: buf.height(),
+            channels: P::CHANNEL_COUNT as usize,
+            buf_read: &mut buf_read,
+            buf_write: &mut buf_write,
+        },
+        orientation,
+    )
+    .context("Failed to reorient image")?;
+
+    let (width, height) = orientation.dimensions(buf.width(), buf.height());
+
+    ImageBuffer::from_raw(width, height, oriented).context("Failed to reorient image")
+}
+
+/// Arrange an RGBA color as the channels of an image, i.e. luma and alpha for gray images
+fn background([r, g, b, a]: [u8; 4], color: ColorType) -> [u8; 4] {
+    match color.channel_count() {
+        1 | 2 => {
+            let luma = Luma::default().luma(r as f32, g as f32, b as f32);
+            [luma.round() as u8, a, 0, 0]
+        }
+        _ => [r, g, b, a],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::GrayImage;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(parse_background("10").unwrap(), [10, 10, 10, 255]);
+        assert_eq!(parse_background("1,2,3,4").unwrap(), [1, 2, 3, 4]);
+        assert!(parse_background("1,2").is_err());
+        assert!(parse_background("256").is_err());
+
+        assert_eq!(
+            parse_affine("1,0,5,0,1,-2.5").unwrap(),
+            Affine::translation(5.0, -2.5)
+        );
+        assert!(parse_affine("1,0,5").is_err());
+    }
+
+    #[test]
+    fn test_rotate() {
+        let image = DynamicImage::ImageLuma8(GrayImage::from_raw(3, 1, vec![0, 128, 255]).unwrap());
+
+        let rotate = |angle, expand| Rotate {
+            angle,
+            expand,
+            filter: Resample::Bilinear,
+            background: [255; 4],
+        };
+
+        // Multiples of 90° are lossless in either direction
+        let actual = rotate_image(&image, &rotate(-90.0, false)).unwrap();
+        assert_eq!(actual.to_luma8().into_raw(), [255, 128, 0]);
+        assert_eq!(actual.dimensions(), (1, 3));
+
+        assert_eq!(
+            rotate_image(&image, &rotate(30.0, true))
+                .unwrap()
+                .dimensions(),
+            (4, 3)
+        );
+
+        assert!(rotate_image(&image, &rotate(f32::NAN, false)).is_err());
+        assert!(rotate_image(&image, &rotate(f32::INFINITY, true)).is_err());
+
+        // 16-bit samples are kept as they are
+        let samples = vec![0, 1, 65535, 2, 3, 4];
+        let image = DynamicImage::ImageRgb16(ImageBuffer::from_raw(2, 1, samples).unwrap());
+
+        let actual = rotate_image(&image, &rotate(180.0, false)).unwrap();
+        assert_eq!(actual.to_rgb16().into_raw(), [2, 3, 4, 0, 1, 65535]);
+    }
+}